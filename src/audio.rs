@@ -13,6 +13,7 @@ pub use self::iterator::ArcIt;
 pub mod experiments;
 pub mod acore;
 pub mod blep;
+pub mod spectrum;
 
 pub mod streamlog;
 pub mod iterator;
@@ -4,18 +4,23 @@
 #[allow(unused)]
 use log::{Level, log_enabled, trace, debug, info, warn, error};
 
+#[cfg(feature = "sdl")]
 use std::{sync::{Arc, Mutex}, ops::DerefMut};
+#[cfg(feature = "sdl")]
 use sdl2::audio::{AudioSpec, AudioFormat, AudioCallback};
 
+#[cfg(feature = "sdl")]
 use super::Freq;
 
 // use super::dsp::ringbuf::RingBuf;
 
 // ================================================================================
 
+#[cfg(feature = "sdl")]
 const AUDIO_BUF_MAX_SIZE : usize = 8000;
 
 
+#[cfg(feature = "sdl")]
 type MixerSampleType = f32;
 
 // ================================================================================
@@ -29,13 +34,15 @@ pub trait AudioSource : Sync + Send {
 
 
 // ================================================================================
-// Mixer
+// Mixer (SDL audio output)
+#[cfg(feature = "sdl")]
 #[derive(Clone)]
 pub struct Mixer {
     pub sample_rate: usize,
     sources: Arc<Mutex<Vec<Arc<Mutex<dyn AudioSource>>>>>,
 }
 
+#[cfg(feature = "sdl")]
 impl Mixer {
     fn new(sample_rate:usize) -> Self {
 	Mixer {
@@ -69,6 +76,7 @@ impl Mixer {
     }
 }
 
+#[cfg(feature = "sdl")]
 impl AudioCallback for Mixer {
     type Channel = MixerSampleType;
 
@@ -81,12 +89,14 @@ impl AudioCallback for Mixer {
 
 // --------------------------------------------------------------------------------
 
+#[cfg(feature = "sdl")]
 pub struct AudioCore {
     spec : AudioSpec,
     mixer : Mixer,
     device : Option<sdl2::audio::AudioDevice<Mixer>>,
 }
 
+#[cfg(feature = "sdl")]
 impl AudioCore {
     fn new() -> Self {
 	AudioCore {
@@ -131,11 +141,13 @@ impl AudioCore {
 // ================================================================================
 // ACore and SDL main hook
 
+#[cfg(feature = "sdl")]
 pub struct ACore {
     ac : Arc<Mutex<AudioCore>>,
     pub frequency : Freq,
 }
 
+#[cfg(feature = "sdl")]
 impl ACore {
     fn new(ac: Arc<Mutex<AudioCore>>) -> Self {
 	let frequency = {
@@ -151,6 +163,7 @@ impl ACore {
     }
 }
 
+#[cfg(feature = "sdl")]
 pub fn init<'a>(sdl_context : &sdl2::Sdl) -> ACore {
     let audio = sdl_context.audio().unwrap();
     let requested_audio = sdl2::audio::AudioSpecDesired {
@@ -180,6 +193,7 @@ pub fn init<'a>(sdl_context : &sdl2::Sdl) -> ACore {
     ACore::new(core)
 }
 
+#[cfg(feature = "sdl")]
 impl ACore {
     // pub fn start_mixer<'a>(&mut self, sample_data : &'a [i8]) -> Mixer {
     // 	let mut guard = self.ac.lock().unwrap();
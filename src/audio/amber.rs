@@ -235,6 +235,26 @@ impl InstrumentNote {
 	    InstrumentNote::Absolute(_) => {}, // can't modify absolute notes
 	}
     }
+
+    /// Shifts relative notes by the given number of semitones.  Notes that would leave the period table are
+    /// moved by whole octaves to stay inside it.
+    /// Absolute notes (typically percussion) and notes that are already out of range are left unchanged.
+    pub fn transposed(&self, semitones : isize) -> InstrumentNote {
+	const NUM_NOTES : isize = PERIODS.len() as isize;
+	match self {
+	    InstrumentNote::Relative(n) if (0..NUM_NOTES).contains(n) => {
+		let mut note = *n + semitones;
+		while note < 0 {
+		    note += 12;
+		}
+		while note >= NUM_NOTES {
+		    note -= 12;
+		}
+		return InstrumentNote::Relative(note);
+	    },
+	    _ => return *self,
+	}
+    }
 }
 
 impl InstrumentIterator {
@@ -403,6 +423,13 @@ impl VibratoState {
     }
 }
 
+impl fmt::Display for VibratoState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Vibrato[{}, depth={} dir={:+} delay={}]",
+	       self.spec, self.depth, self.direction, self.delay)
+    }
+}
+
 #[derive(Clone)]
 struct TimbreIterator {
     volume_queue    : VecDeque<VolumeSpec>,
@@ -555,6 +582,12 @@ impl PortandoState {
     }
 }
 
+impl fmt::Display for PortandoState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Portando[{} + {}]", self.current, self.delta)
+    }
+}
+
 #[derive(Clone)]
 struct MonopatternIterator {
     portando : PortandoState,
@@ -765,12 +798,20 @@ struct ChannelState {
     num_ticks : Ticks, // Aggregate ticks
 }
 
+impl fmt::Display for ChannelState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChannelState[tick={} base-note={} speed={} note={:?} avol={} period={}]",
+	       self.num_ticks, self.base_note, self.channel_speed, self.note, self.avolume, self.period)
+    }
+}
+
 #[derive(Clone)]
 pub struct ChannelIterator {
     state : ChannelState,
 
     songdb : Arc<dyn SongDataBank>, // Information about the current song
     channel_avolume : AVolume,
+    transpose : isize, // Global transposition in semitones
     instrument : InstrumentIterator,
     timbre : TimbreIterator,
     monopattern : MonopatternIterator,
@@ -794,6 +835,7 @@ impl ChannelIterator {
 		num_ticks : 0,
 	    },
 	    channel_avolume : 64,
+	    transpose : 0,
 	    songdb,
 	    // samples : Arc::new(vec![]),
 	    instrument,
@@ -847,6 +889,13 @@ impl ChannelIterator {
 	}
     }
 
+    pub fn set_transpose(&mut self, semitones : isize) {
+	self.transpose = semitones;
+	if DEBUG {
+	    self.streamlog_num("transpose", semitones);
+	}
+    }
+
     pub fn streamlog(&mut self, topic : &'static str, message : String) {
 	self.logger.log("chanit", topic, message);
     }
@@ -855,6 +904,21 @@ impl ChannelIterator {
     }
 }
 
+/// Multi-line summary of the channel's internal state, for debugging
+impl fmt::Display for ChannelIterator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	writeln!(f, "{}", self.state)?;
+	writeln!(f, "  channel-avol={} transpose={}", self.channel_avolume, self.transpose)?;
+	writeln!(f, "  instrument: base-note={:?} sample-ticks={:?} queued-ops={}",
+		 self.instrument.base_note, self.instrument.remaining_ticks, self.instrument.queue.len())?;
+	writeln!(f, "  timbre: avol={} delay={:?} {}",
+		 self.timbre.current_avolume, self.timbre.delay, self.timbre.vibrato)?;
+	write!(f, "  monopattern: note={} tune={} delay={:?} remaining-ops={} {}",
+	       self.monopattern.channel_note, self.monopattern.timbre_adjust,
+	       self.monopattern.delay, self.monopattern.ops.len(), self.monopattern.portando)
+    }
+}
+
 impl StreamLogClient for ChannelIterator {
     fn set_logger(&mut self, logger : ArcStreamLogger) {
 	self.logger = logger.clone();
@@ -877,11 +941,10 @@ impl AudioIterator for ChannelIterator {
 	    MPStep::Stop            => pdebug!("  : Finished Monopattern"),
 	    MPStep::SetTimbre(ti, ti_index, instr_opt, instr_index_opt) => {
 		pdebug!("  : Timbre/Instrument switch");
-		if DEBUG {
-		    self.streamlog_num("timbre", ti_index as isize);
-		    if let Some(instr_index) = instr_index_opt {
-			self.streamlog_num("instrument", instr_index as isize);
-		    }
+		// Always reported, like division and monopattern changes: the audio debugger's breakpoints rely on these
+		self.streamlog_num("timbre", ti_index as isize);
+		if let Some(instr_index) = instr_index_opt {
+		    self.streamlog_num("instrument", instr_index as isize);
 		}
 		self.timbre = ti;
 		if let Some(instr) = instr_opt {
@@ -899,7 +962,7 @@ impl AudioIterator for ChannelIterator {
 	self.monopattern.tick_note(&mut self.state);
 	//self.streamlog("note[post-monopat]", format!("{:?}", self.state.note));
 
-	let note = self.state.note;
+	let note = self.state.note.transposed(self.transpose);
 	// if note.is_relative() {
 	//     note.modify(self.state.base_note);
 	// }
@@ -910,7 +973,7 @@ impl AudioIterator for ChannelIterator {
 	self.monopattern.tick_portando(&mut self.state);
 
 	// Done with updating, send updates downstream
-	if note.get() > PERIODS.len() {
+	if note.get() >= PERIODS.len() {
 	    // out of range, make quiet
 	    out_queue.push_back(AQOp::SetVolume(volume(0)));
 	    if DEBUG {
@@ -1063,10 +1126,9 @@ impl SongIterator {
 	    let s = format!("{monopat}");
 	    let count = mono_it.count_length(ch.state.clone(), &self.songdb);
 	    pinfo!("ch #{index:x}, P#{:02x}: [len {count}] {s}", div_chan.monopat);
-	    if DEBUG {
-		ch.logger.log_num("chanit", "division", div as isize);
-		ch.logger.log_num("chanit", "monopattern", div_chan.monopat as isize);
-	    }
+	    // Always reported, like timbre and instrument changes: the audio debugger's breakpoints rely on these
+	    ch.logger.log_num("chanit", "division", div as isize);
+	    ch.logger.log_num("chanit", "monopattern", div_chan.monopat as isize);
 	    ch.set_monopattern(&self.monopatterns[div_chan.monopat], timbre_tune);
 	}
 	for ch in self.channels.iter_mut() {
@@ -1092,6 +1154,15 @@ impl SongIterator {
 	pinfo!("-- division: {}/{}", self.division_index, self.division_last);
     }
 
+    /// Index of the division that is currently playing
+    pub fn division(&self) -> usize {
+	self.division_index
+    }
+
+    pub fn is_stopped(&self) -> bool {
+	self.stopped
+    }
+
     pub fn end(&self, queue : &mut VecDeque<AQOp>) {
 	queue.push_back(AQOp::End);
     }
@@ -1108,6 +1179,13 @@ impl SongIterator {
 	return self.channels[chan_index].next(queue);
     }
 
+    /// Transposes all channels by the given number of semitones
+    pub fn set_transpose(&mut self, semitones : isize) {
+	for ch in self.channels.iter_mut() {
+	    ch.set_transpose(semitones);
+	}
+    }
+
     /// Callback when setting loggers
     pub fn set_logger(&mut self, chan_index : usize, logger : ArcStreamLogger) {
 	self.channels[chan_index].set_logger(logger);
@@ -1177,3 +1255,14 @@ pub fn play_song(song : &Song) -> ArcPoly {
     return Arc::new(Mutex::new(SongPolyIterator::new(&song, song.songinfo.first_division,
 						     song.songinfo.last_division)));
 }
+
+#[cfg(test)]
+#[test]
+fn test_transposed() {
+    let top = PERIODS.len() as isize - 1;
+    assert_eq!(InstrumentNote::Relative(10), InstrumentNote::Relative(5).transposed(5));
+    assert_eq!(InstrumentNote::Relative(9), InstrumentNote::Relative(1).transposed(-4));
+    assert_eq!(InstrumentNote::Relative(top - 11), InstrumentNote::Relative(top).transposed(1));
+    assert_eq!(InstrumentNote::Relative(100), InstrumentNote::Relative(100).transposed(-3));
+    assert_eq!(InstrumentNote::Absolute(3), InstrumentNote::Absolute(3).transposed(7));
+}
@@ -6,7 +6,7 @@
 use log::{Level, log_enabled, trace, debug, info, warn, error};
 
 use hound::WavWriter;
-use std::{collections::{VecDeque, HashSet, HashMap}, sync::{Mutex, Arc}, fs::File, io::BufWriter, mem, fmt::Display};
+use std::{collections::{VecDeque, HashSet, HashMap}, sync::{Mutex, Arc}, fs::File, io::BufWriter, mem, fmt::Display, time::Duration};
 use lazy_static::lazy_static;
 use rubato::{Resampler, SincFixedIn, SincInterpolationType, SincInterpolationParameters, WindowFunction};
 use rustfft::{FftPlanner, num_complex::Complex, FftDirection};
@@ -17,6 +17,9 @@ use super::acore::AudioSource;
 
 pub const SAMPLE_RATE : usize = 48_000;
 
+/// CoSo songs advance at a fixed rate of 50 ticks per second
+const TICKS_PER_SECOND : usize = 50;
+
 // ================================================================================
 // SampleProvider
 
@@ -816,7 +819,9 @@ impl SingleSongPlayer {
 	self.poly_it.channels[channel as usize].set_logger(logger);
     }
 
-    fn fill(&mut self, sample_provider: &SampleProvider, channel: u8, buf: &mut [f32], sample_rate: usize) {
+    /// If `ops_trace` is set, records all AQOps processed for this tick
+    fn fill(&mut self, sample_provider: &SampleProvider, channel: u8, buf: &mut [f32], sample_rate: usize,
+	    ops_trace: Option<&mut Vec<AQOp>>) {
 	debug!("SingleSongPlayer::fill({}, {sample_rate})", buf.len());
 	let i = channel as usize;
 	let mut d = VecDeque::<AQOp>::new();
@@ -827,6 +832,9 @@ impl SingleSongPlayer {
 	    self.poly_it.next_division();
 	}
 	self.poly_it.channels[i].next(&mut d);
+	if let Some(ops) = ops_trace {
+	    ops.extend(d.iter().cloned());
+	}
 
 	for dd in d {
 	    trace!("  #{i}- {dd:?}");
@@ -872,6 +880,8 @@ pub trait SongTracer : Sync + Send {
     fn change_song(&mut self) {}
     fn trace_message(&mut self, tick: usize, channel: u8, subsystem: &'static str, category: &'static str, msg: String);
     fn trace_message_num(&mut self, tick: usize, channel: u8, subsystem: &'static str, category: &'static str, msg: isize);
+    /// Audio queue operations that the channel iterator produced for this tick
+    fn trace_aqops(&mut self, _tick: usize, _channel: u8, _ops: &[AQOp]) {}
 }
 
 struct SongTracerStreamLogger {
@@ -905,12 +915,114 @@ impl StreamLogger for SongTracerStreamLogger {
     }
 }
 
+// ----------------------------------------
+// Fades
+
+/// Linear volume ramp for song transitions, counted in output time (so that it does not depend on the tempo)
+#[derive(Clone, Copy)]
+struct Fade {
+    from: f32,
+    to: f32,
+    duration: f32, // seconds
+    elapsed: f32, // seconds
+}
+
+impl Fade {
+    fn new(from: f32, to: f32, duration: Duration) -> Self {
+	Fade {
+	    from,
+	    to,
+	    duration: duration.as_secs_f32(),
+	    elapsed: 0.0,
+	}
+    }
+
+    /// Current gain
+    fn gain(&self) -> f32 {
+	if self.is_done() {
+	    return self.to;
+	}
+	return self.from + (self.to - self.from) * (self.elapsed / self.duration);
+    }
+
+    fn is_done(&self) -> bool {
+	return self.elapsed >= self.duration;
+    }
+
+    /// Gains at the start and at the end of the next `len` samples; advances the fade past them
+    fn advance(&mut self, len: usize, sample_rate: usize) -> (f32, f32) {
+	let start = self.gain();
+	self.elapsed += len as f32 / sample_rate as f32;
+	return (start, self.gain());
+    }
+}
+
+/// Adds src to dest, scaling the volume linearly from start_gain to end_gain
+fn mix_ramped(dest: &mut [f32], src: &[f32], start_gain: f32, end_gain: f32) {
+    let step = (end_gain - start_gain) / usize::max(1, src.len()) as f32;
+    let mut gain = start_gain;
+    for (d, &s) in dest.iter_mut().zip(src.iter()) {
+	*d += s * gain;
+	gain += step;
+    }
+}
+
+// ----------------------------------------
+// Playback controls
+
+/// Runtime adjustments to song playback, for analysis and remixing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlaybackControls {
+    /// Tempo scaling factor (1.0 = original speed)
+    pub tempo: f32,
+    /// Global transposition in semitones
+    pub transpose: isize,
+    pub muted: [bool; 4],
+    /// If any channel is soloed, only soloed channels are audible
+    pub solo: [bool; 4],
+}
+
+impl PlaybackControls {
+    pub const TEMPO_MIN: f32 = 0.25;
+    pub const TEMPO_MAX: f32 = 4.0;
+
+    pub fn new() -> Self {
+	PlaybackControls {
+	    tempo: 1.0,
+	    transpose: 0,
+	    muted: [false; 4],
+	    solo: [false; 4],
+	}
+    }
+
+    pub fn is_audible(&self, channel: usize) -> bool {
+	let any_solo = self.solo.iter().any(|&s| s);
+	!self.muted[channel] && (!any_solo || self.solo[channel])
+    }
+
+    /// Number of output samples per song tick at the current tempo
+    pub fn samples_per_tick(&self, sample_rate: usize) -> usize {
+	(sample_rate as f32 / (TICKS_PER_SECOND as f32 * self.tempo)) as usize
+    }
+}
+
+impl Default for PlaybackControls {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
 // ----------------------------------------
 
 const BUF_SIZE : usize = 8000;
 
 pub struct SongPlayer {
     song: Option<SingleSongPlayer>,
+    /// Fade-in of `song`, if any
+    song_fade: Option<Fade>,
+    /// Songs that we are fading out of
+    outgoing: Vec<(SingleSongPlayer, Fade)>,
+    controls: PlaybackControls,
     tick: usize, // Song tick counter
     sample_provider: SampleProvider,
     left_buf: Vec<f32>,
@@ -927,6 +1039,9 @@ impl SongPlayer {
 
 	SongPlayer {
 	    song: None,
+	    song_fade: None,
+	    outgoing: Vec::new(),
+	    controls: PlaybackControls::new(),
 	    tick: 0,
 	    sample_provider,
 	    left_buf: Vec::<f32>::with_capacity(BUF_SIZE),
@@ -957,6 +1072,8 @@ impl SongPlayer {
 
     fn stop(&mut self) {
 	self.song = None;
+	self.song_fade = None;
+	self.outgoing.clear();
 	self.left_buf.clear();
 	self.right_buf.clear();
 	self.report_change_song();
@@ -965,17 +1082,63 @@ impl SongPlayer {
 
     fn play(&mut self, song_it: &SongIterator) {
 	self.song = Some(SingleSongPlayer::new(song_it));
+	self.song_fade = None;
 	self.tick = 0;
 	self.report_change_song();
 	self.update_channel_loggers();
 	if let Some(ref mut song) = self.song {
 	    song.reset();
+	    song.poly_it.set_transpose(self.controls.transpose);
 	}
 	if self.record {
 	    self.start_recording();
 	}
     }
 
+    /// Starts fading out the current song (if any) from its current volume.
+    /// Songs that were already fading out keep fading.
+    fn start_fade(&mut self, duration: Duration) {
+	if let Some(mut song) = self.song.take() {
+	    let gain = self.song_fade.take().map_or(1.0, |f| f.gain());
+	    // Only the incoming song reports to the tracer
+	    for n in 0..4 {
+		song.set_channel_logger(n, streamlog::dummy());
+	    }
+	    self.outgoing.push((song, Fade::new(gain, 0.0, duration)));
+	}
+    }
+
+    fn crossfade_to(&mut self, song_it: &SongIterator, duration: Duration) {
+	self.start_fade(duration);
+	self.play(song_it);
+	self.song_fade = Some(Fade::new(0.0, 1.0, duration));
+    }
+
+    fn fade_out(&mut self, duration: Duration) {
+	self.start_fade(duration);
+	self.report_change_song();
+    }
+
+    fn set_tempo(&mut self, tempo: f32) {
+	self.controls.tempo = tempo.clamp(PlaybackControls::TEMPO_MIN, PlaybackControls::TEMPO_MAX);
+    }
+
+    fn set_transpose(&mut self, semitones: isize) {
+	self.controls.transpose = semitones;
+	if let Some(ref mut song) = self.song {
+	    song.poly_it.set_transpose(semitones);
+	}
+    }
+
+    /// The song iterator of the currently playing song, if any
+    pub fn song_iterator(&self) -> Option<&SongIterator> {
+	self.song.as_ref().map(|sp| &sp.poly_it)
+    }
+
+    pub fn controls(&self) -> PlaybackControls {
+	self.controls
+    }
+
     pub fn update_channel_loggers(&mut self) {
 	if let Some(ref mut song) = self.song {
 	    if self.stream_loggers.len() == 4 {
@@ -1026,6 +1189,13 @@ impl SongPlayer {
 	}
     }
 
+    fn report_aqops(&self, tick: usize, channel: u8, ops: &[AQOp]) {
+	if let Some(tracer) = &self.tracer {
+	    let mut guard = tracer.lock().unwrap();
+	    guard.trace_aqops(tick, channel, ops);
+	}
+    }
+
     fn report_change_song(&self) {
 	match &self.tracer {
 	    None => {},
@@ -1039,36 +1209,68 @@ impl SongPlayer {
     // buf_left and buf_right are guaranteed to have exactly one tick in length
     fn fill_channels(&mut self, buf_left: &mut [f32], buf_right: &mut [f32], sample_rate: usize) {
 	let mut to_report = vec![];
+	let mut ops_to_report = vec![];
 	let trace = self.have_tracer();
 
-	if let Some(ref mut sp) = self.song {
-	    let channels = [0, 1, 1, 0];
+	let channels = [0, 1, 1, 0];
+	let song_gains = self.song_fade.as_mut().map(|f| f.advance(buf_left.len(), sample_rate));
 
+	if let Some(ref mut sp) = self.song {
 	    for i in 0..4 {
 		let buf = if channels[i] == 1 { &mut* buf_right } else { &mut* buf_left };
+		let audible = self.controls.is_audible(i);
 
-		if trace {
+		if trace || song_gains.is_some() || !audible {
 		    let mut data: Vec<f32> = vec![0.0; buf.len()];
+		    let mut ops = vec![];
 		    sp.fill(&self.sample_provider,
 			    i as u8,
 			    &mut data,
-			    sample_rate);
-		    for (d, &s) in buf.iter_mut().zip(data.iter()) {
-			*d += s;
+			    sample_rate,
+			    if trace { Some(&mut ops) } else { None });
+		    if audible {
+			let (fade_in_start, fade_in_end) = song_gains.unwrap_or((1.0, 1.0));
+			mix_ramped(buf, &data, fade_in_start, fade_in_end);
+		    }
+		    if trace {
+			to_report.push(data);
+			ops_to_report.push(ops);
 		    }
-		    to_report.push(data);
 		} else {
 		    sp.fill(&self.sample_provider,
 			    i as u8,
 			    buf,
-			    sample_rate);
+			    sample_rate,
+			    None);
 		}
 	    }
 	}
+	if self.song_fade.is_some_and(|f| f.is_done()) {
+	    self.song_fade = None;
+	}
+	for (sp, fade) in self.outgoing.iter_mut() {
+	    let (fade_start, fade_end) = fade.advance(buf_left.len(), sample_rate);
+	    for (i, &side) in channels.iter().enumerate() {
+		let buf = if side == 1 { &mut* buf_right } else { &mut* buf_left };
+		let mut data: Vec<f32> = vec![0.0; buf.len()];
+		sp.fill(&self.sample_provider,
+			i as u8,
+			&mut data,
+			sample_rate,
+			None);
+		if self.controls.is_audible(i) {
+		    mix_ramped(buf, &data, fade_start, fade_end);
+		}
+	    }
+	}
+	self.outgoing.retain(|(_, fade)| !fade.is_done());
 	if trace {
 	    for (i, buf) in to_report.iter().enumerate() {
 		self.report_buf(self.tick, i as u8, buf);
 	    }
+	    for (i, ops) in ops_to_report.iter().enumerate() {
+		self.report_aqops(self.tick, i as u8, ops);
+	    }
 	}
 
 	if self.song.is_some() {
@@ -1079,20 +1281,20 @@ impl SongPlayer {
 
     fn fill(&mut self, buf_left: &mut [f32], buf_right: &mut [f32], sample_rate: usize) {
 	info!("SongPlayer::fill({}, {}, {sample_rate})", buf_left.len(), buf_right.len());
-	let samples_per_tick = sample_rate / 50;
+	let samples_per_tick = self.controls.samples_per_tick(sample_rate);
 	let mut pos = 0;
 	if self.left_buf.len() > 0 {
-	    let leftover_length = self.left_buf.len();
-	    assert!(leftover_length < buf_left.len());
-	    buf_left[0..leftover_length].copy_from_slice(&self.left_buf);
-	    buf_right[0..leftover_length].copy_from_slice(&self.right_buf);
+	    // At slow tempo, one tick may span more than one output buffer
+	    let leftover_length = usize::min(self.left_buf.len(), buf_left.len());
+	    buf_left[0..leftover_length].copy_from_slice(&self.left_buf[0..leftover_length]);
+	    buf_right[0..leftover_length].copy_from_slice(&self.right_buf[0..leftover_length]);
 
 	    pos += leftover_length;
-	    self.left_buf.clear();
-	    self.right_buf.clear();
+	    self.left_buf.drain(0..leftover_length);
+	    self.right_buf.drain(0..leftover_length);
 
 	}
-	if let Some(ref mut _sp) = self.song {
+	if self.song.is_some() || !self.outgoing.is_empty() {
 	    while pos + samples_per_tick <= buf_left.len() {
 		debug!("  pos={pos}, += {samples_per_tick}");
 		let end = pos + samples_per_tick;
@@ -1167,8 +1369,61 @@ impl SongPlayerAudioSource {
 	guard.play(poly_it);
     }
 
+    /// Starts playing the given song, fading it in while the current song fades out.
+    /// Plays immediately if no song is currently playing.
+    pub fn crossfade_to(&mut self, poly_it: &SongIterator, duration: Duration) {
+	let mut guard = self.player.lock().unwrap();
+	guard.crossfade_to(poly_it, duration);
+    }
+
+    /// Fades out the current song, then stops
+    pub fn fade_out(&mut self, duration: Duration) {
+	let mut guard = self.player.lock().unwrap();
+	guard.fade_out(duration);
+    }
+
+    pub fn controls(&self) -> PlaybackControls {
+	let guard = self.player.lock().unwrap();
+	guard.controls
+    }
+
+    /// Scales playback speed; clamped to [TEMPO_MIN, TEMPO_MAX]
+    pub fn set_tempo(&mut self, tempo: f32) {
+	let mut guard = self.player.lock().unwrap();
+	guard.set_tempo(tempo);
+    }
+
+    /// Transposes all (non-fixed) notes by the given number of semitones
+    pub fn set_transpose(&mut self, semitones: isize) {
+	let mut guard = self.player.lock().unwrap();
+	guard.set_transpose(semitones);
+    }
+
+    pub fn set_muted(&mut self, channel: usize, muted: bool) {
+	let mut guard = self.player.lock().unwrap();
+	guard.controls.muted[channel] = muted;
+    }
+
+    pub fn set_solo(&mut self, channel: usize, solo: bool) {
+	let mut guard = self.player.lock().unwrap();
+	guard.controls.solo[channel] = solo;
+    }
+
     pub fn player(&self) -> Arc<Mutex<SongPlayer>> {
 	self.player.clone()
     }
 }
 
+
+#[cfg(test)]
+#[test]
+fn test_fade() {
+    let mut fade_in = Fade::new(0.0, 1.0, Duration::from_millis(1000));
+    assert_eq!((0.0, 0.5), fade_in.advance(24000, 48000));
+    // Fading out a half-faded-in song starts at its current volume
+    let mut fade_out = Fade::new(fade_in.gain(), 0.0, Duration::from_millis(500));
+    assert_eq!((0.5, 0.25), fade_out.advance(12000, 48000));
+    assert!(!fade_out.is_done());
+    assert_eq!((0.25, 0.0), fade_out.advance(12000, 48000));
+    assert!(fade_out.is_done());
+}
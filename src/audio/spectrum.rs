@@ -0,0 +1,149 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+//! Frequency analysis of traced audio, for visualisation and debugging
+
+use std::sync::Arc;
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+
+/// Lowest reported level, in dB relative to full scale
+pub const DB_FLOOR : f32 = -96.0;
+
+pub struct SpectrumAnalyzer {
+    size: usize,
+    sample_rate: usize,
+    window: Vec<f32>,
+    window_gain: f32,
+    fft: Arc<dyn Fft<f32>>,
+    buf: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl SpectrumAnalyzer {
+    /// Analyser for up to `size` samples (Hann-windowed) at the given sample rate
+    pub fn new(size: usize, sample_rate: usize) -> Self {
+	let (window, window_gain) = hann_window(size);
+	let fft = FftPlanner::new().plan_fft_forward(size);
+	let scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+	SpectrumAnalyzer {
+	    size,
+	    sample_rate,
+	    window,
+	    window_gain,
+	    fft,
+	    buf: vec![Complex::new(0.0, 0.0); size],
+	    scratch,
+	}
+    }
+
+    pub fn size(&self) -> usize {
+	self.size
+    }
+
+    /// Number of frequency bins reported by `magnitudes_db`
+    pub fn num_bins(&self) -> usize {
+	self.size / 2
+    }
+
+    /// Magnitudes of all frequency bins below the Nyquist frequency, in dB relative to full scale.
+    /// Input shorter than the analyser size is windowed over its own length and zero-padded, longer input
+    /// is truncated.
+    pub fn magnitudes_db(&mut self, samples: &[f32]) -> Vec<f32> {
+	let samples = &samples[0..usize::min(samples.len(), self.size)];
+	if samples.len() != self.window.len() {
+	    (self.window, self.window_gain) = hann_window(samples.len());
+	}
+	for (i, c) in self.buf.iter_mut().enumerate() {
+	    let v = if i < samples.len() { samples[i] * self.window[i] } else { 0.0 };
+	    *c = Complex::new(v, 0.0);
+	}
+	self.fft.process_with_scratch(&mut self.buf, &mut self.scratch);
+	self.buf[0..self.num_bins()].iter().map(|c| {
+	    let magnitude = c.norm() / self.window_gain;
+	    if !(magnitude > 0.0) {
+		DB_FLOOR
+	    } else {
+		f32::max(DB_FLOOR, 20.0 * magnitude.log10())
+	    }
+	}).collect()
+    }
+
+    /// Centre frequency of the given bin, in Hz
+    pub fn bin_frequency(&self, bin: usize) -> f32 {
+	(bin * self.sample_rate) as f32 / self.size as f32
+    }
+
+    /// Maps `rows` logarithmically spaced frequencies, from `min_freq` up to the Nyquist frequency, to bins.
+    /// Useful for drawing spectra with a logarithmic frequency axis.
+    pub fn log_bins(&self, rows: usize, min_freq: f32) -> Vec<usize> {
+	let max_freq = self.sample_rate as f32 / 2.0;
+	let bin_width = self.sample_rate as f32 / self.size as f32;
+	(0..rows).map(|row| {
+	    let freq = min_freq * f32::powf(max_freq / min_freq, row as f32 / rows as f32);
+	    usize::min(self.num_bins() - 1, (freq / bin_width) as usize)
+	}).collect()
+    }
+}
+
+/// Hann window of the given length, and its gain for a full-scale sine
+fn hann_window(len: usize) -> (Vec<f32>, f32) {
+    let window: Vec<f32> = (0..len).map(
+	|i| 0.5 - 0.5 * f32::cos(2.0 * std::f32::consts::PI * i as f32 / len as f32)).collect();
+    let window_gain = window.iter().sum::<f32>() * 0.5;
+    return (window, window_gain);
+}
+
+// ----------------------------------------
+
+#[cfg(test)]
+#[test]
+fn test_spectrum_silence() {
+    let mut analyzer = SpectrumAnalyzer::new(256, 48000);
+    let spectrum = analyzer.magnitudes_db(&[0.0; 256]);
+    assert_eq!(128, spectrum.len());
+    assert!(spectrum.iter().all(|&v| v == DB_FLOOR));
+}
+
+#[cfg(test)]
+#[test]
+fn test_spectrum_sine_peak() {
+    const SIZE : usize = 1024;
+    const SAMPLE_RATE : usize = 48000;
+    let mut analyzer = SpectrumAnalyzer::new(SIZE, SAMPLE_RATE);
+    let bin = 40;
+    let freq = analyzer.bin_frequency(bin);
+    let sine: Vec<f32> = (0..SIZE).map(
+	|i| f32::sin(2.0 * std::f32::consts::PI * freq * i as f32 / SAMPLE_RATE as f32)).collect();
+    let spectrum = analyzer.magnitudes_db(&sine);
+    let (peak, peak_db) = spectrum.iter().enumerate().fold((0, DB_FLOOR), |(bi, bv), (i, &v)| if v > bv { (i, v) } else { (bi, bv) });
+    assert_eq!(bin, peak);
+    // full-scale sine should come out at roughly 0 dBFS
+    assert!(peak_db > -1.0 && peak_db < 1.0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_spectrum_log_bins() {
+    let analyzer = SpectrumAnalyzer::new(1024, 48000);
+    let bins = analyzer.log_bins(100, 20.0);
+    assert_eq!(100, bins.len());
+    assert_eq!(0, bins[0]);
+    assert!(bins.windows(2).all(|w| w[0] <= w[1]));
+    assert!(bins[99] < analyzer.num_bins());
+}
+
+#[cfg(test)]
+#[test]
+fn test_spectrum_short_input() {
+    // One song tick at 48 kHz, analysed with a larger FFT
+    const SIZE : usize = 2048;
+    const SAMPLE_RATE : usize = 48000;
+    let mut analyzer = SpectrumAnalyzer::new(SIZE, SAMPLE_RATE);
+    let freq = analyzer.bin_frequency(100);
+    let sine: Vec<f32> = (0..960).map(
+	|i| f32::sin(2.0 * std::f32::consts::PI * freq * i as f32 / SAMPLE_RATE as f32)).collect();
+    let spectrum = analyzer.magnitudes_db(&sine);
+    let peak_db = spectrum.iter().cloned().fold(DB_FLOOR, f32::max);
+    assert!(peak_db > -1.0 && peak_db < 1.0);
+    assert!(spectrum[100] == peak_db);
+}
@@ -1,5 +1,5 @@
 use std::path::PathBuf;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -24,10 +24,52 @@ pub enum Command {
     Words,
     /// Extract all map strings
     Strings,
+    /// Re-encode all map and code strings, check that they decode to the same text, and
+    /// optionally write the re-encoded entries to a directory (only if no new string fragments are needed)
+    ReencodeText{dir: Option<PathBuf>},
+    /// Export all game text as gettext PO (or XLIFF), keyed by stable IDs
+    ExportText{
+	file: PathBuf,
+	/// Data files of another release (e.g., English for German) to include as reference
+	#[arg(short, long)]
+	reference: Option<PathBuf>,
+	/// Write XLIFF 1.2 (default: guess from the file name, falling back to PO)
+	#[arg(long)]
+	xliff: bool,
+    },
+    /// Validate a translated PO or XLIFF file and optionally write the re-encoded map and code text files to a directory
+    /// (only if the text can be encoded with the existing string fragments)
+    ImportText{file: PathBuf, dir: Option<PathBuf>},
+    /// Compare two data sets (e.g., German and English release): report everything that differs beyond text
+    CompareDatasets{
+	a: PathBuf,
+	b: PathBuf,
+	/// Also print all aligned strings
+	#[arg(long)]
+	strings: bool,
+    },
+    /// Find where a word, item, keyword or message is used: plain text, or keyword:N, key:N, char:N, chest:N
+    /// (chest contents, in CHESTDAT.AMB, are not indexed yet; chest:N only finds the events that open chest N)
+    Search{query: String},
+    /// Export the keyword / item / quest dependency graph (.dot or .json) and report what cannot be obtained
+    Progression{file: PathBuf},
     /// Plays the song with the given song number
     Song { song : Option<usize> },
     /// Plays the song with the given song number
     PrintSong { song : Option<usize> },
+    /// Interactive song debugger
+    DebugAudio,
+    /// Change a song's division range and speed and write the resulting AMBERDEV.UDO (to --output, default:
+    /// ./AMBERDEV.UDO).  The edited song must fit into the space of the original song.
+    SetSongInfo{
+	song: usize,
+	#[arg(long)]
+	first_division: Option<usize>,
+	#[arg(long)]
+	last_division: Option<usize>,
+	#[arg(long)]
+	speed: Option<usize>,
+    },
     /// Graphics demo (mainly intended for debugging and exploration)
     GfxDemo,
 
@@ -35,6 +77,16 @@ pub enum Command {
     ListChars,
     /// Print all information on a given character
     ShowChar { character: usize },
+    /// Write a character to a JSON (.json) or RON (any other extension) file
+    ExportChar { character: usize, file: PathBuf },
+    /// Load a character from a file written by export-char and print it
+    ImportChar { file: PathBuf },
+    /// List all items that characters carry
+    ListItems,
+    /// Print all information on a given item
+    ShowItem { item: usize },
+    /// Interactive text-mode conversation with a given character
+    Talk { character: usize },
 
     /// List all palettes
     ListPalettes,
@@ -44,7 +96,70 @@ pub enum Command {
     ListPixmaps,
     /// Extract pixmap as PNG
     ExtractPixmap{pixmap: String, palette: Option<String>},
+    /// Export all palettes, pixmaps, tilesets and lab blocks as PNG, plus a JSON manifest
+    ExportAll{dir: PathBuf},
+    /// Export all animated tiles and lab blocks as animated GIFs (or APNGs)
+    ExportAnimations{
+	dir: PathBuf,
+	/// Write APNG instead of GIF
+	#[arg(long)]
+	apng: bool,
+    },
+    /// Convert a PNG into an Amiga icon frame (width, height, #bitplanes header plus bitplanes)
+    ImportPixmap{
+	png: PathBuf,
+	palette: String,
+	/// Number of bitplanes in the output (default: as few as possible)
+	#[arg(short, long)]
+	bitplanes: Option<usize>,
+	/// Map colours that are not in the palette onto the nearest palette colour
+	#[arg(short, long)]
+	nearest: bool,
+    },
+    /// Render the first-person view of a lab map into a PNG
+    LabView{
+	map: usize,
+	x: usize,
+	y: usize,
+	#[arg(value_enum)]
+	dir: Direction,
+	/// In-game hour (0-23)
+	#[arg(long, default_value_t = 12)]
+	hour: usize,
+    },
+    /// Render a map (or, with --all, an atlas of all maps) top-down into a PNG
+    RenderMap{
+	#[arg(required_unless_present = "all")]
+	nr: Option<usize>,
+	/// Render all maps into one atlas
+	#[arg(long)]
+	all: bool,
+	/// Outline tiles with hotspots
+	#[arg(long)]
+	hotspots: bool,
+	/// Print event numbers on hotspots
+	#[arg(long)]
+	events: bool,
+	/// Outline NPC start positions
+	#[arg(long)]
+	npcs: bool,
+	/// Draw NPC movement routes
+	#[arg(long)]
+	routes: bool,
+    },
+    /// Render a map, as if fully explored, in the style of the in-game automap into a PNG
+    RenderAutomap{
+	nr: usize,
+    },
 
     /// Map viewer and 3D map walking demo
     MapViewer,
 }
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
@@ -2,18 +2,17 @@
 // Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
 
 use amber_remix::datafiles::attr::Attributed;
+use amber_remix::datafiles::chardata::CharData;
 #[allow(unused)]
 use log::{Level, log_enabled, trace, debug, info, warn, error};
 
-use cli::Command;
+use cli::{Command, Direction};
 
-use png_codec::Rgba;
-
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{io, fs};
 
 
-use amber_remix::datafiles::{self, ResourcePath, attr};
+use amber_remix::datafiles::{self, ResourcePath, map_string_table::{MapStringTable, TextEncoder}, translation::{self, TextFormat}, compare, search, progression, attr, export, import, animation::AnimationFormat, daycycle::TimeOfDay, labview, mapview, automap, map::MapDir};
 
 use clap::Parser;
 mod font;
@@ -21,6 +20,86 @@ mod cli;
 mod map_demo;
 mod gfx_demo;
 mod song_player;
+mod talk;
+
+/// Re-encodes the strings of all MAPTEXT.AMB and CODETXT.AMB entries; writes the results to `dir`, if given.
+/// Refuses to write if the strings need new string fragments: those would have to go into AMBERDEV.UDO,
+/// which we cannot write them back into yet.
+fn reencode_text(data : &datafiles::AmberstarFiles, map_strings : &[Vec<String>], code_strings : &[Vec<String>],
+		 dir : Option<&Path>) -> io::Result<()> {
+    let mut encoder = TextEncoder::new(&data.amberdev.string_fragments);
+    let mut encoded = vec![];
+    for (filename, tables) in [("MAPTEXT.AMB", map_strings), ("CODETXT.AMB", code_strings)] {
+	for (i, strings) in tables.iter().enumerate() {
+	    match encoder.encode_table(strings) {
+		Ok(bytes) => encoded.push((format!("{filename}.{i:03}"), strings, bytes)),
+		Err(e)    => error!("{filename}[{i}]: {e}"),
+	    }
+	}
+    }
+    let mut mismatches = 0;
+    for (name, strings, bytes) in &encoded {
+	let decoded = MapStringTable::new(bytes, &encoder.fragments);
+	for (k, (orig, new)) in strings.iter().zip(decoded.strings.iter()).enumerate() {
+	    if orig != new {
+		error!("{name} string {k} does not round-trip:\n  '{orig}'\n  '{new}'");
+		mismatches += 1;
+	    }
+	}
+    }
+    println!("Re-encoded {} tables, {mismatches} mismatches, {} new fragments", encoded.len(), encoder.num_new_fragments());
+
+    if let Some(dir) = dir {
+	if encoder.num_new_fragments() > 0 {
+	    error!("Not writing anything: the text needs {} new string fragments, which cannot be stored in AMBERDEV.UDO yet",
+		   encoder.num_new_fragments());
+	    return Ok(());
+	}
+	fs::create_dir_all(dir)?;
+	for (name, _, bytes) in &encoded {
+	    fs::write(dir.join(name), bytes)?;
+	}
+    }
+    return Ok(());
+}
+
+fn export_text(data : &datafiles::AmberstarFiles, reference : Option<&str>, file : &Path, format : TextFormat) -> io::Result<()> {
+    let source_text = translation::collect_text(data);
+    let reference_data = reference.map(|path| datafiles::AmberstarFiles::new(path));
+    let reference_text = reference_data.as_ref().map(|r| translation::collect_text(r));
+    let entries = translation::pair(&source_text, reference_text.as_deref());
+    let reference_lang = reference_data.as_ref().map(|r| r.amberdev.language.code());
+    fs::write(file, format.write(&entries, data.amberdev.language.code(), reference_lang))?;
+    println!("Wrote {} strings to {}", entries.len(), file.display());
+    return Ok(());
+}
+
+fn import_text(data : &datafiles::AmberstarFiles, file : &Path, format : TextFormat, dir : Option<&Path>) -> io::Result<()> {
+    let entries = match format.read(&fs::read_to_string(file)?) {
+	Ok(entries) => entries,
+	Err(e)      => { error!("{}: {e}", file.display()); return Ok(()); },
+    };
+    let game_text = translation::collect_text(data);
+    let problems = translation::validate(&entries, &game_text);
+    for problem in &problems {
+	println!("{problem}");
+    }
+    let translations = translation::translations(&entries);
+    println!("{} entries, {} translated, {} problems", entries.len(), translations.len(), problems.len());
+    if !problems.is_empty() {
+	return Ok(());
+    }
+    let unsupported = translation::unsupported_ids(&translations);
+    for id in &unsupported {
+	warn!("{id}: cannot be imported yet, only map and code text are written");
+    }
+    if !unsupported.is_empty() {
+	println!("{} translations skipped", unsupported.len());
+    }
+    let map_strings = translation::translate_tables(&data.map_text, translation::MAP_TEXT_PREFIX, &translations);
+    let code_strings = translation::translate_tables(&data.code_text, translation::CODE_TEXT_PREFIX, &translations);
+    return reencode_text(data, &map_strings, &code_strings, dir);
+}
 
 fn print_strings(data : &datafiles::AmberstarFiles) {
 
@@ -45,7 +124,6 @@ fn print_strings(data : &datafiles::AmberstarFiles) {
     }
 }
 
-// ================================================================================
 fn main() -> io::Result<()> {
     env_logger::init();
     let cli = cli::Cli::parse();
@@ -72,11 +150,38 @@ fn main() -> io::Result<()> {
 	    }
 	    true
 	},
+	Command::CompareDatasets{ a, b, strings } => {
+	    let data_a = datafiles::AmberstarFiles::new(a.to_str().unwrap());
+	    let data_b = datafiles::AmberstarFiles::new(b.to_str().unwrap());
+	    println!("{}: {:?}, {}: {:?}", a.display(), data_a.amberdev.language, b.display(), data_b.amberdev.language);
+	    let comparison = compare::compare(&data_a, &data_b);
+	    if strings {
+		for t in &comparison.aligned {
+		    println!("{}\n  {}\n  {}", t.id, t.a.replace('\n', "\\n"), t.b.replace('\n', "\\n"));
+		}
+	    }
+	    for d in &comparison.differences {
+		println!("{d}");
+	    }
+	    println!("{} strings aligned, {} differences", comparison.aligned.len(), comparison.differences.len());
+	    true
+	},
+	Command::ImportChar{ file } => {
+	    let text = fs::read_to_string(&file)?;
+	    let c : CharData = match file.extension().and_then(|e| e.to_str()) {
+		Some("json") => serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+		_            => ron::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+	    };
+	    let mut attr_iterator: attr::AttrIterator = (&c).attributes();
+	    c.print_header();
+	    attr::print_rec(&mut attr_iterator, "\t");
+	    true
+	},
 	_ => false,
     };
 
     if !completed {
-	let data = datafiles::AmberstarFiles::new(source);
+	let mut data = datafiles::AmberstarFiles::new(source);
 
 	match command {
 	    Command::Words =>
@@ -84,10 +189,84 @@ fn main() -> io::Result<()> {
 		    println!("{:4} 0x{:04x}: {}", w, w, data.amberdev.string_fragments.get(w as u16));
 		},
 	    Command::Strings => print_strings(&data),
+	    Command::ReencodeText { dir } => {
+		let map_strings : Vec<Vec<String>> = data.map_text.iter().map(|t| t.strings.clone()).collect();
+		let code_strings : Vec<Vec<String>> = data.code_text.iter().map(|t| t.strings.clone()).collect();
+		reencode_text(&data, &map_strings, &code_strings, dir.as_deref())?;
+	    }
+	    Command::ExportText { file, reference, xliff } => {
+		let format = if xliff { TextFormat::Xliff } else { TextFormat::from_path(&file).unwrap_or(TextFormat::Po) };
+		export_text(&data, reference.as_ref().and_then(|r| r.to_str()), &file, format)?;
+	    }
+	    Command::ImportText { file, dir } => {
+		match TextFormat::from_path(&file) {
+		    None         => error!("Unknown file format (expected .po or .xlf): {}", file.display()),
+		    Some(format) => import_text(&data, &file, format, dir.as_deref())?,
+		}
+	    }
+	    Command::Search { query } => {
+		let index = search::SearchIndex::new(&data);
+		let results = index.search(&query);
+		for entry in &results {
+		    println!("{}", index.describe(entry));
+		}
+		println!("{} results", results.len());
+	    }
+	    Command::Progression { file } => {
+		let graph = progression::ProgressionGraph::new(&data);
+		let out = match file.extension().and_then(|e| e.to_str()) {
+		    Some("json") => serde_json::to_string_pretty(&graph.to_json()).unwrap(),
+		    _            => graph.to_dot(),
+		};
+		fs::write(&file, out)?;
+		for node in graph.unreachable() {
+		    let required_by : Vec<String> = graph.required_by(node).iter().map(|n| graph.describe(n)).collect();
+		    println!("Unobtainable {}, required by {}", graph.describe(node), required_by.join(", "));
+		}
+		println!("{} nodes, {} edges written to {}", graph.labels.len(), graph.edges.len(), file.display());
+	    }
+	    Command::ListItems => {
+		for (i, item) in data.items.iter().enumerate() {
+		    println!("{i:3} (0x{i:02x}) {:-20} {:-14} {:5} gp {:5} g", item.name, item.item_type.to_string(), item.buy_price, item.weight);
+		}
+	    },
+	    Command::ShowItem { item } => {
+		match data.items.get(item) {
+		    None       => error!("Out of range"),
+		    Some(item) => {
+			let mut attr_iterator: attr::AttrIterator = item.attributes();
+			attr::print_rec(&mut attr_iterator, "\t");
+		    },
+		}
+	    },
+	    Command::Talk { character } =>
+		talk::talk(&data, character).unwrap(),
 	    Command::Song{song:song_nr} =>
 		song_player::play_song(&data, song_nr.unwrap_or(0)).unwrap(),
 	    Command::PrintSong{song:song_nr} =>
 		song_player::print_iter_song(&data, song_nr.unwrap_or(0)),
+	    Command::DebugAudio =>
+		amber_remix::debug_audio::debug_audio(&data).unwrap(),
+	    Command::SetSongInfo { song: song_nr, first_division, last_division, speed } => {
+		let mut dest_file = cli.output.clone();
+		let dest_file_str: &str = dest_file.to_str().unwrap();
+		if dest_file_str == "" || dest_file_str == "." {
+		    dest_file = PathBuf::from("AMBERDEV.UDO");
+		}
+		match data.songs.get(song_nr) {
+		    None       => error!("No song {song_nr}"),
+		    Some(song) => {
+			let mut song = song.clone();
+			song.songinfo.first_division = first_division.unwrap_or(song.songinfo.first_division);
+			song.songinfo.last_division = last_division.unwrap_or(song.songinfo.last_division);
+			song.songinfo.speed = speed.unwrap_or(song.songinfo.speed);
+			match data.amberdev.replace_song(&mut song).and_then(|_| data.encode_amberdev()) {
+			    Ok(encoded) => fs::write(&dest_file, encoded)?,
+			    Err(msg)    => error!("{msg}"),
+			}
+		    },
+		}
+	    },
 	    Command::GfxDemo => gfx_demo::show_images(&data),
 	    Command::MapViewer => map_demo::show_maps(&data),
 
@@ -112,6 +291,19 @@ fn main() -> io::Result<()> {
 		}
 	    },
 
+	    Command::ExportChar { character, file } => {
+		match data.chardata.get(character) {
+		    None    => error!("Out of range"),
+		    Some(c) => {
+			let out = match file.extension().and_then(|e| e.to_str()) {
+			    Some("json") => serde_json::to_string_pretty(c).unwrap(),
+			    _            => ron::ser::to_string_pretty(c, ron::ser::PrettyConfig::default()).unwrap(),
+			};
+			fs::write(&file, out)?;
+		    },
+		}
+	    },
+
 	    Command::ListPalettes => {
 		let palettes = data.palettes();
 		let mut keys: Vec<ResourcePath> = palettes.keys().into_iter().map(|k| k.clone()).collect();
@@ -164,26 +356,111 @@ fn main() -> io::Result<()> {
 			Some(palname)  => ResourcePath::from(&palname),
 		    };
 		    if palette.is_empty() {
-			panic!("No default palette for pixmap {pixmap_name}, specify palette explicitly");
-		    }
-		    if let Some(palette) = palettes.get(&palette) {
-			let palette: Vec<Rgba> = palette.colors.iter().map(|c| Rgba::new(c.r, c.g, c.b, c.a)).collect();
-			let png = png_codec::IndexedImage {
-			    height: pixmap.height as u32,
-			    width: pixmap.width as u32,
-			    pixels: &pixmap.pixels,
-			    palette: &palette,
-			};
-			let encoded = png.encode(5).unwrap();
-			std::fs::write(dest_file, &encoded).expect("Failed to save image");
+			error!("No default palette for pixmap {pixmap_name}, specify palette explicitly");
+		    } else if let Some(palette) = palettes.get(&palette) {
+			export::write_png(&dest_file, pixmap, palette)?;
 		    } else {
-			panic!("Not found: palette {palette}");
+			error!("Not found: palette {palette}");
+		    }
+		} else {
+		    error!("Not found: pixmap {pixmap}");
+		}
+	    }
+	    Command::ExportAll { dir } => {
+		export::export_all(&data, &dir)?;
+		println!("Wrote {}", dir.join("manifest.json").display());
+	    }
+	    Command::ExportAnimations { dir, apng } => {
+		let format = if apng { AnimationFormat::Apng } else { AnimationFormat::Gif };
+		export::export_animations(&data, &dir, format)?;
+	    }
+	    Command::LabView { map, x, y, dir, hour } => {
+		let mut dest_file = cli.output.clone();
+		let dest_file_str: &str = dest_file.to_str().unwrap();
+		if dest_file_str == "" || dest_file_str == "." {
+		    dest_file = PathBuf::from("labview.png");
+		}
+		let dir = match dir {
+		    Direction::North => MapDir::NORTH,
+		    Direction::East  => MapDir::EAST,
+		    Direction::South => MapDir::SOUTH,
+		    Direction::West  => MapDir::WEST,
+		};
+		match data.maps.get(map) {
+		    None      => error!("No such map: {map}"),
+		    Some(m)   => match labview::render_lab_view(&data, m, x, y, dir, TimeOfDay::new(hour, 0), 0) {
+			None                    => error!("Map {map} is not a first-person map"),
+			Some((pixmap, palette)) => export::write_png(&dest_file, &pixmap, &palette)?,
+		    },
+		}
+	    }
+	    Command::RenderMap { nr, all, hotspots, events, npcs, routes } => {
+		let overlays = mapview::Overlays { hotspots, events, npcs, routes };
+		let mut dest_file = cli.output.clone();
+		let dest_file_str: &str = dest_file.to_str().unwrap();
+		let use_default_name = dest_file_str == "" || dest_file_str == ".";
+		match (all, nr) {
+		    (true, _) => {
+			if use_default_name {
+			    dest_file = PathBuf::from("atlas.png");
+			}
+			mapview::render_atlas(&data, overlays).write_png(&dest_file)?;
+		    },
+		    (false, Some(nr)) => match data.maps.get(nr) {
+			None      => error!("No such map: {nr}"),
+			Some(map) => {
+			    if use_default_name {
+				dest_file = PathBuf::from(format!("map.{nr:03}.png"));
+			    }
+			    let (pixmap, palette) = mapview::render_map(&data, map, overlays);
+			    export::write_png(&dest_file, &pixmap, &palette)?;
+			},
+		    },
+		    (false, None) => error!("Need a map number or --all"),
+		}
+	    }
+	    Command::RenderAutomap { nr } => {
+		let mut dest_file = cli.output.clone();
+		let dest_file_str: &str = dest_file.to_str().unwrap();
+		if dest_file_str == "" || dest_file_str == "." {
+		    dest_file = PathBuf::from(format!("automap.{nr:03}.png"));
+		}
+		match data.maps.get(nr) {
+		    Some(map) => {
+			let mut automap = automap::Automap::for_map(map);
+			automap.reveal();
+			let (pixmap, palette) = automap::render(&data, map, &automap);
+			export::write_png(&dest_file, &pixmap, &palette)?;
+		    },
+		    None => error!("No such map: {nr}"),
+		}
+	    }
+	    Command::ImportPixmap { png, palette, bitplanes, nearest } => {
+		let mut dest_file = cli.output.clone();
+		let dest_file_str: &str = dest_file.to_str().unwrap();
+		if dest_file_str == "" || dest_file_str == "." {
+		    dest_file = png.with_extension("icn");
+		}
+		let palettes = data.palettes();
+		let matching = if nearest { import::PaletteMatching::Nearest } else { import::PaletteMatching::Exact };
+		if let Some(palette) = palettes.get(&ResourcePath::from(&palette)) {
+		    match import::png_to_icon_frame(&png, palette, matching, bitplanes) {
+			Ok((encoded, color_match)) => {
+			    if !color_match.is_exact() {
+				warn!("{} pixels mapped to nearest colour (max squared distance {})",
+				      color_match.inexact_pixels, color_match.max_distance);
+			    }
+			    fs::write(&dest_file, encoded)?;
+			}
+			Err(msg) => error!("{msg}"),
 		    }
 		} else {
-		    panic!("Not found: pixmap {pixmap}");
+		    error!("Not found: palette {palette}");
 		}
 	    }
 	    Command::Extract{..}  => {}, // already handled above
+	    Command::CompareDatasets{..}  => {}, // already handled above
+	    Command::ImportChar{..}  => {}, // already handled above
 	}
     }
 
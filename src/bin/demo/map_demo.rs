@@ -10,7 +10,8 @@ use std::{time::Duration, borrow::Borrow};
 
 use sdl2::{pixels::Color, event::Event, keyboard::Keycode, rect::{Rect, Point}, render::{TextureQuery, Canvas, Texture, TextureCreator, BlendMode}};
 
-use amber_remix::datafiles::{palette::Palette, map::{self, LabRef, MapDir, Illumination}, self, tile::Tileset, labgfx::{self, LabBlockType, LabBlock, LabPixmap}};
+use amber_remix::datafiles::{map::{self, LabRef, MapDir, Illumination}, self, tile::Tileset, labgfx::{self, LabBlockType, LabBlock, LabPixmap}, daycycle::{DayCycle, TimeOfDay}, labview};
+use amber_remix::audio::{self, amber::SongIterator, experiments::SongPlayerAudioSource};
 use std::fmt::Write;
 
 use crate::font::Font;
@@ -163,10 +164,7 @@ impl NPC {
     }
 
     pub fn pos_at_cyclepos(&self, cycle_pos : usize) -> Option<(usize, usize)> {
-	match &self.mapnpc.movement {
-	    map::NPCMovement::Cycle(cycle) => cycle[cycle_pos % cycle.len()],
-	    _                              => Some(self.mapnpc.start_pos),
-	}
+	return self.mapnpc.pos_at_cycle(cycle_pos);
     }
 
     pub fn tile_pos(&self) -> Option<(usize, usize)> {
@@ -238,12 +236,26 @@ fn labblock_textures<'a, T>(data : &datafiles::AmberstarFiles, tc : &'a TextureC
 }
 
 
+const SONG_CROSSFADE_MILLIS: u64 = 1500;
+
+/// Index into `data.songs` for the map's background music, if any
+fn map_song(data : &datafiles::AmberstarFiles, map : &map::Map) -> Option<usize> {
+    // Guess, not verified against the game: song_nr counts from 1, with 0 meaning "no music"
+    map.song_nr.checked_sub(1).filter(|&nr| nr < data.songs.len())
+}
+
 pub fn show_maps(data : &datafiles::AmberstarFiles) {
     map::debug_summary();
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
+    let audiocore = audio::acore::init(&sdl_context);
+    let mut mixer = audiocore.mixer();
+    let mut song_player = SongPlayerAudioSource::new(&data.sample_data, &data.songs, audiocore.frequency);
+    mixer.add_source(song_player.player());
+    let mut current_song_nr = None;
+
     let window = video_subsystem.window("amber-remix", 2560, 1600)
         .position_centered()
         .build()
@@ -272,6 +284,7 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
     let help : Vec<(Color, String)> = vec![
 	"=== key bindings ===",
 	"WASD : move over 3D maps | QE: rotate view",
+	"T : stop/start clock | , . : clock -/+ 1 hour",
 	"[F7] toggle tile nr printing",
 	"[F8] toggle event info",
 	"[F9] toggle NPC info",
@@ -302,11 +315,33 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
 
     const TIME_ADVANCE: usize = 20;
     const DAY_LENGTH: usize = 288; // 5 minute-chunks per day
+    const HOUR_LENGTH: usize = DAY_LENGTH / 24;
+
+    let day_cycle = DayCycle::new(&data.daylight_gradients);
+    let mut timeofday_finegrained = 12 * HOUR_LENGTH * 256;
+    let mut clock_running = true;
 
     'running: loop {
 
 	let map = &data.maps[map_nr];
 	let lab_info = &data.labgfx.labdata[map.tileset];
+
+	// Switch music when entering a map with a different song
+	let map_song_nr = map_song(data, map);
+	if map_song_nr != current_song_nr {
+	    let fade_duration = Duration::from_millis(SONG_CROSSFADE_MILLIS);
+	    match map_song_nr {
+		Some(nr) => {
+		    let song = &data.songs[nr];
+		    let song_it = SongIterator::new(song,
+						    song.songinfo.first_division,
+						    song.songinfo.last_division);
+		    song_player.crossfade_to(&song_it, fade_duration);
+		},
+		None     => song_player.fade_out(fade_duration),
+	    }
+	    current_song_nr = map_song_nr;
+	}
 	//let lab_bg_image_nr;
 	let mut lab_bg_images : Vec<(Texture<'_>, Texture<'_>)>; // ceiling, floor
 
@@ -322,7 +357,7 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
 
 	    let mut palette = data.lab_palettes[lab_info.palette_index].clone();
 	    if map.illumination == Illumination::Daylight {
-		palette = palette.with_transparency(11);
+		palette = palette.with_transparency(labview::BG_SKY_INDEX as usize);
 	    }
 
 	    //let palette = &palette::TEST_PALETTE;
@@ -350,7 +385,6 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
 	// Run the loop below while the current map is selected
 	let mut i : usize = 0;
 	let mut movedir = None;
-	let mut timeofday_finegrained = 0;
 	'current_map: loop {
             for event in event_pump.poll_iter() {
 		match event {
@@ -367,6 +401,9 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
 			Keycode::D            => { movedir = Some(dir.rotate_right()); }
 			Keycode::E            => { dir = dir.rotate_right(); }
 			Keycode::Q            => { dir = dir.rotate_left(); }
+			Keycode::T            => { clock_running = !clock_running; }
+			Keycode::Comma        => { timeofday_finegrained = (timeofday_finegrained + 256 * (DAY_LENGTH - HOUR_LENGTH)) % (256 * DAY_LENGTH); }
+			Keycode::Period       => { timeofday_finegrained = (timeofday_finegrained + 256 * HOUR_LENGTH) % (256 * DAY_LENGTH); }
 			Keycode::F1           => { only_tiles = !only_tiles },
 			Keycode::F2           => { if lab_nr > 0 { lab_nr -= 1; lab_img_nr = 0; break 'current_map; } },
 			Keycode::F3           => { if lab_nr < labblocks.len() - 1 { lab_img_nr = 0; lab_nr += 1; break 'current_map; } },
@@ -456,14 +493,13 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
 		}
 	    }
 
-	    timeofday_finegrained += TIME_ADVANCE;
-	    timeofday_finegrained %= 256 * DAY_LENGTH;
+	    if clock_running {
+		timeofday_finegrained += TIME_ADVANCE;
+		timeofday_finegrained %= 256 * DAY_LENGTH;
+	    }
 
-	    // hour, minute
-	    let (timeofday_hour, timeofday_minute) = {
-		let minutes = (timeofday_finegrained * 5) >> 8;
-		(minutes / 60,
-		 minutes % 60) };
+	    let timeofday = TimeOfDay::from_minutes((timeofday_finegrained * 5) >> 8);
+	    let (timeofday_hour, timeofday_minute) = (timeofday.hour, timeofday.minute);
 
 	    // draw NPC
 	    for npc in &mut npcs {
@@ -475,6 +511,7 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
 	    }
 
 
+
 	    if draw_event_info {
 		for y in 0..height {
 		    for x in 0..width {
@@ -618,41 +655,7 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
 
 		// draw floor and ceiling
 		if map.illumination == Illumination::Daylight {
-		    let gradients = &data.daylight_gradients;
-		    let mut bg_gradient = Palette::fill(&Color::BLACK, gradients.day.len());
-
-		    let partial_into_hour: u8 = ((timeofday_minute * 256) / 60) as u8;
-		    let partial_outof_hour: u8 = 255 - partial_into_hour;
-
-		    // day or night
-		    if timeofday_hour >= 8 && timeofday_hour <= 17 {
-			// day
-			bg_gradient.copy_into(&gradients.day);
-		    } else if timeofday_hour == 7 {
-			// late dawn
-			bg_gradient.blend_into(&gradients.day, partial_into_hour);
-		    } else if timeofday_hour == 18 {
-			// early dusk
-			bg_gradient.blend_into(&gradients.day, partial_outof_hour);
-		    } else if timeofday_hour < 6 || timeofday_hour >= 20 {
-			// night
-			bg_gradient.copy_into(&gradients.night);
-		    } else if timeofday_hour == 6 {
-			// early dawn
-			bg_gradient.blend_into(&gradients.night, partial_outof_hour);
-		    } else if timeofday_hour == 19 {
-			// late dusk
-			bg_gradient.blend_into(&gradients.night, partial_into_hour);
-		    } else {
-			println!("Weird hour: {timeofday_hour}");
-		    };
-
-		    // twilight
-		    match timeofday_hour {
-			6 | 18               => bg_gradient.blend_into(&gradients.twilight, partial_into_hour),
-			7 | 19               => bg_gradient.blend_into(&gradients.twilight, partial_outof_hour),
-			_                    => {},
-		    };
+		    let bg_gradient = day_cycle.sky_gradient(map.illumination, timeofday);
 
 		    const DAYLIGHT_WIDTH: usize = 144 * SCALE;
 		    const DAYLIGHT_HEIGHT: usize = 135;
@@ -671,7 +674,7 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
 		// draw floor and ceiling
 		{
 		    let bg_index = if lab_bg_images.len() == 0 { 0 } else {
-			if timeofday_hour >= 7 && timeofday_hour < 19 { 1 } else { 0 }
+			if timeofday.is_daytime() { 1 } else { 0 }
 		    };
 		    let (bg_ceiling, bg_floor) = &lab_bg_images[bg_index];
 		    let mut yoffset = 0;
@@ -691,43 +694,13 @@ pub fn show_maps(data : &datafiles::AmberstarFiles) {
 		    max_bg_height = usize::max(max_bg_height, yoffset as usize + 10);
 		}
 
-		// get NPCs of interest
-		let mut npcs_at_distance = vec![vec![], vec![], vec![], vec![]];
-		for npc in &npcs {
-		    if let Some((npc_x, npc_y)) = npc.tile_pos() {
-			for dist in 0..4 {
-			    if (npc_x, npc_y) == ((x as isize + dir.xvec() * dist) as usize,
-						  (y as isize + dir.yvec() * dist) as usize) {
-				npcs_at_distance[dist as usize].push(npc);
-				break;
-			    }
-			}
-		    }
-		}
-
-		// get labyrinth info
-		let map_views = map.lab_view(x as isize, y as isize, dir);
+		// get labyrinth info, including NPCs of interest
+		let npc_positions : Vec<(LabRef, (usize, usize))> = npcs.iter()
+		    .filter_map(|npc| npc.tile_pos().map(|pos| (npc.mapnpc.lab_ref(&map), pos)))
+		    .collect();
+		let views = labview::draw_order(&map, x as usize, y as usize, dir, &npc_positions);
 		let ttextures = &labblocks[tileset];
 
-		let mut views : Vec<(LabRef, usize, isize)> = vec![];
-		let mut last_distance : isize = 3;
-		for map_view in map_views {
-		    let (_, distance, _) = map_view;
-		    while distance < last_distance as usize {
-			for npc in &npcs_at_distance[last_distance as usize] {
-			    views.push((npc.mapnpc.lab_ref(&map), last_distance as usize, 0));
-			}
-			last_distance -= 1;
-		    }
-		    views.push(map_view);
-		}
-		while 0 <= last_distance {
-		    for npc in &npcs_at_distance[last_distance as usize] {
-			views.push((npc.mapnpc.lab_ref(&map), last_distance as usize, 0));
-		    }
-		    last_distance -= 1;
-		}
-
 		for (labinfo, dist, x) in views {
 		    for img in [labinfo.bg_image, labinfo.fg_image] {
 			let image_index = img; //lab_info.labblocks[img - 1];
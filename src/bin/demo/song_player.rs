@@ -15,9 +15,10 @@ use std::time::Duration;
 
 use amber_remix::audio::amber::SongIterator;
 use amber_remix::datafiles::music::Song;
-use sdl2::{pixels::Color, event::Event, keyboard::Keycode, rect::Rect, render::Canvas};
+use sdl2::{pixels::Color, event::Event, keyboard::{Keycode, Mod}, rect::Rect, render::Canvas};
 
-use amber_remix::audio::experiments::{SongPlayerAudioSource, SongTracer};
+use amber_remix::audio::experiments::{PlaybackControls, SongPlayerAudioSource, SongTracer};
+use amber_remix::audio::spectrum::{SpectrumAnalyzer, DB_FLOOR};
 use amber_remix::datafiles::{self};
 use amber_remix::audio::{self};
 
@@ -64,6 +65,7 @@ struct CurrentSongInfo<'a> {
     song_nr: usize,
     info_functions: &'a [(Keycode, &'a str, InfoFunction)],
     name: &'a str,
+    controls: PlaybackControls,
 }
 
 const COLOR_WHITE : Color = Color::RGBA(0xff, 0xff, 0xff, 0xff);
@@ -74,6 +76,45 @@ const COLOR_YELLOW : Color = Color::RGBA(0xff, 0xff, 0, 0xff);
 const COLOR_CHANNEL_SEP : Color = Color::RGBA(0, 0x80, 0xff, 0xff);
 const COLOR_CHANNEL : Color = COLOR_CYAN;
 
+/// Lowest frequency shown in spectra
+const SPECTRUM_MIN_FREQ : f32 = 20.0;
+const SPECTRUM_FFT_SIZE : usize = 2048;
+
+/// How to visualise the per-channel audio tracks
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TrackView {
+    Waveform,
+    Spectrum,
+    Spectrogram,
+}
+
+impl TrackView {
+    fn next(self) -> Self {
+	match self {
+	    TrackView::Waveform    => TrackView::Spectrum,
+	    TrackView::Spectrum    => TrackView::Spectrogram,
+	    TrackView::Spectrogram => TrackView::Waveform,
+	}
+    }
+}
+
+/// Maps a dB level to [0.0, 1.0]
+fn db_fraction(db: f32) -> f32 {
+    f32::max(0.0, (db - DB_FLOOR) / -DB_FLOOR)
+}
+
+/// Black -> blue -> red -> yellow -> white
+fn heat_color(level: f32) -> Color {
+    let v = (level.clamp(0.0, 1.0) * 1023.0) as u32;
+    let (r, g, b) = match v >> 8 {
+	0 => (0, 0, v),
+	1 => (v & 0xff, 0, 0xff - (v & 0xff)),
+	2 => (0xff, v & 0xff, 0),
+	_ => (0xff, 0xff, v & 0xff),
+    };
+    Color::RGBA(r as u8, g as u8, b as u8, 0xff)
+}
+
 struct PaginatedWriter<'a> {
     base_xpos: isize,
     base_ypos: isize,
@@ -157,6 +198,12 @@ fn songinfo_help(wr: &mut PaginatedWriter, _tracer: &ArcDemoSongTracer, song_inf
     wr.println("KPad   <- ->   : move in song");
     wr.println("KPad End  PgDn : move in song (single step)");
     wr.println("Enter          : Follow song");
+    wr.println("KPad + -       : Tempo");
+    wr.println("PgUp / PgDn    : Transpose");
+    wr.println("1-4            : Mute channel");
+    wr.println("Shift+1-4      : Solo channel");
+    wr.println("V              : Waveform / spectrum / spectrogram");
+    wr.println("O              : XY oscilloscope");
     for (kc, description, _) in song_info.info_functions.iter() {
 	wr.println(&format!("{:15}: {description}", format!("{kc}")));
     }
@@ -466,7 +513,16 @@ impl ArcDemoSongTracer {
 					  &mut* canvas);
 	pw.set_color(COLOR_GREEN);
 	pw.print("[F1] for help  ");
-	pw.println(&format!("Song {:02x}: {}", current_song_info.song_nr, current_song_info.name));
+	pw.print(&format!("Song {:02x}: {}  ", current_song_info.song_nr, current_song_info.name));
+	let controls = current_song_info.controls;
+	pw.set_color(COLOR_CYAN);
+	pw.print(&format!("tempo {:.2}  transpose {:+}  ", controls.tempo, controls.transpose));
+	for c in 0..4 {
+	    let status = if controls.muted[c] { "M" } else if controls.solo[c] { "S" } else { "-" };
+	    pw.set_color(if controls.is_audible(c) { COLOR_CHANNEL } else { COLOR_CHANNEL_SEP });
+	    pw.print(&format!("[{c}:{status}]"));
+	}
+	pw.newline();
 	pw.set_color(COLOR_YELLOW);
 	pw.set_color(COLOR_WHITE);
 	song_info_fn(&mut pw, &self, current_song_info);
@@ -564,6 +620,91 @@ impl ArcDemoSongTracer {
 }
 
 
+impl ArcDemoSongTracer {
+    /// Spectrum of a single tick, with a logarithmic frequency axis
+    fn draw_spectrum(&self, canvas: &mut Canvas<Window>,
+		     pos: Rect,
+		     channel: u8,
+		     tick: usize,
+		     analyzer: &mut SpectrumAnalyzer) {
+	canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
+	canvas.fill_rect(pos).unwrap();
+	let data = self.frame(channel, tick).data;
+	if data.is_empty() {
+	    return;
+	}
+	let spectrum = analyzer.magnitudes_db(&data);
+	let bins = analyzer.log_bins(pos.w as usize, SPECTRUM_MIN_FREQ);
+	for (x, &bin) in bins.iter().enumerate() {
+	    let level = db_fraction(spectrum[bin]);
+	    let bar_height = (level * pos.h as f32) as i32;
+	    if bar_height > 0 {
+		canvas.set_draw_color(heat_color(level));
+		canvas.fill_rect(Rect::new(pos.x + x as i32, pos.y + pos.h - bar_height,
+					   1, bar_height as u32)).unwrap();
+	    }
+	}
+    }
+
+    /// Spectrum over time, one column per tick, starting at start_tick
+    fn draw_spectrogram(&self, canvas: &mut Canvas<Window>,
+			pos: Rect,
+			channel: u8,
+			start_tick: usize,
+			downscale: i32,
+			analyzer: &mut SpectrumAnalyzer) {
+	canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
+	canvas.fill_rect(pos).unwrap();
+	let tick_width = i32::max(1, self.tick_length() as i32 / downscale);
+	let bins = analyzer.log_bins(pos.h as usize, SPECTRUM_MIN_FREQ);
+	let mut tick = start_tick;
+	let mut x = 0;
+	while x < pos.w {
+	    let data = self.frame(channel, tick).data;
+	    if !data.is_empty() {
+		let spectrum = analyzer.magnitudes_db(&data);
+		let column_width = i32::min(tick_width, pos.w - x) as u32;
+		for (row, &bin) in bins.iter().enumerate() {
+		    let level = db_fraction(spectrum[bin]);
+		    if level > 0.0 {
+			canvas.set_draw_color(heat_color(level));
+			canvas.fill_rect(Rect::new(pos.x + x, pos.y + pos.h - 1 - row as i32,
+						   column_width, 1)).unwrap();
+		    }
+		}
+	    }
+	    x += tick_width;
+	    tick += 1;
+	}
+    }
+
+    /// XY oscilloscope over the stereo mix of one tick: left on the X axis, right on the Y axis
+    fn draw_xy_scope(&self, canvas: &mut Canvas<Window>, pos: Rect, tick: usize) {
+	canvas.set_draw_color(Color::RGBA(0, 0x10, 0, 0xff));
+	canvas.fill_rect(pos).unwrap();
+	let xcenter = pos.x as f32 + pos.w as f32 / 2.0;
+	let ycenter = pos.y as f32 + pos.h as f32 / 2.0;
+	canvas.set_draw_color(Color::RGBA(0, 0x40, 0, 0xff));
+	canvas.draw_line(sdl2::rect::Point::new(pos.x, ycenter as i32),
+			 sdl2::rect::Point::new(pos.x + pos.w - 1, ycenter as i32)).unwrap();
+	canvas.draw_line(sdl2::rect::Point::new(xcenter as i32, pos.y),
+			 sdl2::rect::Point::new(xcenter as i32, pos.y + pos.h - 1)).unwrap();
+
+	let frames: Vec<ChannelTraceFrame> = (0..4).map(|c| self.frame(c, tick)).collect();
+	let len = frames.iter().map(|f| f.data.len()).max().unwrap_or(0);
+	let sample = |channel: usize, i: usize| frames[channel].data.get(i).copied().unwrap_or(0.0);
+	// Amiga stereo: channels 0 and 3 are left, 1 and 2 are right
+	let points: Vec<sdl2::rect::FPoint> = (0..len).map(|i| {
+	    let left = (sample(0, i) + sample(3, i)) * 0.5;
+	    let right = (sample(1, i) + sample(2, i)) * 0.5;
+	    sdl2::rect::FPoint::new(xcenter + left * pos.w as f32 * 0.5,
+				    ycenter - right * pos.h as f32 * 0.5)
+	}).collect();
+	canvas.set_draw_color(Color::RGBA(0x40, 0xff, 0x40, 0xff));
+	canvas.draw_flines(&points[..]).unwrap();
+    }
+}
+
 pub fn play_song(data : &datafiles::AmberstarFiles, song_nr : usize) -> Result<(), String> {
     let mut song = &data.songs[song_nr];
     let sdl_context = sdl2::init().unwrap();
@@ -597,6 +738,9 @@ pub fn play_song(data : &datafiles::AmberstarFiles, song_nr : usize) -> Result<(
     let mut current_song_nr = song_nr;
     let mut start_tick: usize = 0;
     let mut following_tick = true;
+    let mut track_view = TrackView::Waveform;
+    let mut show_scope = false;
+    let mut analyzer = SpectrumAnalyzer::new(SPECTRUM_FFT_SIZE, mixer.sample_rate);
 
     song_player.play(&poly_it);
     song_player.set_tracer(song_tracer.tracer());
@@ -646,6 +790,7 @@ pub fn play_song(data : &datafiles::AmberstarFiles, song_nr : usize) -> Result<(
 	    song_nr: current_song_nr,
 	    info_functions: &info_functions,
 	    name: &data.amberdev.song_names[current_song_nr],
+	    controls: song_player.controls(),
 	};
 
 	song_tracer.draw_info(&mut canvas,
@@ -655,15 +800,41 @@ pub fn play_song(data : &datafiles::AmberstarFiles, song_nr : usize) -> Result<(
 			      current_song_info,
 			      current_info_function);
 
+	// The tick to show in single-tick views
+	let current_tick = if following_tick { song_tracer.latest_tick() } else { start_tick };
+
 	for c in 0..4 {
-	    song_tracer.draw_audio_track(&mut canvas,
-					 sdl2::rect::Rect::new(100, 500 + (c as i32 * 400),
-							       waveform_pixel_width, 256),
-					 c,
-					 start_tick,
-					 scale as i32,
-					 &font
-	    );
+	    let track_pos = sdl2::rect::Rect::new(100, 500 + (c as i32 * 400),
+						  waveform_pixel_width, 256);
+	    match track_view {
+		TrackView::Waveform    =>
+		    song_tracer.draw_audio_track(&mut canvas,
+						 track_pos,
+						 c,
+						 start_tick,
+						 scale as i32,
+						 &font
+		    ),
+		TrackView::Spectrum    =>
+		    song_tracer.draw_spectrum(&mut canvas,
+					      track_pos,
+					      c,
+					      current_tick,
+					      &mut analyzer),
+		TrackView::Spectrogram =>
+		    song_tracer.draw_spectrogram(&mut canvas,
+						 track_pos,
+						 c,
+						 start_tick,
+						 scale as i32,
+						 &mut analyzer),
+	    }
+	}
+
+	if show_scope {
+	    song_tracer.draw_xy_scope(&mut canvas,
+				      sdl2::rect::Rect::new(2540, 40, 420, 420),
+				      current_tick);
 	}
 
         for event in event_pump.poll_iter() {
@@ -672,7 +843,15 @@ pub fn play_song(data : &datafiles::AmberstarFiles, song_nr : usize) -> Result<(
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
 		    break 'running
                 },
-                Event::KeyDown { keycode : Some(kc), repeat:false, .. } => {
+                Event::KeyDown { keycode : Some(kc), keymod, repeat:false, .. } => {
+		    let controls = song_player.controls();
+		    let channel_key = match kc {
+			Keycode::Num1 => Some(0),
+			Keycode::Num2 => Some(1),
+			Keycode::Num3 => Some(2),
+			Keycode::Num4 => Some(3),
+			_             => None,
+		    };
 		    match kc {
 			Keycode::BACKSPACE => { new_song_nr = Some(current_song_nr) },
 			Keycode::F11 =>  { if current_song_nr > 0 { new_song_nr = Some(current_song_nr - 1); } },
@@ -690,6 +869,20 @@ pub fn play_song(data : &datafiles::AmberstarFiles, song_nr : usize) -> Result<(
 			Keycode::KP_3 => { following_tick = false;
 					    start_tick += 1 },
 			Keycode::KP_ENTER => { following_tick = true; },
+			Keycode::V        => { track_view = track_view.next(); },
+			Keycode::O        => { show_scope = !show_scope; },
+			Keycode::KP_PLUS  => { song_player.set_tempo(controls.tempo * 1.125) },
+			Keycode::KP_MINUS => { song_player.set_tempo(controls.tempo / 1.125) },
+			Keycode::PAGEUP   => { song_player.set_transpose(controls.transpose + 1) },
+			Keycode::PAGEDOWN => { song_player.set_transpose(controls.transpose - 1) },
+			_ if channel_key.is_some() => {
+			    let c = channel_key.unwrap();
+			    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+				song_player.set_solo(c, !controls.solo[c]);
+			    } else {
+				song_player.set_muted(c, !controls.muted[c]);
+			    }
+			},
 			_ => {
 			    for (k, _, f) in info_functions.iter() {
 				if *k == kc {
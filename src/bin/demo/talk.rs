@@ -0,0 +1,113 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Text-mode conversation simulator, for testing dialogue
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+
+use amber_remix::datafiles::{self, conversation::{Action, Conversation, PartyState}};
+use rustyline::Editor;
+
+const HELP : &str = "\
+ask <word>      ask about a keyword
+show <key>      show an item with the given key ID
+give <key>      give away an item with the given key ID
+pay <gold>      pay gold
+feed <rations>  hand over food
+join            ask to join the party
+topics          list keywords that the character reacts to right now
+learn <word>    (cheat) learn a keyword
+take <key>      (cheat) get an item with the given key ID from any character's inventory
+gold <n>        (cheat) get gold
+food <n>        (cheat) get food
+flag <n>        (cheat) set an interaction status flag
+party           show the party state
+quit            end the conversation";
+
+fn parse_number(s : &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+	Some(hex) => usize::from_str_radix(hex, 16).ok(),
+	None      => s.parse().ok(),
+    }
+}
+
+fn print_party(conversation : &Conversation) {
+    let party = &conversation.party;
+    let words : Vec<&str> = party.keywords.iter().map(|w| w.as_str()).collect();
+    let items : Vec<String> = party.items.iter().map(|i| i.show_short()).collect();
+    println!("  keywords: {}", words.join(", "));
+    println!("  items:    {}", items.join(", "));
+    println!("  gold: {}  food: {}  XP: {}", party.gold, party.food, party.xp);
+    println!("  flags: {:?}  stat changes: {:?}  members: {:?}", party.flags, party.stat_changes, party.members);
+    println!("  interactions: {}", if conversation.is_complete() { "complete" } else { "incomplete" });
+}
+
+/// Runs one line of input; returns false to quit
+fn run(conversation : &mut Conversation, data : &datafiles::AmberstarFiles, line : &str) -> bool {
+    let words : Vec<&str> = line.split_whitespace().collect();
+    let (cmd, arg) = match words[..] {
+	[]         => return true,
+	[cmd]      => (cmd, None),
+	[cmd, arg] => (cmd, Some(arg)),
+	_          => { println!("Too many arguments; try 'help'"); return true; },
+    };
+    let number = arg.and_then(parse_number);
+    let word = arg.map(|w| (w, conversation.word(w)));
+
+    let action = match (cmd, number, word) {
+	("ask", _, Some((_, Some(w))))   => Action::Ask(w),
+	("ask" | "learn", _, Some((w, None))) => { println!("No such word: {w}"); return true; },
+	("learn", _, Some((_, Some(w)))) => { conversation.party.keywords.insert(w); return true; },
+	("show", Some(key), _)           => Action::Show(key),
+	("give", Some(key), _)           => Action::Give(key),
+	("pay", Some(gp), _)             => Action::Pay(gp),
+	("feed", Some(n), _)             => Action::Feed(n),
+	("join", None, None)             => Action::AskJoin,
+	("topics", None, None)           => { println!("  {}", conversation.topics().join(", ")); return true; },
+	("take", Some(key), _)           => {
+	    match data.chardata.iter().flat_map(|c| c.items.iter()).find(|(count, item)| *count > 0 && item.key_id().id == key) {
+		Some((_, item)) => conversation.party.items.push(item.clone()),
+		None            => println!("No item with key {key:x}"),
+	    }
+	    return true;
+	},
+	("gold", Some(n), _)             => { conversation.party.gold += n; return true; },
+	("food", Some(n), _)             => { conversation.party.food += n; return true; },
+	("flag", Some(n), _)             => { conversation.party.flags.insert(n); return true; },
+	("party", None, None)            => { print_party(conversation); return true; },
+	("quit", None, None)             => return false,
+	_                                => { println!("{HELP}"); return true; },
+    };
+    match conversation.act(action) {
+	Ok(responses) => for r in responses {
+	    println!("  {r}");
+	},
+	Err(e)        => println!("  {e}"),
+    }
+    return true;
+}
+
+pub fn talk(data : &datafiles::AmberstarFiles, char_nr : usize) -> rustyline::Result<()> {
+    let character = match data.chardata.get(char_nr) {
+	Some(c) => c,
+	None    => { error!("No such character: {char_nr}"); return Ok(()); },
+    };
+    let mut conversation = Conversation::new(&data.amberdev.string_fragments, char_nr, character, PartyState::default());
+    println!("Talking to {} ('help' for commands)", character.name);
+
+    let mut rl = Editor::<()>::new();
+    rl.load_history(".amber-remix-talk-history").unwrap_or(());
+    loop {
+	match rl.readline(&format!("{}> ", character.name)) {
+	    Ok(line) => {
+		rl.add_history_entry(line.as_str());
+		if !run(&mut conversation, data, &line) {
+		    break;
+		}
+	    },
+	    Err(_)   => break,
+	}
+    }
+    rl.save_history(".amber-remix-talk-history")
+}
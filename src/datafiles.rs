@@ -31,11 +31,12 @@ use self::map::Map;
 
 const DEBUG : bool = true;
 
-mod string_fragment_table;
-mod map_string_table;
+pub mod string_fragment_table;
+pub mod map_string_table;
 mod decode;
 mod bytepattern;
 mod pictures;
+mod icons;
 pub mod attr;
 pub mod amber_string;
 pub mod palette;
@@ -46,8 +47,20 @@ pub mod tile;
 pub mod map;
 pub mod labgfx;
 pub mod amberdev;
-mod chardata;
-mod item;
+pub mod daycycle;
+pub mod labview;
+pub mod mapview;
+pub mod automap;
+pub mod translation;
+pub mod compare;
+pub mod search;
+pub mod conversation;
+pub mod progression;
+pub mod animation;
+pub mod export;
+pub mod import;
+pub mod chardata;
+pub mod item;
 
 #[derive(Debug)]
 pub enum FileHeaderType {
@@ -55,6 +68,7 @@ pub enum FileHeaderType {
     // As file header:
     //     [hdr:32]
     //     [decompsize:32]  // only lower 24 bit
+    //     [compsize:32]
     //     ... (lob-compressed data)
     // (also in AMNC, AMNP, AMPC
     JH(u16),
@@ -227,6 +241,60 @@ impl<'a> DataBuf<'a> {
     }
 }
 
+/// LOB-compresses `data`, producing the stream that `decompress_lob` reads (without file header)
+fn compress_lob(data : &[u8]) -> Vec<u8> {
+    const MAX_OFFSET : usize = 0xfff;
+    const MIN_LENGTH : usize = 3;
+    const MAX_LENGTH : usize = 0xf + MIN_LENGTH;
+    const MAX_CANDIDATES : usize = 64;
+
+    let mut result = vec![];
+    // Earlier positions of each three-byte prefix
+    let mut prefix_positions : HashMap<&[u8], Vec<usize>> = HashMap::new();
+    let mut header_pos = 0;
+    let mut header_count = 8;
+    let mut pos = 0;
+    while pos < data.len() {
+	if header_count == 8 {
+	    header_pos = result.len();
+	    result.push(0);
+	    header_count = 0;
+	}
+	let mut best_length = 0;
+	let mut best_offset = 0;
+	if pos + MIN_LENGTH <= data.len() {
+	    let max_length = min(MAX_LENGTH, data.len() - pos);
+	    if let Some(candidates) = prefix_positions.get(&data[pos..pos + MIN_LENGTH]) {
+		for &candidate in candidates.iter().rev().take(MAX_CANDIDATES) {
+		    if pos - candidate > MAX_OFFSET {
+			break;
+		    }
+		    let length = (0..max_length).take_while(|&i| data[candidate + i] == data[pos + i]).count();
+		    if length > best_length {
+			best_length = length;
+			best_offset = pos - candidate;
+		    }
+		}
+	    }
+	}
+	let step = if best_length >= MIN_LENGTH {
+	    result.push((((best_offset >> 4) & 0xf0) | (best_length - MIN_LENGTH)) as u8);
+	    result.push(best_offset as u8);
+	    best_length
+	} else {
+	    result[header_pos] |= 0x80 >> header_count;
+	    result.push(data[pos]);
+	    1
+	};
+	header_count += 1;
+	for p in pos..min(pos + step, data.len().saturating_sub(MIN_LENGTH - 1)) {
+	    prefix_positions.entry(&data[p..p + MIN_LENGTH]).or_default().push(p);
+	}
+	pos += step;
+    }
+    result
+}
+
 // ----------------------------------------
 
 #[test]
@@ -312,10 +380,23 @@ fn test_databuf_decompress_lob() {
 		     0x33, 0x44, 0xaa, 0x33, 0x44, 0x22, 0x33, 0xaa, 0x33, 0x44, 0xbb] );
 }
 
+#[test]
+fn test_compress_lob_roundtrip() {
+    let mut data = vec![0x11, 0x22, 0x33, 0x44, 0x22, 0x33, 0x44, 0xaa, 0x00, 0x00, 0x00, 0x00, 0x00];
+    data.extend((0..5000).map(|i| ((i * 7) % 13) as u8));
+    data.extend((0..300).map(|i| (i & 0xff) as u8));
+    data.extend([0x33; 40]);
+    let mut compressed = compress_lob(&data);
+    assert!(compressed.len() < data.len());
+    let db = DataBuf { data : &mut compressed };
+    assert_eq!(db.decompress_lob(0, data.len()), data);
+}
+
 // ================================================================================
 pub struct DataFile {
     pub filetype : FileHeaderType,
     header_offset : usize, // Can be 4 for decoded JH
+    jh_key : Option<u16>,  // JH key, if we have decoded JH
     pub num_entries : u16,
     data : Vec<u8>,
 }
@@ -328,10 +409,15 @@ impl DataFile {
 	let meta = f.metadata().unwrap();
 	let mut buffer = vec![0; meta.len() as usize];
 	f.read(&mut buffer).unwrap();
+	return DataFile::new(buffer);
+    }
+
+    fn new(data : Vec<u8>) -> DataFile {
 	let mut result = DataFile { filetype      : FileHeaderType::RAW,
 				    header_offset : 0,
+				    jh_key        : None,
 				    num_entries   : 0,
-				    data          : Vec::from(buffer),
+				    data,
 	};
 	let buf = result.as_buf(0);
 	let filetype = buf.header_type(0);
@@ -380,6 +466,7 @@ impl DataFile {
 		buf.decode_jh(0, k);
 		self.filetype = buf.header_type(0);
 		self.header_offset = 4;
+		self.jh_key = Some(k);
 		return self.decode(index);
 	    }
 	    // LOB compression
@@ -435,6 +522,61 @@ impl DataFile {
 	    }
 	}
     }
+
+    /// Encodes `content` in the same format as this (single-entry, decoded) file, for writing back
+    /// The LOB marker (top byte of the decompressed size) is copied over unchanged.
+    pub fn encode(&self, content : &[u8]) -> Result<Vec<u8>, String> {
+	let mut result = match self.filetype {
+	    FileHeaderType::LOB => {
+		if content.len() > 0xffffff {
+		    return Err(format!("{} bytes are too many for LOB compression", content.len()));
+		}
+		let mut header = self.data[self.header_offset..self.header_offset + 12].to_vec();
+		let compressed = compress_lob(content);
+		let marker = decode::u32(&header, 4) & 0xff000000;
+		decode::put_u32(&mut header, 4, marker | content.len() as u32);
+		decode::put_u32(&mut header, 8, compressed.len() as u32);
+		header.extend(compressed);
+		header
+	    },
+	    FileHeaderType::RAW | FileHeaderType::ZERO => content.to_vec(),
+	    ref t => return Err(format!("Encoding {t:?} files is not supported")),
+	};
+	if let Some(key) = self.jh_key {
+	    // JH encoding is its own inverse
+	    DataBuf { data : &mut result }.decode_jh(0, key);
+	    let mut jh = self.data[0..4].to_vec();
+	    jh.extend(result);
+	    result = jh;
+	}
+	Ok(result)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_datafile_encode_jh_lob() {
+    let content : Vec<u8> = (0..600).map(|i| ((i * 5) % 11) as u8).collect();
+    let mut lob = vec![0x01, b'L', b'O', b'B', 0x06, 0, 0, 0, 0, 0, 0, 0];
+    lob.extend(compress_lob(&content));
+    decode::put_u32(&mut lob, 4, 0x06000000 | content.len() as u32);
+    let compressed_size = (lob.len() - 12) as u32;
+    decode::put_u32(&mut lob, 8, compressed_size);
+    DataBuf { data : &mut lob }.decode_jh(0, 0x1234);
+    let mut data = vec![b'J', b'H', 0x12, 0x34];
+    data.extend(lob);
+
+    let mut file = DataFile::new(data);
+    assert_eq!(content, file.decode(0));
+
+    let new_content : Vec<u8> = (0..900).map(|i| ((i * 3) % 7) as u8).collect();
+    let mut reencoded = DataFile::new(file.encode(&new_content).unwrap());
+    assert!(matches!(reencoded.filetype, FileHeaderType::JH(0x1234)));
+    assert_eq!(new_content, reencoded.decode(0));
+    // Decoding JH happens in place, so the LOB header is now readable
+    let header = reencoded.as_buf(4);
+    assert_eq!(0x06000000 | new_content.len() as u32, header.u32(4));
+    assert_eq!(compress_lob(&new_content).len() as u32, header.u32(8));
 }
 
 
@@ -461,6 +603,7 @@ pub struct AmberstarFiles {
     pub sample_data : sampledata::SampleData,
     pub songs : Vec<Song>,
     pub tiles : Vec<Tileset<Pixmap>>,
+    pub tiles_indexed : Vec<Tileset<IndexedPixmap>>,
     pub maps : Vec<Map>,
     pub bg_pictures : Vec<Vec<IndexedPixmap>>,
     pub combat_bg_pictures_indexed : Vec<IndexedPixmap>,
@@ -469,6 +612,9 @@ pub struct AmberstarFiles {
     pub monster_gfx : Vec<Vec<Pixmap>>,
     pub labgfx : labgfx::LabInfo,
     pub chardata : Vec<CharData>,
+    /// Distinct items that characters carry (the global item table in AMBERDEV has not been located yet)
+    pub items : Vec<item::Item>,
+    /// Icon files, one tileset (with its own palette) per entry
     pub daylight_gradients: DaylightGradientPalettes, // day, night, twilight
 }
 
@@ -502,11 +648,11 @@ fn load_palettes(dfile : &mut DataFile) -> Vec<Palette> {
     return result;
 }
 
-fn load_tiles(dfile : &mut DataFile) -> Vec<Tileset<Pixmap>> {
+fn load_tiles(dfile : &mut DataFile) -> Vec<Tileset<IndexedPixmap>> {
     let mut result = vec![];
     for e in 0..dfile.num_entries {
 	let dat = dfile.decode(e);
-	result.push(tile::new(&dat));
+	result.push(tile::new_indexed(&dat));
     }
     return result;
 }
@@ -595,6 +741,13 @@ impl AmberstarFiles {
 	return load_relative(&self.path, f);
     }
 
+    /// Encodes `amberdev` (e.g., after `Amberdev::replace_song`) in the format of AMBERDEV.UDO
+    pub fn encode_amberdev(&self) -> Result<Vec<u8>, String> {
+	let mut amberdev_f = self.load("AMBERDEV.UDO");
+	amberdev_f.decode(0);
+	amberdev_f.encode(&self.amberdev.data)
+    }
+
     /// Extracts all palettes and gives them names
     pub fn palettes(&self) -> HashMap<ResourcePath, Palette> {
 	let mut m = HashMap::new();
@@ -614,6 +767,13 @@ impl AmberstarFiles {
 		m.insert(&p/s, palette);
 	    }
 	}
+	{
+	    let p = ResourcePath::new(&["amberdev"]);
+	    for (i, palette) in self.amberdev_palettes.iter().enumerate() {
+		let s = format!("{:02x}", i);
+		m.insert(&p/s, palette.clone());
+	    }
+	}
 	{
 	    let p = ResourcePath::new(&["pics80"]);
 	    for (i, palette) in self.pics80_palettes.iter().enumerate() {
@@ -622,6 +782,14 @@ impl AmberstarFiles {
 	    }
 	}
 
+	for (name, icon_files) in self.icon_files() {
+	    let p = ResourcePath::new(&[name]);
+	    for (i, tileset) in icon_files.iter().enumerate() {
+		let s = format!("{:02x}", i);
+		m.insert(&p/s, tileset.palette.clone());
+	    }
+	}
+
 	let daylight_gradients = Palette::daylight_palettes(&self.amberdev);
 	m.insert(ResourcePath::from("outdoors.day"), daylight_gradients.day);
 	m.insert(ResourcePath::from("outdoors.night"), daylight_gradients.night);
@@ -629,6 +797,44 @@ impl AmberstarFiles {
 	return m;
     }
 
+    /// The .ICN files, by their resource name.  Their layout is not verified, so they are only
+    /// loaded on demand; files that fail to decode are skipped with a warning.
+    fn icon_files(&self) -> Vec<(&'static str, Vec<Tileset<IndexedPixmap>>)> {
+	let mut result = vec![];
+	for (name, filename) in [("f_t_anim", "F_T_ANIM.ICN"),
+				 ("tactic", "TACTIC.ICN"),
+				 ("puzzle", "PUZZLE.ICN")] {
+	    match icons::load_icons(&mut self.load(filename)) {
+		Ok(tilesets) => result.push((name, tilesets)),
+		Err(msg)     => pwarn!("Skipping {filename}: {msg}"),
+	    }
+	}
+	return result;
+    }
+
+    /// Data file, entry number and (for entries with several pictures) picture number within the entry
+    /// that the pixmap with the given name (cf. `pixmaps()`) comes from
+    pub fn pixmap_source(name : &ResourcePath) -> Option<(&'static str, usize, Option<usize>)> {
+	let index = |i : usize| usize::from_str_radix(name.get(i)?, 16).ok();
+	// (file, number of indices in the name, shift from first index to entry number)
+	let (file, levels, entry_shift) = match name.first()?.as_str() {
+	    "combat"  => ("COM_BACK.AMB", 1, 0),
+	    "bg"      => ("BACKGRND.AMB", 2, 0),
+	    "monster" => ("MON_GFX.AMB", 2, 0),
+	    "pics80"  => ("PICS80.AMB", 1, 1),
+	    "char"    => ("CHARDATA.AMB", 1, 0),
+	    "f_t_anim"=> ("F_T_ANIM.ICN", 2, 0),
+	    "tactic"  => ("TACTIC.ICN", 2, 0),
+	    "puzzle"  => ("PUZZLE.ICN", 2, 0),
+	    _         => return None,
+	};
+	if name.len() != levels + 1 {
+	    return None;
+	}
+	let picture = if levels == 2 { Some(index(2)?) } else { None };
+	return Some((file, index(1)? << entry_shift, picture));
+    }
+
     /// Extracts all indexed pictures by name, plus their preferred palettes
     pub fn pixmaps(&self) -> HashMap<ResourcePath, (ResourcePath, IndexedPixmap)> {
 	let mut m = HashMap::new();
@@ -682,6 +888,17 @@ impl AmberstarFiles {
 		}
 	    }
 	}
+	for (name, icon_files) in self.icon_files() {
+	    let p = ResourcePath::new(&[name]);
+	    for (i, tileset) in icon_files.iter().enumerate() {
+		let is = format!("{:02x}", i);
+		let ps = &p/is;
+		for (j, pic) in tileset.all_frames().into_iter().enumerate() {
+		    let js = format!("{:02x}", j);
+		    m.insert(&ps/js, (ps.clone(), pic));
+		}
+	    }
+	}
 	return m;
     }
 
@@ -720,7 +937,8 @@ impl AmberstarFiles {
 	let sample_data = sampledata::SampleData::new(sampledata_f.decode(0));
 
 	let mut tiles_f = load_relative(path, "ICON_DAT.AMB");
-	let tiles = load_tiles(&mut tiles_f);
+	let tiles_indexed = load_tiles(&mut tiles_f);
+	let tiles = tiles_indexed.iter().map(|t| t.with_palette()).collect();
 
 	let mut map_data_f = load_relative(path, "MAP_DATA.AMB");
 	let maps = load_maps(&mut map_data_f);
@@ -748,6 +966,9 @@ impl AmberstarFiles {
 	let mut chardata_f = load_relative(path, "CHARDATA.AMB");
 	let chardata : Vec<CharData> = (0..(chardata_f.num_entries)).map(|i| CharData::new(&amberdev.string_fragments, i, &chardata_f.decode(i))).collect();
 
+	let items = item::inventory_items(&chardata);
+
+
 	let amberdev_palettes = Palette::amberdev_palettes(&amberdev);
 	let path : String = format!("{}", path);
 
@@ -767,6 +988,7 @@ impl AmberstarFiles {
 	    sample_data,
 	    songs,
 	    tiles,
+	    tiles_indexed,
 	    maps,
 	    bg_pictures,
 	    combat_bg_pictures_indexed,
@@ -775,7 +997,17 @@ impl AmberstarFiles {
 	    monster_gfx,
 	    labgfx,
 	    chardata,
+	    items,
 	    daylight_gradients,
 	}
     }
 }
+
+#[cfg(test)]
+#[test]
+fn test_pixmap_source() {
+    assert_eq!(Some(("PICS80.AMB", 0x0a, None)), AmberstarFiles::pixmap_source(&ResourcePath::new(&["pics80", "05"])));
+    assert_eq!(Some(("BACKGRND.AMB", 3, Some(0x11))), AmberstarFiles::pixmap_source(&ResourcePath::new(&["bg", "03", "11"])));
+    assert_eq!(None, AmberstarFiles::pixmap_source(&ResourcePath::new(&["monster", "02"])));
+    assert_eq!(None, AmberstarFiles::pixmap_source(&ResourcePath::new(&["char", "01", "00"])));
+}
@@ -59,20 +59,33 @@ pub fn vec_from_terminated_bytes(src: &[u8]) -> Vec<String> {
     return results;
 }
 
-/// Panics if not found
-pub fn to_byte(c: char) -> u8 {
+/// Atari ST codepoint for `c`, if there is one
+pub fn try_to_byte(c: char) -> Option<u8> {
     if c.is_ascii() {
 	let code: u32 = c.into();
-	return code as u8;
+	return Some(code as u8);
     }
     for pos in 128..ATARI_ST_CODEPOINTS.len() {
 	if ATARI_ST_CODEPOINTS[pos] == c {
-	    return pos as u8;
+	    return Some(pos as u8);
 	}
     }
-    panic!("Cannot convert '{c}' to Atari ST codepoint");
+    return None;
+}
+
+/// Panics if not found
+pub fn to_byte(c: char) -> u8 {
+    match try_to_byte(c) {
+	Some(b) => b,
+	None    => panic!("Cannot convert '{c}' to Atari ST codepoint"),
+    }
 }
 
 pub fn to_bytes(src: &str) -> Vec<u8> {
     src.chars().map(to_byte).collect()
 }
+
+/// Like `to_bytes`, but reports characters without Atari ST codepoint
+pub fn try_to_bytes(src: &str) -> Result<Vec<u8>, String> {
+    src.chars().map(|c| try_to_byte(c).ok_or_else(|| format!("Cannot convert '{c}' to Atari ST codepoint"))).collect()
+}
@@ -5,14 +5,24 @@
 
 use std::ops::Deref;
 
-use super::{string_fragment_table::StringFragmentTable, amber_string, decode};
+use super::{string_fragment_table::StringFragmentTable, amber_string, decode, music::Song};
 
 /// Game file language
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Language {
     DE, EN
 }
 
+impl Language {
+    /// ISO 639-1 language code
+    pub fn code(&self) -> &'static str {
+	match self {
+	    Language::DE => "de",
+	    Language::EN => "en",
+	}
+    }
+}
+
 /// Positions of special entities within an Amberdev file
 pub struct Positions {
     /// start of the string fragment table
@@ -98,6 +108,23 @@ impl Amberdev {
 	return self.data.len();
     }
 
+    /// Stores a (possibly edited) song in the place that it was loaded from; unused space is zeroed.
+    /// Songs cannot grow: the encoded song must fit into the space of the original (`Song::source_len`),
+    /// since we do not know all places in the executable that refer to song positions.  Fails if the
+    /// song does not come from here or no longer fits.
+    pub fn replace_song(&mut self, song : &mut Song) -> Result<(), String> {
+	let offset = song.source_offset().ok_or("Song was not loaded from AMBERDEV")?;
+	let slot_len = song.source_len();
+	let coso = song.to_coso()?;
+	if coso.len() > slot_len {
+	    return Err(format!("Song needs {} bytes, but only {slot_len} are available at 0x{offset:x}", coso.len()));
+	}
+	self.data[offset..offset + coso.len()].copy_from_slice(&coso);
+	self.data[offset + coso.len()..offset + slot_len].fill(0);
+	song.set_stored(coso);
+	Ok(())
+    }
+
     fn extract_merchant_names(&self) -> Vec<String> {
 	let mut pos = self.positions.merchant_name_table;
 	let mut result = vec![];
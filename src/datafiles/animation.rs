@@ -0,0 +1,167 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Animated GIF / APNG export for tile icons and LabBlock perspectives
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use super::labgfx::LabImage;
+use super::palette::Palette;
+use super::pixmap::IndexedPixmap;
+use super::tile::TileIcon;
+
+/// Tile animations advance every 16 frames at 60 Hz, as in the map viewer
+pub const TILE_FRAME_MILLIS : u32 = 16 * 1000 / 60;
+/// LabBlock animations advance every 8 frames at 60 Hz, as in the map viewer
+pub const LAB_FRAME_MILLIS : u32 = 8 * 1000 / 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationOrder {
+    /// 0, 1, ..., n-1, 0, 1, ...
+    Loop,
+    /// 0, 1, ..., n-1, n-2, ..., 1, 0, 1, ...
+    BackAndForth,
+}
+
+impl AnimationOrder {
+    /// Frame indices for one full animation cycle
+    pub fn sequence(&self, num_frames : usize) -> Vec<usize> {
+	let mut seq : Vec<usize> = (0..num_frames).collect();
+	if *self == AnimationOrder::BackAndForth && num_frames > 2 {
+	    seq.extend((1..num_frames - 1).rev());
+	}
+	return seq;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+	match self {
+	    AnimationFormat::Gif  => "gif",
+	    AnimationFormat::Apng => "png",
+	}
+    }
+}
+
+/// Equally-sized frames, in playback order
+pub struct Animation {
+    pub frames : Vec<IndexedPixmap>,
+    pub frame_millis : u32,
+}
+
+impl Animation {
+    pub fn new(frames : &[IndexedPixmap], order : AnimationOrder, frame_millis : u32) -> Animation {
+	Animation {
+	    frames : order.sequence(frames.len()).iter().map(|i| frames[*i].clone()).collect(),
+	    frame_millis,
+	}
+    }
+
+    /// Tile animation; the TileFlags determine the frame order.
+    /// (ANIM_RANDOM_START only shifts the starting frame between tiles, so it does not affect the cycle.)
+    pub fn from_tile(icon : &TileIcon<IndexedPixmap>) -> Animation {
+	let order = if icon.flags.anim_back_and_forth() { AnimationOrder::BackAndForth } else { AnimationOrder::Loop };
+	Animation::new(&icon.frames, order, TILE_FRAME_MILLIS)
+    }
+
+    /// LabBlock perspective animation, with frames placed at their offsets on a shared canvas
+    /// (on top of the animation base image, if there is one)
+    pub fn from_lab_image(image : &LabImage<IndexedPixmap>, order : AnimationOrder) -> Animation {
+	let all = image.base_pixmap.iter().chain(image.pixmaps.iter());
+	let min_x = all.clone().map(|p| p.xoffset).min().unwrap_or(0);
+	let min_y = all.clone().map(|p| p.yoffset).min().unwrap_or(0);
+	let width = all.clone().map(|p| p.x_extent() - min_x).max().unwrap_or(0);
+	let height = all.map(|p| p.y_extent() - min_y).max().unwrap_or(0);
+
+	let frames : Vec<IndexedPixmap> = image.pixmaps.iter().map(|p| {
+	    let mut frame = IndexedPixmap::empty(width, height);
+	    if let Some(base) = &image.base_pixmap {
+		frame.blit_into(&base.pixmap, base.xoffset - min_x, base.yoffset - min_y);
+	    }
+	    frame.blit_into(&p.pixmap, p.xoffset - min_x, p.yoffset - min_y);
+	    frame
+	}).collect();
+	Animation::new(&frames, order, LAB_FRAME_MILLIS)
+    }
+
+    fn size(&self) -> (usize, usize) {
+	self.frames.first().map(|f| (f.width, f.height)).unwrap_or((0, 0))
+    }
+
+    pub fn write(&self, path : &Path, palette : &Palette, format : AnimationFormat) -> io::Result<()> {
+	match format {
+	    AnimationFormat::Gif  => self.write_gif(path, palette),
+	    AnimationFormat::Apng => self.write_apng(path, palette),
+	}
+    }
+
+    pub fn write_gif(&self, path : &Path, palette : &Palette) -> io::Result<()> {
+	let (width, height) = self.size();
+	let rgb : Vec<u8> = palette.colors.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+	let transparent = palette.colors.iter().position(|c| c.a < 0x80).map(|i| i as u8);
+	let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), width as u16, height as u16, &rgb)
+	    .map_err(gif_err)?;
+	encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_err)?;
+	for frame in &self.frames {
+	    let gif_frame = gif::Frame {
+		width : width as u16,
+		height : height as u16,
+		// GIF delays are in units of 10ms
+		delay : ((self.frame_millis + 5) / 10) as u16,
+		dispose : gif::DisposalMethod::Background,
+		transparent,
+		buffer : Cow::Borrowed(&frame.pixels),
+		..gif::Frame::default()
+	    };
+	    encoder.write_frame(&gif_frame).map_err(gif_err)?;
+	}
+	return Ok(());
+    }
+
+    pub fn write_apng(&self, path : &Path, palette : &Palette) -> io::Result<()> {
+	let (width, height) = self.size();
+	let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width as u32, height as u32);
+	encoder.set_color(png::ColorType::Indexed);
+	encoder.set_depth(png::BitDepth::Eight);
+	encoder.set_palette(palette.colors.iter().flat_map(|c| [c.r, c.g, c.b]).collect::<Vec<u8>>());
+	encoder.set_trns(palette.colors.iter().map(|c| c.a).collect::<Vec<u8>>());
+	encoder.set_animated(self.frames.len() as u32, 0).map_err(png_err)?;
+	encoder.set_frame_delay(self.frame_millis as u16, 1000).map_err(png_err)?;
+	let mut writer = encoder.write_header().map_err(png_err)?;
+	for frame in &self.frames {
+	    writer.write_image_data(&frame.pixels).map_err(png_err)?;
+	}
+	return writer.finish().map_err(png_err);
+    }
+}
+
+fn gif_err(e : gif::EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn png_err(e : png::EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+#[cfg(test)]
+#[test]
+fn test_animation_order() {
+    assert_eq!(vec![0, 1, 2, 3], AnimationOrder::Loop.sequence(4));
+    assert_eq!(vec![0, 1, 2, 3, 2, 1], AnimationOrder::BackAndForth.sequence(4));
+    assert_eq!(vec![0, 1], AnimationOrder::BackAndForth.sequence(2));
+    assert_eq!(Vec::<usize>::new(), AnimationOrder::BackAndForth.sequence(0));
+}
@@ -228,3 +228,27 @@ pub fn print_rec(it: &mut AttrIterator, prefix: &str) {
 	println!("{prefix}{s}");
     }
 }
+
+fn flatten_into(result: &mut Vec<(String, String)>, prefix: &str, value: AttributeValue) {
+    match value {
+	AttributeValue::String(s) => result.push((prefix.to_string(), s)),
+	AttributeValue::Count(n, sub) => {
+	    result.push((format!("{prefix}.count"), format!("{n}")));
+	    flatten_into(result, prefix, *sub);
+	},
+	AttributeValue::Entity(e) => {
+	    for Attr { name, value } in e {
+		flatten_into(result, &format!("{prefix}.{name}"), value);
+	    }
+	},
+    }
+}
+
+/// All (nested) attributes as (dotted path, value) pairs, e.g. for comparing entities
+pub fn flatten(it: AttrIterator) -> Vec<(String, String)> {
+    let mut result = vec![];
+    for Attr { name, value } in it {
+	flatten_into(&mut result, &name, value);
+    }
+    result
+}
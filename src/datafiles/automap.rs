@@ -0,0 +1,193 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Automap: which parts of each map the party has explored, and how the in-game automap draws them
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use super::AmberstarFiles;
+use super::map::Map;
+use super::palette::{Color, Palette};
+use super::pixmap::IndexedPixmap;
+
+// ----------------------------------------
+// The saved automaps (AUTOMAP.AMB) are not decoded yet: we have not verified their layout, so explored
+// areas only exist in memory and the automap symbols are missing.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Automap {
+    pub width : usize,
+    pub height : usize,
+    pub explored : Vec<bool>,
+}
+
+impl Automap {
+    /// Nothing explored
+    pub fn empty(width : usize, height : usize) -> Automap {
+	Automap {
+	    width,
+	    height,
+	    explored : vec![false; width * height],
+	}
+    }
+
+    pub fn for_map(map : &Map) -> Automap {
+	Automap::empty(map.width, map.height)
+    }
+
+    pub fn is_explored(&self, x : usize, y : usize) -> bool {
+	x < self.width && y < self.height && self.explored[x + y * self.width]
+    }
+
+    pub fn set_explored(&mut self, x : usize, y : usize, explored : bool) {
+	if x < self.width && y < self.height {
+	    self.explored[x + y * self.width] = explored;
+	}
+    }
+
+    /// Marks the whole map as explored
+    pub fn reveal(&mut self) {
+	self.explored.iter_mut().for_each(|e| *e = true);
+    }
+
+    pub fn num_explored(&self) -> usize {
+	self.explored.iter().filter(|e| **e).count()
+    }
+}
+
+// ----------------------------------------
+// Rendering
+
+/// Width and height of one map tile in the automap, in pixels
+pub const AUTOMAP_CELL_SIZE : usize = 8;
+
+/// Colour indices past the end of the 16-colour map palettes
+pub const UNEXPLORED_INDEX : u8 = 0x10;
+pub const FLOOR_INDEX : u8 = 0x11;
+pub const WALL_INDEX : u8 = 0x12;
+const AUTOMAP_COLORS : [(u8, Color); 3] = [
+    (UNEXPLORED_INDEX, Color::BLACK),
+    (FLOOR_INDEX,      Color::rgb(0x40, 0x40, 0x40)),
+    (WALL_INDEX,       Color::rgb(0xc0, 0xc0, 0xc0)),
+];
+
+/// What an explored tile looks like on the automap
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cell {
+    /// 2D maps: solid colour (index into the map palette)
+    Color(u8),
+    /// First-person maps: open space
+    Floor,
+    /// First-person maps: blocks the view
+    Wall,
+    /// First-person maps: furniture, doors, decorations etc. that do not block the view
+    Object,
+}
+
+fn fill(dest : &mut IndexedPixmap, x : usize, y : usize, width : usize, height : usize, color : u8) {
+    for py in y..y + height {
+	dest.pixels[py * dest.width + x..py * dest.width + x + width].fill(color);
+    }
+}
+
+/// Draws explored cells; walls are drawn as outlines along the edges they share with explored non-wall cells
+fn render_cells(automap : &Automap, cell_at : &dyn Fn(usize, usize) -> Cell) -> IndexedPixmap {
+    const C : usize = AUTOMAP_CELL_SIZE;
+    let mut result = IndexedPixmap::empty(automap.width * C, automap.height * C);
+    result.pixels.fill(UNEXPLORED_INDEX);
+    let is_wall = |x : isize, y : isize| x < 0 || y < 0 || x as usize >= automap.width || y as usize >= automap.height
+	|| cell_at(x as usize, y as usize) == Cell::Wall;
+
+    for y in 0..automap.height {
+	for x in 0..automap.width {
+	    if !automap.is_explored(x, y) {
+		continue;
+	    }
+	    let (px, py) = (x * C, y * C);
+	    match cell_at(x, y) {
+		Cell::Color(c) => fill(&mut result, px, py, C, C, c),
+		Cell::Wall     => {},
+		cell           => {
+		    fill(&mut result, px, py, C, C, FLOOR_INDEX);
+		    let (ix, iy) = (x as isize, y as isize);
+		    if is_wall(ix, iy - 1) { fill(&mut result, px, py, C, 1, WALL_INDEX); }
+		    if is_wall(ix, iy + 1) { fill(&mut result, px, py + C - 1, C, 1, WALL_INDEX); }
+		    if is_wall(ix - 1, iy) { fill(&mut result, px, py, 1, C, WALL_INDEX); }
+		    if is_wall(ix + 1, iy) { fill(&mut result, px + C - 1, py, 1, C, WALL_INDEX); }
+		    if cell == Cell::Object {
+			fill(&mut result, px + C / 4, py + C / 4, C / 2, C / 2, WALL_INDEX);
+		    }
+		},
+	    }
+	}
+    }
+    return result;
+}
+
+/// Renders the explored parts of the map: 2D maps in the map colours of their (topmost) tiles,
+/// first-person maps as wall outlines.  The palette is the map's palette plus the automap colours.
+pub fn render(data : &AmberstarFiles, map : &Map, automap : &Automap) -> (IndexedPixmap, Palette) {
+    let mut palette;
+    let pixmap;
+    if map.first_person {
+	palette = data.lab_palettes[data.labgfx.labdata[map.tileset].palette_index].clone();
+	pixmap = render_cells(automap, &|x, y| {
+	    match map.tile_at(0, x, y).and_then(|t| t.checked_sub(1)).and_then(|t| map.lab_info.get(t)) {
+		None                                => Cell::Floor,
+		Some(l) if l.flags.view_block()     => Cell::Wall,
+		Some(_)                             => Cell::Object,
+	    }
+	});
+    } else {
+	let tileset = &data.tiles_indexed[map.tileset];
+	palette = tileset.palette.clone();
+	pixmap = render_cells(automap, &|x, y| {
+	    let top_tile = (0..map.num_layers).rev().filter_map(|layer| map.tile_at(layer, x, y)).next();
+	    match top_tile.and_then(|t| t.checked_sub(1)).and_then(|t| tileset.tile_icons.get(t)) {
+		Some(icon) => Cell::Color(tileset.palette.nearest(&icon.map_color).0 as u8),
+		None       => Cell::Color(UNEXPLORED_INDEX),
+	    }
+	});
+    }
+    palette.colors.resize(usize::max(palette.len(), 0x20), Color::BLACK);
+    for (index, color) in AUTOMAP_COLORS {
+	palette.colors[index as usize] = color;
+    }
+    return (pixmap, palette);
+}
+
+#[cfg(test)]
+#[test]
+fn test_explored() {
+    let mut automap = Automap::empty(3, 3);
+    automap.set_explored(2, 0, true);
+    automap.set_explored(1, 1, true);
+    automap.set_explored(3, 0, true);
+    assert_eq!(2, automap.num_explored());
+    assert!(automap.is_explored(2, 0));
+    assert!(!automap.is_explored(0, 1));
+    assert!(!automap.is_explored(3, 0));
+    automap.reveal();
+    assert_eq!(9, automap.num_explored());
+}
+
+#[cfg(test)]
+#[test]
+fn test_render_walls() {
+    // 3x1 corridor: wall, floor, object; the wall itself is not drawn
+    let mut automap = Automap::empty(3, 1);
+    automap.reveal();
+    let cells = [Cell::Wall, Cell::Floor, Cell::Object];
+    let pixmap = render_cells(&automap, &|x, _| cells[x]);
+    const C : usize = AUTOMAP_CELL_SIZE;
+    let at = |x, y| pixmap.pixels[x + y * pixmap.width];
+    assert_eq!(UNEXPLORED_INDEX, at(C / 2, C / 2));
+    assert_eq!(WALL_INDEX, at(C, C / 2));             // left edge of the floor, next to the wall
+    assert_eq!(FLOOR_INDEX, at(C + C / 2, C / 2));
+    assert_eq!(WALL_INDEX, at(C + C / 2, 0));         // map border
+    assert_eq!(FLOOR_INDEX, at(2 * C - 1, C / 2));    // no wall between floor and object
+    assert_eq!(WALL_INDEX, at(2 * C + C / 2, C / 2)); // object
+}
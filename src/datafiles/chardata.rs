@@ -11,13 +11,14 @@ use crate::datafiles::{decode, amber_string, map_string_table::MapStringTable, p
 
 use itertools::chain;
 use enumset::{EnumSet, EnumSetType};
+use serde::{Deserialize, Serialize};
 
 use std::{assert, fmt::Display};
 
 use super::attr::{Attr, Usizeable};
 use super::{item::{Item, KeyID}, pixmap::IndexedPixmap, string_fragment_table::StringFragmentTable, attr::Attributed};
 
-#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Percentage {
     pub percentage: usize,
 }
@@ -42,7 +43,7 @@ impl Display for Percentage {
 
 // --------------------------------------------------------------------------------
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Stat {
     SkillAttack,
     SkillParry,
@@ -136,7 +137,7 @@ impl Display for Stat {
 
 // --------------------------------------------------------------------------------
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct PointPool {
     pub current : usize,
     pub max : usize,
@@ -156,7 +157,7 @@ impl Display for PointPool {
 
 // --------------------------------------------------------------------------------
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum InteractionTrigger {
     Ask(u16),
     Show(KeyID),
@@ -182,12 +183,12 @@ impl InteractionTrigger {
 	}
     }
 
-    const ASK : u8	= 0x01;
-    const SHOW : u8	= 0x02;
-    const GIVE : u8	= 0x03;
-    const PAY : u8	= 0x04;
-    const FEED : u8	= 0x05;
-    const JOIN : u8	= 0x06;
+    pub(crate) const ASK : u8	= 0x01;
+    pub(crate) const SHOW : u8	= 0x02;
+    pub(crate) const GIVE : u8	= 0x03;
+    pub(crate) const PAY : u8	= 0x04;
+    pub(crate) const FEED : u8	= 0x05;
+    pub(crate) const JOIN : u8	= 0x06;
 
     pub fn show(&self, fragment_table : &StringFragmentTable) -> String {
 	match self {
@@ -204,7 +205,7 @@ impl InteractionTrigger {
 
 // ----------------------------------------
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Reaction {
     Say(usize), // index into messages[]
     TeachWord(u16), // dictionary word
@@ -241,13 +242,13 @@ impl Reaction {
 	return result;
     }
 
-    const SAY : u8		= 0x01;
-    const TEACH_WORD : u8	= 0x02;
-    const GIVE_ITEM : u8	= 0x03;
-    const GIVE_GOLD : u8	= 0x04;
-    const GIVE_FOOD : u8	= 0x05;
-    const COMPLETE : u8		= 0x06;
-    const MODIFY_STAT : u8	= 0x07;
+    pub(crate) const SAY : u8		= 0x01;
+    pub(crate) const TEACH_WORD : u8	= 0x02;
+    pub(crate) const GIVE_ITEM : u8	= 0x03;
+    pub(crate) const GIVE_GOLD : u8	= 0x04;
+    pub(crate) const GIVE_FOOD : u8	= 0x05;
+    pub(crate) const COMPLETE : u8		= 0x06;
+    pub(crate) const MODIFY_STAT : u8	= 0x07;
 
     pub fn show(&self, fragment_table : &StringFragmentTable, messages : &Vec<String>) -> String {
 	match self {
@@ -265,7 +266,7 @@ impl Reaction {
 }
 
 // ----------------------------------------
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Interaction {
     pub id : usize,
     pub trigger : InteractionTrigger,
@@ -290,7 +291,7 @@ impl Display for MagicSchool {
 
 // --------------------------------------------------------------------------------
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct StatField {
     pub stat : Stat,
     pub current : usize,
@@ -303,13 +304,13 @@ impl StatField {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CharData {
     pub monster : bool,
     pub monster_gfx : usize,
-    pub gender : usize,
-    pub race : usize,
-    pub class : usize,
+    pub gender : usize, // raw number in CHARDATA.AMB, not typed yet: which number is which gender is not verified
+    pub race : Race,
+    pub class : Class,
     pub stats : Vec<StatField>,
     pub level : usize,
     pub magic_schools: EnumSet<MagicSchool>,
@@ -336,6 +337,7 @@ pub struct CharData {
     // 6 right finger
     // 7 feet
     // 8 left finger
+    // Raw bit sets, not typed yet: which bit is which language / condition is not known
     pub languages: usize,
     pub current_language: usize,
     pub physical_conditions: usize,
@@ -346,8 +348,9 @@ pub struct CharData {
     pub resistance: usize,      // min # magic bonus to hit
     pub battle_position: usize,
     pub attacks_per_round: usize,
-    pub monster_flags: usize,   // special monster vulnerabilities
-    pub elemental_flags: usize, // elemental immunities and vulnerabilities
+    pub monster_flag_bits: u8,   // special monster vulnerabilities, see `monster_flags()`
+    pub elemental_immunities: EnumSet<Element>,
+    pub elemental_vulnerabilities: EnumSet<Element>, // take double damage
     pub portrait : Option<IndexedPixmap>,
     pub interactions : Vec<Vec<Interaction>>,  // not completed
     pub messages : Vec<String>,
@@ -372,8 +375,8 @@ impl Attributed for &CharData {
 	    attr::string("name", &self.name),
 	    attr::usize_if_nonzero("istat-flag-id", self.interaction_status_flag),
 	    attr::usize("gender", self.gender),
-	    attr::usize("race", self.race),
-	    attr::usize("class", self.class),
+	    attr::formatted("race", self.race),
+	    attr::formatted("class", self.class),
 	    attr::usize("level", self.level),
 	    attr::uhex_if_nonzero("languages", self.languages),
 	    attr::uhex("language", self.current_language),
@@ -390,6 +393,10 @@ impl Attributed for &CharData {
 	    attr::usize("resistance", self.resistance),
 	    attr::uhex_if_nonzero("cond-physical", self.physical_conditions),
 	    attr::uhex_if_nonzero("cond-mental", self.mental_conditions),
+	    attr::formatted_if(!self.monster_flags().is_empty(), "monster-flags", self.monster_flags()),
+	    attr::uhex_if_nonzero("monster-flags-unknown", (self.monster_flag_bits & !self.monster_flags().as_u8()) as usize),
+	    attr::formatted_if(!self.elemental_immunities.is_empty(), "immune", self.elemental_immunities),
+	    attr::formatted_if(!self.elemental_vulnerabilities.is_empty(), "vulnerable", self.elemental_vulnerabilities),
 	    attr::formatted("morale", self.morale_percentage),
 	    attr::formatted("spellcast", self.spellcast_success_chance),
 	    attr::usize("battle-pos", self.battle_position),
@@ -411,10 +418,14 @@ impl CharData {
 	if self.monster {
 	    self.monster_gfx - 1 + CharData::MONSTER_ICON_OFFSET
 	} else {
-	    self.class
+	    self.class.nr() as usize
 	}
     }
 
+    pub fn monster_flags(&self) -> EnumSet<MonsterFlag> {
+	EnumSet::from_u8_truncated(self.monster_flag_bits)
+    }
+
     // index into the monster_gfx table
     pub fn combat_monster_gfx(&self) -> Option<usize> {
 	if self.monster {
@@ -451,27 +462,48 @@ impl CharData {
 }
 
 
-const RACES: [&str; 15] = [
-    "Human",
-    "Elf",
-    "Dwarf",
-    "Gnome",
-    "Halfling",
-    "Half Elf",
-    "Half Orc",
-    "",
-    "",
-    "",
-    "",
-    "",
-    "",
-    "Animal",
-    "Monster",
-];
+/// Races, in the order of their numbers in CHARDATA.AMB (numbers 7-12 are not used)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Race {
+    Human,
+    Elf,
+    Dwarf,
+    Gnome,
+    Halfling,
+    HalfElf,
+    HalfOrc,
+    Animal,
+    Monster,
+    Unknown(u8),
+}
+
+impl Race {
+    pub fn new(nr : u8) -> Race {
+	match nr {
+	    0  => Race::Human,
+	    1  => Race::Elf,
+	    2  => Race::Dwarf,
+	    3  => Race::Gnome,
+	    4  => Race::Halfling,
+	    5  => Race::HalfElf,
+	    6  => Race::HalfOrc,
+	    13 => Race::Animal,
+	    14 => Race::Monster,
+	    _  => Race::Unknown(nr),
+	}
+    }
+}
 
-const CLASS_NONE: usize = 0;
-const CLASS_ANIMAL: usize = 9;
-const CLASS_MONSTER: usize = 10;
+impl Display for Race {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	match self {
+	    Race::HalfElf    => write!(f, "Half Elf"),
+	    Race::HalfOrc    => write!(f, "Half Orc"),
+	    Race::Unknown(n) => write!(f, "race?{n:x}"),
+	    _                => write!(f, "{:?}", self),
+	}
+    }
+}
 
 const CLASSES: [&str; 11] = [
     "None",
@@ -487,6 +519,90 @@ const CLASSES: [&str; 11] = [
     "Monster",
 ];
 
+/// Character classes, in the order of their numbers in CHARDATA.AMB
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Class {
+    None,
+    Warrior,
+    Paladin,
+    Ranger,
+    Thief,
+    Monk,
+    WhiteMage,
+    GreyMage,
+    BlackMage,
+    Animal,
+    Monster,
+    Unknown(u8),
+}
+
+impl Class {
+    const KNOWN : [Class; 11] = [
+	Class::None,
+	Class::Warrior,
+	Class::Paladin,
+	Class::Ranger,
+	Class::Thief,
+	Class::Monk,
+	Class::WhiteMage,
+	Class::GreyMage,
+	Class::BlackMage,
+	Class::Animal,
+	Class::Monster,
+    ];
+
+    pub fn new(nr : u8) -> Class {
+	Class::KNOWN.get(nr as usize).copied().unwrap_or(Class::Unknown(nr))
+    }
+
+    /// Number in CHARDATA.AMB
+    pub fn nr(&self) -> u8 {
+	match self {
+	    Class::Unknown(nr) => *nr,
+	    _                  => Class::KNOWN.iter().position(|c| c == self).unwrap() as u8,
+	}
+    }
+}
+
+impl Display for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	match self {
+	    Class::Unknown(n) => write!(f, "class?{n:x}"),
+	    _                 => write!(f, "{}", CLASSES[self.nr() as usize]),
+	}
+    }
+}
+
+// --------------------------------------------------------------------------------
+// Flag sets.  Bit n of the flag byte in CHARDATA.AMB is the n-th variant.
+
+#[derive(EnumSetType, Debug)]
+pub enum MonsterFlag {
+    Undead,
+    Demon,
+    /// Immune to physical and mental conditions
+    ImmuneToAilments,
+}
+
+/// Elements for immunities (low nibble of the elemental flags) and vulnerabilities (high nibble)
+#[derive(EnumSetType, Debug)]
+pub enum Element {
+    Fire,
+    Earth,
+    Water,
+    Wind,
+}
+
+macro_rules! display_debug {
+    ($($t:ty),*) => {
+	$(impl Display for $t {
+	    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{:?}", self)
+	    }
+	})*
+    };
+}
+display_debug!(MonsterFlag, Element);
 
 fn print_unknown(data: &[u8], start: usize, len: usize) {
     let mut interesting = false;
@@ -512,13 +628,13 @@ impl CharData {
 	if self.monster {
 	    return "Monster".to_string();
 	}
-	if self.class == CLASS_NONE
-	    || self.class == CLASS_ANIMAL
-	    || self.class == CLASS_MONSTER {
+	if self.class == Class::None
+	    || self.class == Class::Animal
+	    || self.class == Class::Monster {
 
-	    return RACES[self.race].to_string();
+	    return self.race.to_string();
 	}
-	return format!("{} {}", RACES[self.race], CLASSES[self.class]);
+	return format!("{} {}", self.race, self.class);
     }
 
     pub fn new(fragment_table : &StringFragmentTable, npc_id : u16, data : &[u8]) -> Self {
@@ -526,8 +642,11 @@ impl CharData {
 	let monster = data[0x0002] > 0;
 	assert!(data[0x0002] < 2);
 	let gender = data[0x0003] as usize;
-	let race = data[0x0004] as usize;
-	let class = data[0x0005] as usize;
+	let race = Race::new(data[0x0004]);
+	let class = Class::new(data[0x0005]);
+	if let Class::Unknown(nr) = class {
+	    warn!("unknown class {nr:x} in NPC #{npc_id:x}");
+	}
 	let stats = vec![
 	    StatField::new(Stat::SkillAttack,		data[0x0006] as usize, data[0x0010] as usize),
 	    StatField::new(Stat::SkillParry,		data[0x0007] as usize, data[0x0011] as usize),
@@ -583,8 +702,10 @@ impl CharData {
 	let morale_percentage = Percentage::new(data[0x41]); // flee once this % of monsters of same type are defeated
 	let battle_position = data[0x42] as usize; // 0x01 / 0x02 : last row?
 	let attacks_per_round = data[0x43] as usize;
-	let monster_flags = data[0x44] as usize; // 01: undead, 02: demon, 04: immune to ailments
-	let elemental_flags = data[0x45] as usize; // 01 fire, 02 earth, 04 water, 08 wind; lower nibble: immune, upper nibble: vulnerable (dbl damage)
+	let monster_flag_bits = data[0x44]; // 01: undead, 02: demon, 04: immune to ailments
+	// 01 fire, 02 earth, 04 water, 08 wind; lower nibble: immune, upper nibble: vulnerable (dbl damage)
+	let elemental_immunities = EnumSet::from_u8_truncated(data[0x45] & 0x0f);
+	let elemental_vulnerabilities = EnumSet::from_u8_truncated(data[0x45] >> 4);
 	// unknown 003f-0047
 	// unknown attribute at decode(data, 0x005a) / max decode(data, 0x006e)  (always zero)
 	// unknown 0070-0085
@@ -708,11 +829,119 @@ impl CharData {
 	    morale_percentage,
 	    battle_position,
 	    attacks_per_round,
-	    monster_flags,
-	    elemental_flags,
+	    monster_flag_bits,
+	    elemental_immunities,
+	    elemental_vulnerabilities,
 	    portrait,
 	    interactions : interactions_all,
 	    messages,
 	};
     }
 }
+
+/// Builds CHARDATA.AMB entries for tests, with the same layout that `CharData::new` decodes
+#[cfg(test)]
+pub(crate) struct TestCharBuilder {
+    data : Vec<u8>,
+    messages : Vec<String>,
+}
+
+#[cfg(test)]
+impl TestCharBuilder {
+    pub fn new() -> TestCharBuilder {
+	TestCharBuilder { data : vec![0; 0x8d0], messages : vec![] }
+    }
+
+    /// Sets one of the single-byte attributes
+    pub fn byte(mut self, offset : usize, value : u8) -> TestCharBuilder {
+	self.data[offset] = value;
+	return self;
+    }
+
+    /// Puts `count` items with the given key ID and name (fragment) into inventory slot `slot`
+    pub fn item(mut self, slot : usize, count : u8, key_id : u16, name : u16) -> TestCharBuilder {
+	self.data[0x22 + slot] = count;
+	let item_pos = 0x132 + slot * Item::BYTE_SIZE;
+	decode::put_u16(&mut self.data, item_pos + 0x24, key_id);
+	decode::put_u16(&mut self.data, item_pos + 0x26, name);
+	return self;
+    }
+
+    /// Sets interaction `nr` (0-9: before the quest is complete, 10-19: afterwards) to the given
+    /// trigger type and pattern, with (reaction type, details) pairs
+    pub fn interaction(mut self, nr : usize, ty : u8, pattern : u16, reactions : &[(u8, u16)]) -> TestCharBuilder {
+	let base = 0x47a;
+	self.data[base + nr] = ty;
+	decode::put_u16(&mut self.data, base + 20 + nr * 2, pattern);
+	for (r, (reaction, details)) in reactions.iter().enumerate() {
+	    self.data[base + 60 + nr * 5 + r] = *reaction;
+	    decode::put_u16(&mut self.data, base + 360 + nr * 10 + r * 2, *details);
+	}
+	return self;
+    }
+
+    pub fn message(mut self, message : &str) -> TestCharBuilder {
+	self.messages.push(message.to_string());
+	return self;
+    }
+
+    pub fn build(self, fragments : &StringFragmentTable) -> CharData {
+	use super::map_string_table::TextEncoder;
+	let mut data = self.data;
+	if !self.messages.is_empty() {
+	    data.extend(TextEncoder::new(fragments).encode_table(&self.messages).unwrap());
+	}
+	return CharData::new(fragments, 0, &data);
+    }
+}
+
+/// Character with a quest flag (7), key 5 in the first inventory slot, and interactions around two keywords:
+/// asking about `words.0` says "Hello" and teaches `words.1`; giving key 5 pays 100 gold and completes quest 7;
+/// afterwards, asking about `words.0` says "Thank you".  Needs "Hello", "Thank", " " and "you" as fragments.
+#[cfg(test)]
+pub(crate) fn test_char(fragments : &StringFragmentTable, words : (u16, u16)) -> CharData {
+    return TestCharBuilder::new()
+	.byte(0x3d, 7) // interaction status flag
+	.item(0, 1, 5, 0)
+	.interaction(0, InteractionTrigger::ASK, words.0, &[(Reaction::SAY, 0), (Reaction::TEACH_WORD, words.1)])
+	.interaction(1, InteractionTrigger::GIVE, 5, &[(Reaction::GIVE_GOLD, 100), (Reaction::COMPLETE, 7)])
+	.interaction(10, InteractionTrigger::ASK, words.0, &[(Reaction::SAY, 1)])
+	.message("Hello")
+	.message("Thank you")
+	.build(fragments);
+}
+
+#[cfg(test)]
+#[test]
+fn test_serde_roundtrip() {
+    let mut fragments = StringFragmentTable::new(&[]);
+    let sword = fragments.push("Sword").unwrap();
+    let c = TestCharBuilder::new()
+	.byte(0x03, 1) // gender
+	.byte(0x04, 1) // elf
+	.byte(0x05, 6) // white mage
+	.byte(0x1a, 0x02)
+	.byte(0x37, 0x03) // languages
+	.byte(0x3a, 0x02) // physical conditions
+	.byte(0x44, 0xf2) // unnamed monster flag bits must survive the round trip
+	.byte(0x45, 0x21) // immune to fire, vulnerable to earth
+	.item(0, 1, 0, sword)
+	.interaction(0, InteractionTrigger::ASK, sword, &[(Reaction::SAY, 0)])
+	.build(&fragments);
+    assert_eq!((1, Race::Elf, Class::WhiteMage), (c.gender, c.race, c.class));
+    assert_eq!((0x03, 0x02), (c.languages, c.physical_conditions));
+    assert_eq!((EnumSet::only(Element::Fire), EnumSet::only(Element::Earth)), (c.elemental_immunities, c.elemental_vulnerabilities));
+    assert_eq!((EnumSet::only(MonsterFlag::Demon), 0xf2), (c.monster_flags(), c.monster_flag_bits));
+    assert_eq!(Class::Unknown(0x20), Class::new(0x20));
+
+    let json = serde_json::to_string(&c).unwrap();
+    let from_json : CharData = serde_json::from_str(&json).unwrap();
+    assert_eq!(json, serde_json::to_string(&from_json).unwrap());
+    assert_eq!(0xf2, from_json.monster_flag_bits);
+    assert_eq!(EnumSet::only(Element::Earth), from_json.elemental_vulnerabilities);
+    let ron = ron::to_string(&c).unwrap();
+    let from_ron : CharData = ron::from_str(&ron).unwrap();
+    assert_eq!(json, serde_json::to_string(&from_ron).unwrap());
+    assert_eq!("Sword", from_ron.items[0].1.name);
+    assert_eq!(InteractionTrigger::Ask(sword), from_ron.interactions[0][0].trigger);
+}
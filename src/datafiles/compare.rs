@@ -0,0 +1,310 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Structural comparison of two releases (e.g., German and English): everything except text
+// should be identical, and the text should line up one-to-one
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Debug, Display};
+
+use super::AmberstarFiles;
+use super::attr::{self, Attributed};
+use super::chardata::{CharData, Interaction, InteractionTrigger, Reaction};
+use super::map::{EventOp, Map};
+use super::string_fragment_table::StringFragmentTable;
+use super::translation;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    /// Where the difference is, e.g. "map.012.npc.03"
+    pub location : String,
+    pub description : String,
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	write!(f, "{}: {}", self.location, self.description)
+    }
+}
+
+/// The same string in both releases
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AlignedText {
+    pub id : String,
+    pub a : String,
+    pub b : String,
+}
+
+#[derive(Default)]
+pub struct Comparison {
+    /// Everything that differs beyond text
+    pub differences : Vec<Difference>,
+    pub aligned : Vec<AlignedText>,
+}
+
+struct Comparer<'a> {
+    fragments_a : &'a StringFragmentTable,
+    fragments_b : &'a StringFragmentTable,
+    result : Comparison,
+}
+
+impl<'a> Comparer<'a> {
+    fn differ(&mut self, location : &str, description : String) {
+	self.result.differences.push(Difference { location : location.to_string(), description });
+    }
+
+    fn check<T : PartialEq + Debug>(&mut self, location : &str, what : &str, a : T, b : T) {
+	if a != b {
+	    self.differ(location, format!("{what}: {a:?} vs. {b:?}"));
+	}
+    }
+
+    /// Number of entries; returns the number of entries present in both
+    fn check_len(&mut self, location : &str, what : &str, a : usize, b : usize) -> usize {
+	self.check(location, &format!("number of {what}"), a, b);
+	return usize::min(a, b);
+    }
+
+    fn align(&mut self, id : String, a : &str, b : &str) {
+	if a.is_empty() != b.is_empty() {
+	    self.differ(&id, format!("empty in one release only: '{a}' vs. '{b}'"));
+	}
+	self.result.aligned.push(AlignedText { id, a : a.to_string(), b : b.to_string() });
+    }
+
+    /// Dictionary words (string fragments) are numbered differently in each release
+    fn align_word(&mut self, id : String, a : u16, b : u16) {
+	let (a, b) = (self.fragments_a.get(a), self.fragments_b.get(b));
+	self.align(id, &a, &b);
+    }
+
+    /// Attributes of Attributed entities, except for names
+    fn check_attrs(&mut self, location : &str, a : attr::AttrIterator, b : attr::AttrIterator) {
+	let is_text = |path : &String| path == "name" || path.ends_with(".name");
+	let a : BTreeMap<String, String> = attr::flatten(a).into_iter().filter(|(p, _)| !is_text(p)).collect();
+	let b : BTreeMap<String, String> = attr::flatten(b).into_iter().filter(|(p, _)| !is_text(p)).collect();
+	for path in a.keys().chain(b.keys().filter(|k| !a.contains_key(*k))) {
+	    match (a.get(path), b.get(path)) {
+		(Some(va), Some(vb)) if va == vb => {},
+		(va, vb)                         => self.differ(location, format!("{path}: {} vs. {}",
+										 va.map_or("-", |v| v.as_str()),
+										 vb.map_or("-", |v| v.as_str()))),
+	    }
+	}
+    }
+
+    // ----------------------------------------
+    // Maps and events
+
+    fn compare_map(&mut self, nr : usize, a : &Map, b : &Map) {
+	let loc = format!("map.{nr:03}");
+	self.align(format!("{loc}.name"), &a.name, &b.name);
+	self.check(&loc, "size", (a.width, a.height), (b.width, b.height));
+	self.check(&loc, "layers", a.num_layers, b.num_layers);
+	self.check(&loc, "tileset", a.tileset, b.tileset);
+	self.check(&loc, "song", a.song_nr, b.song_nr);
+	self.check(&loc, "flags", a.flags, b.flags);
+	self.check(&loc, "first-person", a.first_person, b.first_person);
+	self.check(&loc, "illumination", a.illumination, b.illumination);
+	self.check(&loc, "environment", a.environment, b.environment);
+	self.check(&loc, "can rest", a.can_rest, b.can_rest);
+	self.check(&loc, "can mapshow", a.can_mapshow, b.can_mapshow);
+	if (a.width, a.height) != (b.width, b.height) {
+	    return;
+	}
+
+	for layer in 0..usize::min(a.num_layers, b.num_layers) {
+	    let differing : Vec<(usize, usize)> = (0..a.height).flat_map(|y| (0..a.width).map(move |x| (x, y)))
+		.filter(|(x, y)| a.tile_at(layer, *x, *y) != b.tile_at(layer, *x, *y))
+		.collect();
+	    if let Some((x, y)) = differing.first() {
+		self.differ(&loc, format!("{} tiles differ in layer {layer}, first at ({x}, {y})", differing.len()));
+	    }
+	}
+	let differing : Vec<(usize, usize)> = (0..a.height).flat_map(|y| (0..a.width).map(move |x| (x, y)))
+	    .filter(|(x, y)| a.hotspot_at(*x, *y) != b.hotspot_at(*x, *y))
+	    .collect();
+	if let Some((x, y)) = differing.first() {
+	    self.differ(&loc, format!("{} hotspots differ, first at ({x}, {y}): {:?} vs. {:?}",
+				      differing.len(), a.hotspot_at(*x, *y), b.hotspot_at(*x, *y)));
+	}
+
+	let num_labrefs = self.check_len(&loc, "lab blocks", a.lab_info.len(), b.lab_info.len());
+	for i in 0..num_labrefs {
+	    let (la, lb) = (&a.lab_info[i], &b.lab_info[i]);
+	    self.check(&format!("{loc}.lab.{i:02}"), "flags / fg / bg / magic",
+		       (la.flags.flags, la.fg_image, la.bg_image, la.magic),
+		       (lb.flags.flags, lb.fg_image, lb.bg_image, lb.magic));
+	}
+
+	let num_npcs = self.check_len(&loc, "NPCs", a.npcs.len(), b.npcs.len());
+	for i in 0..num_npcs {
+	    let (na, nb) = (&a.npcs[i], &b.npcs[i]);
+	    let npc_loc = format!("{loc}.npc.{i:02}");
+	    self.check(&npc_loc, "sprite", na.sprite, nb.sprite);
+	    self.check(&npc_loc, "action", &na.talk_action, &nb.talk_action);
+	    self.check(&npc_loc, "flags", na.flags, nb.flags);
+	    self.check(&npc_loc, "position", na.start_pos, nb.start_pos);
+	    if na.movement != nb.movement {
+		self.differ(&npc_loc, "movement differs".to_string());
+	    }
+	}
+
+	let num_events = self.check_len(&loc, "events", a.event_table.len(), b.event_table.len());
+	for i in 0..num_events {
+	    let (ea, eb) = (&a.event_table[i], &b.event_table[i]);
+	    let event_loc = format!("{loc}.event.{:03}", i + 1);
+	    let (mut raw_a, mut raw_b) = (ea.raw, eb.raw);
+	    for (op_a, op_b) in ea.program().iter().zip(eb.program().iter()) {
+		if let (EventOp::LearnKeyword(ka), EventOp::LearnKeyword(kb)) = (op_a, op_b) {
+		    self.align_word(format!("{event_loc}.keyword"), *ka as u16, *kb as u16);
+		    // The keyword is at raw[6..8] (of 10 bytes)
+		    raw_a[6..8].fill(0);
+		    raw_b[6..8].fill(0);
+		}
+	    }
+	    if raw_a != raw_b {
+		self.differ(&event_loc, format!("{} vs. {}", hex(&ea.raw), hex(&eb.raw)));
+	    }
+	}
+    }
+
+    // ----------------------------------------
+    // Characters and items
+
+    fn compare_char(&mut self, nr : usize, a : &CharData, b : &CharData) {
+	let loc = format!("char.{nr:03}");
+	self.check_attrs(&loc, (&a).attributes(), (&b).attributes());
+	match (&a.portrait, &b.portrait) {
+	    (Some(pa), Some(pb)) => if (pa.width, pa.height, &pa.pixels) != (pb.width, pb.height, &pb.pixels) {
+		self.differ(&loc, "portraits differ".to_string());
+	    },
+	    (pa, pb)             => self.check(&loc, "has portrait", pa.is_some(), pb.is_some()),
+	}
+
+	let num_lists = self.check_len(&loc, "interaction lists", a.interactions.len(), b.interactions.len());
+	for l in 0..num_lists {
+	    let list_loc = format!("{loc}.interactions.{l}");
+	    let num = self.check_len(&list_loc, "interactions", a.interactions[l].len(), b.interactions[l].len());
+	    for i in 0..num {
+		self.compare_interaction(&format!("{list_loc}.{i:02}"), &a.interactions[l][i], &b.interactions[l][i]);
+	    }
+	}
+    }
+
+    fn compare_interaction(&mut self, loc : &str, a : &Interaction, b : &Interaction) {
+	self.check(loc, "id", a.id, b.id);
+	match (a.trigger, b.trigger) {
+	    (InteractionTrigger::Ask(wa), InteractionTrigger::Ask(wb)) => self.align_word(format!("{loc}.ask"), wa, wb),
+	    (ta, tb)                                                   => self.check(loc, "trigger", ta, tb),
+	}
+	let num = self.check_len(loc, "reactions", a.reactions.len(), b.reactions.len());
+	for r in 0..num {
+	    match (a.reactions[r], b.reactions[r]) {
+		(Reaction::TeachWord(wa), Reaction::TeachWord(wb)) => self.align_word(format!("{loc}.teach.{r}"), wa, wb),
+		(ra, rb)                                           => self.check(loc, &format!("reaction {r}"), ra, rb),
+	    }
+	}
+    }
+
+    // ----------------------------------------
+    // Text
+
+    /// Pairs up all text by ID; IDs that exist in only one release indicate structural differences
+    fn compare_text(&mut self, a : &[(String, String)], b : &[(String, String)]) {
+	let b_text : HashMap<&str, &str> = b.iter().map(|(id, s)| (id.as_str(), s.as_str())).collect();
+	let a_ids : HashMap<&str, ()> = a.iter().map(|(id, _)| (id.as_str(), ())).collect();
+	for (id, s) in a {
+	    match b_text.get(id.as_str()) {
+		Some(sb) => self.align(id.clone(), s, sb),
+		None     => self.differ(id, format!("only in the first release: '{s}'")),
+	    }
+	}
+	for (id, s) in b {
+	    if !a_ids.contains_key(id.as_str()) {
+		self.differ(id, format!("only in the second release: '{s}'"));
+	    }
+	}
+    }
+}
+
+fn hex(bytes : &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ")
+}
+
+/// Compares two data sets: everything that is not text must be identical, and all text is aligned by
+/// its stable ID (as in `translation::collect_text`) plus map names and dictionary words
+pub fn compare(a : &AmberstarFiles, b : &AmberstarFiles) -> Comparison {
+    let mut c = Comparer {
+	fragments_a : &a.amberdev.string_fragments,
+	fragments_b : &b.amberdev.string_fragments,
+	result : Comparison::default(),
+    };
+
+    let num_maps = c.check_len("maps", "maps", a.maps.len(), b.maps.len());
+    for i in 0..num_maps {
+	c.compare_map(i, &a.maps[i], &b.maps[i]);
+    }
+
+    let num_chars = c.check_len("chars", "characters", a.chardata.len(), b.chardata.len());
+    for i in 0..num_chars {
+	c.compare_char(i, &a.chardata[i], &b.chardata[i]);
+    }
+
+    let num_songs = c.check_len("songs", "songs", a.songs.len(), b.songs.len());
+    for i in 0..num_songs {
+	let (sa, sb) = (&a.songs[i], &b.songs[i]);
+	if sa.songinfo != sb.songinfo || format!("{sa}") != format!("{sb}") {
+	    c.differ(&format!("song.{i:02}"), format!("song data differs ({} vs. {})", sa.songinfo, sb.songinfo));
+	}
+    }
+
+    c.compare_text(&translation::collect_text(a), &translation::collect_text(b));
+    return c.result;
+}
+
+#[cfg(test)]
+fn test_comparer<'a>(fragments_a : &'a StringFragmentTable, fragments_b : &'a StringFragmentTable) -> Comparer<'a> {
+    Comparer { fragments_a, fragments_b, result : Comparison::default() }
+}
+
+#[cfg(test)]
+#[test]
+fn test_compare_interactions() {
+    let mut fragments_a = StringFragmentTable::new(&[]);
+    let mut fragments_b = StringFragmentTable::new(&[]);
+    let wa = fragments_a.push("SCHWERT").unwrap();
+    fragments_b.push("HELLO").unwrap();
+    let wb = fragments_b.push("SWORD").unwrap();
+    let mut c = test_comparer(&fragments_a, &fragments_b);
+
+    let interaction = |word, reactions| Interaction { id : 1, trigger : InteractionTrigger::Ask(word), reactions };
+    c.compare_interaction("i", &interaction(wa, vec![Reaction::Say(0), Reaction::TeachWord(wa)]),
+			  &interaction(wb, vec![Reaction::Say(0), Reaction::TeachWord(wb)]));
+    assert_eq!(Vec::<Difference>::new(), c.result.differences);
+    assert_eq!(vec![AlignedText { id : "i.ask".to_string(), a : "SCHWERT".to_string(), b : "SWORD".to_string() },
+		    AlignedText { id : "i.teach.1".to_string(), a : "SCHWERT".to_string(), b : "SWORD".to_string() }],
+	       c.result.aligned);
+
+    c.compare_interaction("i", &interaction(wa, vec![Reaction::Say(0)]), &interaction(wb, vec![Reaction::Say(1)]));
+    assert_eq!(1, c.result.differences.len());
+    assert_eq!("i", c.result.differences[0].location);
+}
+
+#[cfg(test)]
+#[test]
+fn test_compare_text() {
+    let fragments = StringFragmentTable::new(&[]);
+    let mut c = test_comparer(&fragments, &fragments);
+    let text = |entries : &[(&str, &str)]| entries.iter().map(|(id, s)| (id.to_string(), s.to_string())).collect::<Vec<_>>();
+    c.compare_text(&text(&[("map.000.000", "Hallo"), ("map.000.001", "Tschüss"), ("song.00", "Lied")]),
+		   &text(&[("map.000.000", "Hello"), ("map.000.001", ""), ("code.000.000", "Extra")]));
+    assert_eq!(2, c.result.aligned.len());
+    let locations : Vec<&str> = c.result.differences.iter().map(|d| d.location.as_str()).collect();
+    assert_eq!(vec!["map.000.001", "song.00", "code.000.000"], locations);
+}
@@ -0,0 +1,255 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Conversations with characters: interactions triggered by the party, and their effects on the party
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+use super::chardata::{CharData, Interaction, InteractionTrigger, Reaction};
+use super::item::Item;
+use super::string_fragment_table::StringFragmentTable;
+
+/// What the party has gained in conversations so far
+#[derive(Clone, Default)]
+pub struct PartyState {
+    /// Known dictionary words, by text: the string fragment table has several fragments with the same text,
+    /// and all of them count as the same word
+    pub keywords : BTreeSet<String>,
+    pub items : Vec<Item>,
+    pub gold : usize,
+    pub food : usize,
+    pub xp : usize,
+    /// Interaction status flags set by `Reaction::CompleteQuest`
+    pub flags : BTreeSet<usize>,
+    /// (amount, stat, modification type), as in `Reaction::ModifyStat`
+    pub stat_changes : Vec<(usize, usize, usize)>,
+    /// Characters that have joined the party
+    pub members : Vec<usize>,
+}
+
+impl PartyState {
+    fn item_with_key(&self, key : usize) -> Option<usize> {
+	self.items.iter().position(|item| item.key_id().id == key)
+    }
+}
+
+/// What the party can do in a conversation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Ask about a dictionary word (by text, cf. `Conversation::word`)
+    Ask(String),
+    /// Show an item with the given key ID
+    Show(usize),
+    /// Give away an item with the given key ID
+    Give(usize),
+    /// Pay gold
+    Pay(usize),
+    /// Hand over food rations
+    Feed(usize),
+    /// Ask the character to join the party.  We do not roll against the character's join chance, to keep
+    /// conversations reproducible: any character with a nonzero chance joins.
+    AskJoin,
+}
+
+/// What happened in response to an action
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    Say(String),
+    LearnWord(String),
+    ReceiveItem(String),
+    ReceiveGold(usize),
+    ReceiveFood(usize),
+    ReceiveXP(usize),
+    CompleteQuest(usize),
+    ModifyStat(usize, usize, usize),
+    Joined,
+    /// Nothing in the active interaction set matched
+    NoReaction,
+    Unknown(Reaction),
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Response::Say(s)                    => write!(f, "\"{s}\""),
+	    Response::LearnWord(w)              => write!(f, "[learned '{w}']"),
+	    Response::ReceiveItem(name)         => write!(f, "[received {name}]"),
+	    Response::ReceiveGold(gp)           => write!(f, "[received {gp} gold]"),
+	    Response::ReceiveFood(n)            => write!(f, "[received {n} rations]"),
+	    Response::ReceiveXP(xp)             => write!(f, "[received {xp} XP]"),
+	    Response::CompleteQuest(flag)       => write!(f, "[quest flag {flag:x} set]"),
+	    Response::ModifyStat(n, stat, t)    => write!(f, "[stat {stat:04x} modified by {n} (type {t})]"),
+	    Response::Joined                    => write!(f, "[joined the party]"),
+	    Response::NoReaction                => write!(f, "[no reaction]"),
+	    Response::Unknown(r)                => write!(f, "[unknown reaction {r:?}]"),
+	}
+    }
+}
+
+/// A conversation between the party and one character.  The character uses its "complete" interaction set once
+/// its `interaction_status_flag` has been set in the party state.
+pub struct Conversation<'a> {
+    pub char_nr : usize,
+    pub character : &'a CharData,
+    fragments : &'a StringFragmentTable,
+    pub party : PartyState,
+}
+
+impl<'a> Conversation<'a> {
+    pub fn new(fragments : &'a StringFragmentTable, char_nr : usize, character : &'a CharData, party : PartyState) -> Conversation<'a> {
+	Conversation { char_nr, character, fragments, party }
+    }
+
+    /// Has the character's quest been completed?
+    pub fn is_complete(&self) -> bool {
+	let flag = self.character.interaction_status_flag;
+	flag != 0 && self.party.flags.contains(&flag)
+    }
+
+    pub fn active_interactions(&self) -> &'a [Interaction] {
+	let set = if self.is_complete() { 1 } else { 0 };
+	self.character.interactions.get(set).map_or(&[], |v| &v[..])
+    }
+
+    /// Words that the character reacts to in the active interaction set
+    pub fn topics(&self) -> Vec<String> {
+	self.active_interactions().iter().filter_map(|i| match i.trigger {
+	    InteractionTrigger::Ask(w) => Some(self.fragments.get(w)),
+	    _                          => None,
+	}).collect()
+    }
+
+    /// Dictionary word for the given text, if it exists (ignoring case)
+    pub fn word(&self, text : &str) -> Option<String> {
+	let index = self.fragments.index_of(text).or_else(|| self.fragments.index_of(&text.to_uppercase()))?;
+	return Some(self.fragments.get(index));
+    }
+
+    fn matches(&self, trigger : &InteractionTrigger, action : &Action) -> bool {
+	match (trigger, action) {
+	    (InteractionTrigger::Ask(w), Action::Ask(a))         => self.fragments.get_str(*w) == a,
+	    (InteractionTrigger::Show(k), Action::Show(key))     => k.id == *key,
+	    (InteractionTrigger::Give(k), Action::Give(key))     => k.id == *key,
+	    (InteractionTrigger::Pay(gp), Action::Pay(paid))     => paid >= gp,
+	    (InteractionTrigger::Feed(n), Action::Feed(fed))     => fed >= n,
+	    (InteractionTrigger::AskJoin, Action::AskJoin)       => true,
+	    _                                                    => false,
+	}
+    }
+
+    fn apply(&mut self, reaction : &Reaction) -> Response {
+	let c = self.character;
+	match *reaction {
+	    Reaction::Say(m)       => Response::Say(c.messages.get(m).cloned().unwrap_or_else(|| format!("<invalid message {m}>"))),
+	    Reaction::TeachWord(w) => {
+		let word = self.fragments.get(w);
+		self.party.keywords.insert(word.clone());
+		Response::LearnWord(word)
+	    },
+	    Reaction::GiveItem(i)  => match c.items.get(i) {
+		Some((_, item)) => {
+		    self.party.items.push(item.clone());
+		    Response::ReceiveItem(item.name.clone())
+		},
+		None            => Response::Unknown(*reaction),
+	    },
+	    Reaction::GiveGold(gp) => { self.party.gold += gp; Response::ReceiveGold(gp) },
+	    Reaction::GiveFood(n)  => { self.party.food += n; Response::ReceiveFood(n) },
+	    Reaction::GiveXP(xp)   => { self.party.xp += xp; Response::ReceiveXP(xp) },
+	    Reaction::CompleteQuest(flag) => {
+		self.party.flags.insert(flag);
+		Response::CompleteQuest(flag)
+	    },
+	    Reaction::ModifyStat(n, stat, t) => {
+		self.party.stat_changes.push((n, stat, t));
+		Response::ModifyStat(n, stat, t)
+	    },
+	    Reaction::Unknown(..)  => Response::Unknown(*reaction),
+	}
+    }
+
+    /// Performs the action: checks that the party can do it, pays for it, and applies the reactions of all
+    /// matching interactions in the active set
+    pub fn act(&mut self, action : Action) -> Result<Vec<Response>, String> {
+	match action {
+	    Action::Ask(ref w) if !self.party.keywords.contains(w)
+		=> return Err(format!("The party does not know the word '{w}'")),
+	    Action::Show(key) | Action::Give(key) if self.party.item_with_key(key).is_none()
+		=> return Err(format!("The party has no item with key {key:x}")),
+	    Action::Pay(gp) if gp > self.party.gold
+		=> return Err(format!("The party has only {} gold", self.party.gold)),
+	    Action::Feed(n) if n > self.party.food
+		=> return Err(format!("The party has only {} rations", self.party.food)),
+	    _ => {},
+	}
+
+	let interactions = self.active_interactions();
+	let matching : Vec<&Interaction> = interactions.iter().filter(|i| self.matches(&i.trigger, &action)).collect();
+	let mut responses = vec![];
+	if !matching.is_empty() {
+	    match action {
+		Action::Give(key) => { let i = self.party.item_with_key(key).unwrap(); self.party.items.remove(i); },
+		Action::Pay(gp)   => self.party.gold -= gp,
+		Action::Feed(n)   => self.party.food -= n,
+		_                 => {},
+	    }
+	}
+	for interaction in matching {
+	    for reaction in &interaction.reactions {
+		responses.push(self.apply(reaction));
+	    }
+	}
+	if action == Action::AskJoin && self.character.join_chance.percentage > 0 && !self.party.members.contains(&self.char_nr) {
+	    self.party.members.push(self.char_nr);
+	    responses.push(Response::Joined);
+	}
+	if responses.is_empty() {
+	    responses.push(Response::NoReaction);
+	}
+	return Ok(responses);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_conversation() {
+    use super::chardata;
+    let mut fragments = StringFragmentTable::new(&[]);
+    for f in ["Hello", "Thank", " ", "you"] {
+	fragments.push(f).unwrap();
+    }
+    fragments.push("SWORD").unwrap();
+    // The character uses a second fragment with the same text
+    let sword = fragments.push("SWORD").unwrap();
+    let king = fragments.push("KING").unwrap();
+    let character = chardata::test_char(&fragments, (sword, king));
+    let mut party = PartyState::default();
+    party.items.push(character.items[0].1.clone());
+    let mut conversation = Conversation::new(&fragments, 0, &character, party);
+    let sword = "SWORD".to_string();
+
+    assert_eq!(Some(sword.clone()), conversation.word("sword"));
+    assert!(conversation.act(Action::Ask(sword.clone())).is_err());
+    conversation.party.keywords.insert(sword.clone());
+    assert_eq!(vec!["SWORD"], conversation.topics());
+    assert_eq!(Ok(vec![Response::Say("Hello".to_string()), Response::LearnWord("KING".to_string())]),
+	       conversation.act(Action::Ask(sword.clone())));
+    assert!(conversation.party.keywords.contains("KING"));
+    assert_eq!(Ok(vec![Response::NoReaction]), conversation.act(Action::Ask("KING".to_string())));
+
+    assert!(conversation.act(Action::Give(6)).is_err());
+    assert_eq!(Ok(vec![Response::ReceiveGold(100), Response::CompleteQuest(7)]), conversation.act(Action::Give(5)));
+    assert!(conversation.party.items.is_empty());
+    assert_eq!(100, conversation.party.gold);
+
+    // Now using the "complete" set
+    assert!(conversation.is_complete());
+    assert_eq!(Ok(vec![Response::Say("Thank you".to_string())]), conversation.act(Action::Ask(sword)));
+    assert_eq!(Ok(vec![Response::NoReaction]), conversation.act(Action::AskJoin));
+}
@@ -0,0 +1,123 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Time-of-day lighting: sky gradients for a given in-game time.
+// How (and whether) the game darkens maps at night is not known yet, so map palettes are left alone.
+
+use super::map::Illumination;
+use super::palette::{Color, DaylightGradientPalettes, Palette};
+
+pub const HOURS_PER_DAY : usize = 24;
+pub const MINUTES_PER_HOUR : usize = 60;
+
+/// In-game clock time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeOfDay {
+    pub hour : usize,
+    pub minute : usize,
+}
+
+impl TimeOfDay {
+    pub fn new(hour : usize, minute : usize) -> TimeOfDay {
+	TimeOfDay { hour : hour % HOURS_PER_DAY, minute : minute % MINUTES_PER_HOUR }
+    }
+
+    pub fn from_minutes(minutes : usize) -> TimeOfDay {
+	let minutes = minutes % (HOURS_PER_DAY * MINUTES_PER_HOUR);
+	TimeOfDay::new(minutes / MINUTES_PER_HOUR, minutes % MINUTES_PER_HOUR)
+    }
+
+    pub fn minutes(&self) -> usize {
+	self.hour * MINUTES_PER_HOUR + self.minute
+    }
+
+    /// Daytime floor and ceiling images apply
+    pub fn is_daytime(&self) -> bool {
+	self.hour >= 7 && self.hour < 19
+    }
+
+    /// How far we are into the current hour (0..=255)
+    fn partial_into_hour(&self) -> u8 {
+	((self.minute * 256) / MINUTES_PER_HOUR) as u8
+    }
+}
+
+/// Lighting over the course of an in-game day
+pub struct DayCycle<'a> {
+    gradients : &'a DaylightGradientPalettes,
+}
+
+impl<'a> DayCycle<'a> {
+    pub fn new(gradients : &'a DaylightGradientPalettes) -> DayCycle<'a> {
+	DayCycle {
+	    gradients,
+	}
+    }
+
+    /// Sky / background gradient for the time of day, one colour per row.
+    /// Maps that are always lit get the day gradient, maps that are never lit the night gradient.
+    pub fn sky_gradient(&self, illumination : Illumination, time : TimeOfDay) -> Palette {
+	let gradients = self.gradients;
+	match illumination {
+	    Illumination::Always   => return gradients.day.clone(),
+	    Illumination::Never    => return gradients.night.clone(),
+	    Illumination::Daylight => {},
+	}
+	let mut bg_gradient = Palette::fill(&Color::BLACK, gradients.day.len());
+
+	let partial_into_hour = time.partial_into_hour();
+	let partial_outof_hour : u8 = 255 - partial_into_hour;
+	let hour = time.hour;
+
+	// day or night
+	if hour >= 8 && hour <= 17 {
+	    // day
+	    bg_gradient.copy_into(&gradients.day);
+	} else if hour == 7 {
+	    // late dawn
+	    bg_gradient.blend_into(&gradients.day, partial_into_hour);
+	} else if hour == 18 {
+	    // early dusk
+	    bg_gradient.blend_into(&gradients.day, partial_outof_hour);
+	} else if hour < 6 || hour >= 20 {
+	    // night
+	    bg_gradient.copy_into(&gradients.night);
+	} else if hour == 6 {
+	    // early dawn
+	    bg_gradient.blend_into(&gradients.night, partial_outof_hour);
+	} else if hour == 19 {
+	    // late dusk
+	    bg_gradient.blend_into(&gradients.night, partial_into_hour);
+	}
+
+	// twilight
+	match hour {
+	    6 | 18 => bg_gradient.blend_into(&gradients.twilight, partial_into_hour),
+	    7 | 19 => bg_gradient.blend_into(&gradients.twilight, partial_outof_hour),
+	    _      => {},
+	};
+	return bg_gradient;
+    }
+}
+
+#[cfg(test)]
+fn test_gradients() -> DaylightGradientPalettes {
+    DaylightGradientPalettes {
+	day : Palette::fill(&Color::rgb(0x80, 0x80, 0xff), 4),
+	night : Palette::fill(&Color::rgb(0, 0, 0x20), 4),
+	twilight : Palette::fill(&Color::rgb(0x40, 0, 0), 4),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_sky_gradient() {
+    let gradients = test_gradients();
+    let cycle = DayCycle::new(&gradients);
+    assert_eq!(gradients.day.colors, cycle.sky_gradient(Illumination::Daylight, TimeOfDay::new(12, 0)).colors);
+    assert_eq!(gradients.night.colors, cycle.sky_gradient(Illumination::Daylight, TimeOfDay::new(23, 30)).colors);
+    assert_eq!(gradients.day.colors, cycle.sky_gradient(Illumination::Always, TimeOfDay::new(23, 30)).colors);
+    assert_eq!(gradients.night.colors, cycle.sky_gradient(Illumination::Never, TimeOfDay::new(12, 0)).colors);
+    // Half past six: half of the night plus half of the twilight
+    assert_eq!(Color::rgb(0x20, 0, 0x10), cycle.sky_gradient(Illumination::Daylight, TimeOfDay::new(6, 30)).get(0));
+}
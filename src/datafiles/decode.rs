@@ -12,3 +12,23 @@ pub fn u32(vec : &[u8], offset : usize) -> u32 {
     let lo = u16(vec, offset + 2) as u32;
     return hi << 16 | lo;
 }
+
+pub fn put_u16(vec : &mut [u8], offset : usize, v : u16) {
+    vec[offset] = (v >> 8) as u8;
+    vec[offset + 1] = v as u8;
+}
+
+pub fn put_u32(vec : &mut [u8], offset : usize, v : u32) {
+    put_u16(vec, offset, (v >> 16) as u16);
+    put_u16(vec, offset + 2, v as u16);
+}
+
+pub fn push_u16(vec : &mut Vec<u8>, v : u16) {
+    vec.push((v >> 8) as u8);
+    vec.push(v as u8);
+}
+
+pub fn push_u32(vec : &mut Vec<u8>, v : u32) {
+    push_u16(vec, (v >> 16) as u16);
+    push_u16(vec, v as u16);
+}
@@ -0,0 +1,371 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Batch export of all graphics as indexed PNGs plus a JSON manifest
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use png_codec::Rgba;
+use serde_json::{json, Value};
+
+use super::{AmberstarFiles, ResourcePath};
+use super::animation::{Animation, AnimationFormat, AnimationOrder};
+use super::labgfx::{LabBlock, LabImage};
+use super::palette::Palette;
+use super::pixmap::IndexedPixmap;
+use super::tile::{Tileset, TileFlags, TileIcon, COLOR_INDEX_FOR_TRANSPARENCY};
+
+/// Width and height of one colour in an exported palette swatch
+const SWATCH_SIZE : usize = 8;
+
+/// Position of one frame within a sprite sheet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameRect {
+    pub x : usize,
+    pub y : usize,
+    pub width : usize,
+    pub height : usize,
+}
+
+impl FrameRect {
+    fn to_json(&self) -> Value {
+	json!({ "x" : self.x, "y" : self.y, "width" : self.width, "height" : self.height })
+    }
+}
+
+/// Packs frames into one pixmap, one row of frames per animation.
+/// Returns the sheet and the position of each frame, in the same layout as `rows`.
+pub fn sprite_sheet(rows : &[Vec<&IndexedPixmap>]) -> (IndexedPixmap, Vec<Vec<FrameRect>>) {
+    let width = rows.iter().map(|row| row.iter().map(|p| p.width).sum()).max().unwrap_or(0);
+    let height = rows.iter().map(|row| row.iter().map(|p| p.height).max().unwrap_or(0)).sum();
+    let mut sheet = IndexedPixmap::empty(width, height);
+    let mut rects = vec![];
+    let mut y = 0;
+    for row in rows {
+	let mut x = 0;
+	let mut row_rects = vec![];
+	for pixmap in row {
+	    sheet.blit_into(pixmap, x, y);
+	    row_rects.push(FrameRect { x, y, width : pixmap.width, height : pixmap.height });
+	    x += pixmap.width;
+	}
+	y += row.iter().map(|p| p.height).max().unwrap_or(0);
+	rects.push(row_rects);
+    }
+    return (sheet, rects);
+}
+
+/// Writes the pixmap as indexed PNG
+pub fn write_png(path : &Path, pixmap : &IndexedPixmap, palette : &Palette) -> io::Result<()> {
+    let palette : Vec<Rgba> = palette.colors.iter().map(|c| Rgba::new(c.r, c.g, c.b, c.a)).collect();
+    let png = png_codec::IndexedImage {
+	height: pixmap.height as u32,
+	width: pixmap.width as u32,
+	pixels: &pixmap.pixels,
+	palette: &palette,
+    };
+    let encoded = png.encode(5).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{e:?}")))?;
+    return fs::write(path, &encoded);
+}
+
+/// Palettes that have a colour for every colour index used in the pixmaps
+fn candidate_palettes<'a>(palettes : &'a [(ResourcePath, Palette)], pixmaps : &[&IndexedPixmap]) -> Vec<&'a (ResourcePath, Palette)> {
+    let max_index = pixmaps.iter().flat_map(|p| p.pixels.iter()).max().map(|i| *i as usize).unwrap_or(0);
+    palettes.iter().filter(|(_, p)| p.len() > max_index).collect()
+}
+
+/// The default palette, if known, otherwise all candidate palettes
+fn palettes_for<'a>(palettes : &'a [(ResourcePath, Palette)], default_palette : &ResourcePath, pixmaps : &[&IndexedPixmap]) -> Vec<&'a (ResourcePath, Palette)> {
+    match palettes.iter().find(|(name, _)| name == default_palette) {
+	Some(p) => vec![p],
+	None    => candidate_palettes(palettes, pixmaps),
+    }
+}
+
+fn file_name(name : &str, palette : &ResourcePath, multiple_palettes : bool) -> String {
+    if multiple_palettes {
+	format!("{name}@{palette}.png")
+    } else {
+	format!("{name}.png")
+    }
+}
+
+fn source_json(file : &str, index : usize) -> Value {
+    json!({ "file" : file, "index" : index })
+}
+
+fn picture_source_json(file : &str, index : usize, picture : Option<usize>) -> Value {
+    let mut source = source_json(file, index);
+    if let Some(picture) = picture {
+	source["picture"] = json!(picture);
+    }
+    return source;
+}
+
+fn flags_json(flags : &TileFlags) -> Value {
+    json!({
+	"raw" : flags.flags,
+	"anim_back_and_forth" : flags.anim_back_and_forth(),
+	"anim_random_start" : flags.anim_random_start(),
+	"draw_with_transparency" : flags.draw_with_transparency(),
+	"view_block" : flags.view_block(),
+	"illusion" : flags.illusion(),
+    })
+}
+
+fn export_palettes(dir : &Path, palettes : &[(ResourcePath, Palette)]) -> io::Result<Vec<Value>> {
+    let mut entries = vec![];
+    for (name, palette) in palettes {
+	let mut swatch = IndexedPixmap::empty(palette.len() * SWATCH_SIZE, SWATCH_SIZE);
+	for (i, pixel) in swatch.pixels.iter_mut().enumerate() {
+	    *pixel = ((i % swatch.width) / SWATCH_SIZE) as u8;
+	}
+	let file = format!("palettes/{name}.png");
+	write_png(&dir.join(&file), &swatch, palette)?;
+	let colors : Vec<String> = palette.colors.iter().map(|c| format!("#{:02x}{:02x}{:02x}{:02x}", c.r, c.g, c.b, c.a)).collect();
+	entries.push(json!({
+	    "name" : format!("{name}"),
+	    "file" : file,
+	    "colors" : colors,
+	}));
+    }
+    return Ok(entries);
+}
+
+fn export_pixmaps(data : &AmberstarFiles, dir : &Path, palettes : &[(ResourcePath, Palette)]) -> io::Result<Vec<Value>> {
+    let pixmaps = data.pixmaps();
+    let mut names : Vec<&ResourcePath> = pixmaps.keys().collect();
+    names.sort();
+    let mut entries = vec![];
+    for name in names {
+	let (default_palette, pixmap) = &pixmaps[name];
+	let selected = palettes_for(palettes, default_palette, &[pixmap]);
+	if selected.is_empty() {
+	    pwarn!("No suitable palette for pixmap {name}, skipping");
+	}
+	for (palette_name, palette) in selected.iter().map(|p| (&p.0, &p.1)) {
+	    let file = format!("pixmaps/{}", file_name(&format!("{name}"), palette_name, selected.len() > 1));
+	    write_png(&dir.join(&file), pixmap, palette)?;
+	    let rect = FrameRect { x : 0, y : 0, width : pixmap.width, height : pixmap.height };
+	    entries.push(json!({
+		"name" : format!("{name}"),
+		"file" : file,
+		"palette" : format!("{palette_name}"),
+		"source" : AmberstarFiles::pixmap_source(name).map(|(f, i, p)| picture_source_json(f, i, p)),
+		"frames" : [rect.to_json()],
+	    }));
+	}
+    }
+    return Ok(entries);
+}
+
+/// Palette that the tile is drawn with (cf. `Tileset::with_palette`)
+fn tile_palette(tileset : &Tileset<IndexedPixmap>, icon : &TileIcon<IndexedPixmap>) -> Palette {
+    if icon.flags.draw_with_transparency() {
+	return tileset.palette.with_transparency(COLOR_INDEX_FOR_TRANSPARENCY);
+    }
+    return tileset.palette.clone();
+}
+
+/// Writes opaque tiles and tiles with DRAW_WITH_TRANSPARENCY into separate sprite sheets, since they
+/// need different palettes
+fn export_tileset(dir : &Path, nr : usize, tileset : &Tileset<IndexedPixmap>) -> io::Result<Value> {
+    let mut tiles : Vec<(usize, Value)> = vec![];
+    for (transparent, suffix) in [(false, ""), (true, ".transparent")] {
+	let icons : Vec<(usize, &TileIcon<IndexedPixmap>)> = tileset.tile_icons.iter().enumerate()
+	    .filter(|(_, icon)| icon.flags.draw_with_transparency() == transparent)
+	    .collect();
+	let Some((_, first_icon)) = icons.first() else {
+	    continue;
+	};
+	let rows : Vec<Vec<&IndexedPixmap>> = icons.iter().map(|(_, t)| t.frames.iter().collect()).collect();
+	let (sheet, rects) = sprite_sheet(&rows);
+	let file = format!("tiles/tileset.{nr:02x}{suffix}.png");
+	write_png(&dir.join(&file), &sheet, &tile_palette(tileset, first_icon))?;
+
+	tiles.extend(icons.iter().zip(rects.iter()).map(|((i, icon), frames)| (*i, json!({
+	    // Maps refer to tiles starting from 1; 0 is "no tile"
+	    "tile" : i + 1,
+	    "file" : file,
+	    "flags" : flags_json(&icon.flags),
+	    "map_color" : format!("#{:02x}{:02x}{:02x}", icon.map_color.r, icon.map_color.g, icon.map_color.b),
+	    "frames" : frames.iter().map(|r| r.to_json()).collect::<Vec<Value>>(),
+	}))));
+    }
+    tiles.sort_by_key(|(i, _)| *i);
+    let tiles : Vec<Value> = tiles.into_iter().map(|(_, tile)| tile).collect();
+    return Ok(json!({
+	"source" : source_json("ICON_DAT.AMB", nr),
+	"player_icon_index" : tileset.player_icon_index,
+	"transparent_color_index" : COLOR_INDEX_FOR_TRANSPARENCY,
+	"tiles" : tiles,
+    }));
+}
+
+/// All frames of one perspective: the animation base image (if any) first, then the animation frames
+fn lab_image_frames(image : &LabImage<IndexedPixmap>) -> Vec<(bool, usize, usize, &IndexedPixmap)> {
+    image.base_pixmap.iter().map(|p| (true, p))
+	.chain(image.pixmaps.iter().map(|p| (false, p)))
+	.map(|(base, p)| (base, p.xoffset, p.yoffset, &p.pixmap))
+	.collect()
+}
+
+fn export_labblock(data : &AmberstarFiles, dir : &Path, palettes : &[(ResourcePath, Palette)], labblock : &LabBlock<IndexedPixmap>) -> io::Result<Vec<Value>> {
+    let frames : Vec<_> = labblock.perspectives.iter().map(|i| lab_image_frames(i)).collect();
+    let rows : Vec<Vec<&IndexedPixmap>> = frames.iter().map(|row| row.iter().map(|f| f.3).collect()).collect();
+    let (sheet, rects) = sprite_sheet(&rows);
+
+    let perspectives : Vec<Value> = frames.iter().zip(rects.iter()).map(|(row, row_rects)| {
+	let row_json : Vec<Value> = row.iter().zip(row_rects.iter()).map(|((base, xoffset, yoffset, _), rect)| {
+	    let mut v = rect.to_json();
+	    v["xoffset"] = json!(xoffset);
+	    v["yoffset"] = json!(yoffset);
+	    v["base"] = json!(base);
+	    v
+	}).collect();
+	json!({ "frames" : row_json })
+    }).collect();
+
+    let default_palette = match data.labgfx.default_palette_index(labblock.id) {
+	Some(i) => ResourcePath::new(&["lab", &format!("{i:02x}")]),
+	None    => ResourcePath::empty(),
+    };
+    let selected = palettes_for(palettes, &default_palette, &[&sheet]);
+    let mut entries = vec![];
+    for (palette_name, palette) in selected.iter().map(|p| (&p.0, &p.1)) {
+	let file = format!("labblocks/{}", file_name(&format!("labblock.{:02x}", labblock.id), palette_name, selected.len() > 1));
+	write_png(&dir.join(&file), &sheet, palette)?;
+	entries.push(json!({
+	    "file" : file,
+	    "palette" : format!("{palette_name}"),
+	    "source" : source_json("LABBLOCK.AMB", labblock.id),
+	    "block_type" : format!("{:?}", labblock.block_type),
+	    "num_frames_distant" : labblock.num_frames_distant,
+	    "perspectives" : perspectives,
+	}));
+    }
+    return Ok(entries);
+}
+
+/// Writes all palettes, pixmaps, tilesets, and lab blocks as PNG files into `dir`, plus `manifest.json`
+/// describing where each frame is and where it came from.
+/// Pixmaps without default palette are written once for every palette that covers all of their colours.
+pub fn export_all(data : &AmberstarFiles, dir : &Path) -> io::Result<()> {
+    for subdir in ["palettes", "pixmaps", "tiles", "labblocks"] {
+	fs::create_dir_all(dir.join(subdir))?;
+    }
+    let palettes_map = data.palettes();
+    let mut palettes : Vec<(ResourcePath, Palette)> = palettes_map.into_iter().collect();
+    palettes.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let palette_entries = export_palettes(dir, &palettes)?;
+    let pixmap_entries = export_pixmaps(data, dir, &palettes)?;
+    let mut tileset_entries = vec![];
+    for (nr, tileset) in data.tiles_indexed.iter().enumerate() {
+	tileset_entries.push(export_tileset(dir, nr, tileset)?);
+    }
+    let mut labblock_entries = vec![];
+    for labblock in data.labgfx.labblocks.iter() {
+	labblock_entries.append(&mut export_labblock(data, dir, &palettes, labblock)?);
+    }
+
+    let manifest = json!({
+	"palettes" : palette_entries,
+	"pixmaps" : pixmap_entries,
+	"tilesets" : tileset_entries,
+	"labblocks" : labblock_entries,
+    });
+    let manifest_str = serde_json::to_string_pretty(&manifest).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    return fs::write(dir.join("manifest.json"), manifest_str);
+}
+
+/// Animation orders of all LabRefs in lab maps that show the given LabBlock (cf. `LabViewRenderer`)
+fn labblock_anim_orders(data : &AmberstarFiles, labblock_nr : usize) -> Vec<AnimationOrder> {
+    let mut orders = vec![];
+    for map in data.maps.iter().filter(|m| m.first_person) {
+	let Some(labdata) = data.labgfx.labdata.get(map.tileset) else {
+	    continue;
+	};
+	for labref in map.lab_info.iter() {
+	    for img in [labref.bg_image, labref.fg_image] {
+		if img.checked_sub(1).and_then(|i| labdata.labblocks.get(i)) != Some(&labblock_nr) {
+		    continue;
+		}
+		let order = if labref.flags.anim_back_and_forth() { AnimationOrder::BackAndForth } else { AnimationOrder::Loop };
+		if !orders.contains(&order) {
+		    orders.push(order);
+		}
+	    }
+	}
+    }
+    return orders;
+}
+
+/// Writes every animated tile and LabBlock perspective as animated GIF or APNG into `dir`.
+/// LabBlocks use the palette of the first lab that references them, or the first lab palette otherwise,
+/// and are written once for every animation order that the lab maps use for them.
+pub fn export_animations(data : &AmberstarFiles, dir : &Path, format : AnimationFormat) -> io::Result<()> {
+    let ext = format.extension();
+    fs::create_dir_all(dir.join("tiles"))?;
+    for (nr, tileset) in data.tiles_indexed.iter().enumerate() {
+	for (i, icon) in tileset.tile_icons.iter().enumerate() {
+	    if icon.frames.len() > 1 {
+		let file = dir.join(format!("tiles/tileset.{nr:02x}.tile.{:03x}.{ext}", i + 1));
+		Animation::from_tile(icon).write(&file, &tile_palette(tileset, icon), format)?;
+	    }
+	}
+    }
+
+    fs::create_dir_all(dir.join("labblocks"))?;
+    for labblock in data.labgfx.labblocks.iter() {
+	let palette_index = match data.labgfx.default_palette_index(labblock.id) {
+	    Some(i) => i,
+	    None    => { pwarn!("No lab uses LabBlock {:02x}, falling back to lab palette 0", labblock.id);
+			 0 },
+	};
+	let palette = &data.lab_palettes[palette_index];
+	let mut orders = labblock_anim_orders(data, labblock.id);
+	if orders.is_empty() {
+	    orders.push(AnimationOrder::Loop);
+	}
+	for order in orders.iter() {
+	    let suffix = match (orders.len(), order) {
+		(1, _)                         => "",
+		(_, AnimationOrder::Loop)         => ".loop",
+		(_, AnimationOrder::BackAndForth) => ".back_and_forth",
+	    };
+	    for (p, image) in labblock.perspectives.iter().enumerate() {
+		if image.pixmaps.len() > 1 {
+		    let file = dir.join(format!("labblocks/labblock.{:02x}.perspective.{p:02}{suffix}.{ext}", labblock.id));
+		    Animation::from_lab_image(image, *order).write(&file, palette, format)?;
+		}
+	    }
+	}
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+#[test]
+fn test_sprite_sheet_layout() {
+    let a = IndexedPixmap { width : 2, height : 1, pixels : vec![1, 2] };
+    let b = IndexedPixmap { width : 1, height : 2, pixels : vec![3, 4] };
+    let (sheet, rects) = sprite_sheet(&[vec![&a, &b], vec![&b]]);
+    assert_eq!((3, 4), (sheet.width, sheet.height));
+    assert_eq!(vec![vec![FrameRect { x : 0, y : 0, width : 2, height : 1 },
+			 FrameRect { x : 2, y : 0, width : 1, height : 2 }],
+		    vec![FrameRect { x : 0, y : 2, width : 1, height : 2 }]],
+	       rects);
+    assert_eq!(vec![1, 2, 3,
+		    0, 0, 4,
+		    3, 0, 0,
+		    4, 0, 0],
+	       sheet.pixels);
+}
@@ -6,7 +6,69 @@ use log::{Level, log_enabled, trace, debug, info, warn, error};
 #[allow(unused)]
 use crate::{ptrace, pdebug, pinfo, pwarn, perror};
 
+use super::{DataFile, pixmap::IndexedPixmap, tile::{self, Tileset}};
+
 // ----------------------------------------
-// Icons are animated graphics used for map tiles.
+// Icon files (F_T_ANIM.ICN, TACTIC.ICN, PUZZLE.ICN) hold small user interface graphics:
+// fight/travel animations, combat tactic icons, and puzzle pieces.
+//
+// Not verified: we assume that their entries have the same layout as the tilesets in ICON_DAT.AMB,
+// including their own palette right after the per-icon tables.  Entries that do not fit this layout
+// are reported as errors.
+
+/// Decodes all entries of an icon file as tilesets
+pub fn load_icons(icon_f : &mut DataFile) -> Result<Vec<Tileset<IndexedPixmap>>, String> {
+    let mut tilesets = vec![];
+    for entry_nr in 0..icon_f.num_entries {
+	let tileset = tile::try_new_indexed(&icon_f.decode(entry_nr)).map_err(|e| format!("Entry {entry_nr}: {e}"))?;
+	tilesets.push(tileset);
+    }
+    return Ok(tilesets);
+}
+
+#[cfg(test)]
+#[test]
+fn test_load_icons() {
+    use super::{decode, pixmap};
+    const NUM_ICONS : usize = 250;
+    let a = IndexedPixmap { width : 16, height : 2, pixels : (0..32).map(|i| (i % 13) as u8).collect() };
+    let b = IndexedPixmap { width : 3, height : 1, pixels : vec![1, 2, 3] };
+    // Player icon, #frames, first frame (counting from 1), flags, map colour per icon; icon 0 is animated
+    let mut data = vec![0; 2 + NUM_ICONS * 8];
+    decode::put_u16(&mut data, 0, 7);
+    for i in 0..NUM_ICONS {
+	data[2 + i] = if i == 0 { 2 } else { 1 };
+	decode::put_u16(&mut data, 2 + NUM_ICONS + i * 2, if i == 0 { 1 } else { 2 });
+    }
+    assert_eq!(0x7d2, data.len());
+    decode::push_u16(&mut data, 16);
+    for i in 0..16 {
+	decode::push_u16(&mut data, (i & 7) as u16);
+	decode::push_u16(&mut data, 0);
+    }
+    data.append(&mut pixmap::encode_icon_frame(&a, 4).unwrap());
+    data.append(&mut pixmap::encode_icon_frame(&b, 2).unwrap());
+    let mut file = DataFile::new(data);
+
+    let icons = load_icons(&mut file).unwrap();
+    assert_eq!(1, icons.len());
+    let tileset = &icons[0];
+    assert_eq!(7, tileset.player_icon_index);
+    assert_eq!(NUM_ICONS, tileset.tile_icons.len());
+    assert_eq!(vec![a.pixels.clone(), b.pixels.clone()], tileset.tile_icons[0].frames.iter().map(|f| f.pixels.clone()).collect::<Vec<_>>());
+    assert_eq!(b.pixels, tileset.tile_icons[1].frames[0].pixels);
+    // The file's own palette
+    assert_eq!(16, tileset.palette.len());
+    assert_eq!((3 * (255 / 7), 0), (tileset.palette.get(11).r, tileset.palette.get(11).g));
+}
 
-pub struct
+#[cfg(test)]
+#[test]
+fn test_load_icons_malformed() {
+    // One icon, then the end marker, but no room for the palette
+    let data = vec![0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert!(load_icons(&mut DataFile::new(data)).is_err());
+    // No end marker
+    let data = vec![0, 0, 1, 1, 1];
+    assert!(load_icons(&mut DataFile::new(data)).is_err());
+}
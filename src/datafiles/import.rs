@@ -0,0 +1,166 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Import of edited PNG images back into indexed Amiga graphics
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use std::fs;
+use std::path::Path;
+
+use png::{BitDepth, ColorType, Transformations};
+
+use super::palette::{Color, Palette};
+use super::pixmap::{self, ColorMatch, IndexedPixmap};
+
+/// How to deal with PNG colours that are not in the target palette
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteMatching {
+    /// Fail unless every colour is in the palette
+    Exact,
+    /// Use the nearest palette colour
+    Nearest,
+}
+
+fn decode_err(e : png::DecodingError) -> String {
+    format!("PNG decoding failed: {e}")
+}
+
+/// Colour indices of an indexed PNG, plus its palette
+fn read_indexed(data : &[u8]) -> Result<(usize, usize, Vec<u8>, Vec<Color>), String> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(Transformations::IDENTITY);
+    let mut reader = decoder.read_info().map_err(decode_err)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf).map_err(decode_err)?;
+    let info = reader.info();
+
+    let rgb = info.palette.as_ref().ok_or("Indexed PNG without palette")?;
+    let trns : &[u8] = info.trns.as_deref().unwrap_or(&[]);
+    let colors = rgb.chunks(3).enumerate().map(|(i, c)| Color::rgba(c[0], c[1], c[2], *trns.get(i).unwrap_or(&0xff))).collect();
+
+    let bits = match frame.bit_depth {
+	BitDepth::One   => 1,
+	BitDepth::Two   => 2,
+	BitDepth::Four  => 4,
+	BitDepth::Eight => 8,
+	BitDepth::Sixteen => return Err("16 bit indexed PNG".to_string()),
+    };
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let mut indices = Vec::with_capacity(width * height);
+    for line in buf.chunks(frame.line_size).take(height) {
+	for x in 0..width {
+	    let bitpos = x * bits;
+	    let byte = line[bitpos >> 3];
+	    let shift = 8 - bits - (bitpos & 7);
+	    indices.push((byte >> shift) & ((1 << bits) - 1) as u8);
+	}
+    }
+    return Ok((width, height, indices, colors));
+}
+
+/// Colours of a greyscale or true-colour PNG
+fn read_colors(data : &[u8]) -> Result<(usize, usize, Vec<Color>), String> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(decode_err)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf).map_err(decode_err)?;
+    let (width, height) = (frame.width as usize, frame.height as usize);
+
+    let to_color : fn(&[u8]) -> Color = match frame.color_type {
+	ColorType::Grayscale      => |c| Color::rgb(c[0], c[0], c[0]),
+	ColorType::GrayscaleAlpha => |c| Color::rgba(c[0], c[0], c[0], c[1]),
+	ColorType::Rgb            => |c| Color::rgb(c[0], c[1], c[2]),
+	ColorType::Rgba           => |c| Color::rgba(c[0], c[1], c[2], c[3]),
+	ColorType::Indexed        => return Err("Unexpected indexed PNG".to_string()),
+    };
+    let bytes_per_pixel = frame.color_type.samples();
+    let mut colors = Vec::with_capacity(width * height);
+    for line in buf.chunks(frame.line_size).take(height) {
+	colors.extend(line[..width * bytes_per_pixel].chunks(bytes_per_pixel).map(to_color));
+    }
+    return Ok((width, height, colors));
+}
+
+/// Converts PNG data into an IndexedPixmap for the given palette.
+/// Indexed PNGs keep their colour indices wherever their palette agrees with `palette`, so that
+/// images exported with `export::write_png` round-trip unchanged even if the palette repeats colours.
+pub fn decode_png(data : &[u8], palette : &Palette, matching : PaletteMatching) -> Result<(IndexedPixmap, ColorMatch), String> {
+    let header = png::Decoder::new(data).read_info().map_err(decode_err)?.info().color_type;
+    let (pixmap, color_match) = if header == ColorType::Indexed {
+	let (width, height, indices, png_colors) = read_indexed(data)?;
+	let mut color_match = ColorMatch::default();
+	let mut index_map = vec![];
+	for (i, col) in png_colors.iter().enumerate() {
+	    if i < palette.len() && palette.get(i) == *col {
+		index_map.push((i, 0));
+	    } else {
+		index_map.push(palette.nearest(col));
+	    }
+	}
+	let mut pixels = Vec::with_capacity(indices.len());
+	for index in indices {
+	    let (new_index, dist) = *index_map.get(index as usize).ok_or(format!("Colour index {index} not in PNG palette"))?;
+	    if dist > 0 {
+		color_match.inexact_pixels += 1;
+		color_match.max_distance = u32::max(color_match.max_distance, dist);
+	    }
+	    pixels.push(new_index as u8);
+	}
+	(IndexedPixmap { width, height, pixels }, color_match)
+    } else {
+	let (width, height, colors) = read_colors(data)?;
+	IndexedPixmap::from_colors(width, height, &colors, palette)
+    };
+
+    if matching == PaletteMatching::Exact && !color_match.is_exact() {
+	return Err(format!("{} pixels do not match any palette colour (max squared distance {})",
+			   color_match.inexact_pixels, color_match.max_distance));
+    }
+    return Ok((pixmap, color_match));
+}
+
+/// Loads a PNG file as IndexedPixmap for the given palette; cf. `decode_png`
+pub fn read_png(path : &Path, palette : &Palette, matching : PaletteMatching) -> Result<(IndexedPixmap, ColorMatch), String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    return decode_png(&data, palette, matching);
+}
+
+/// Loads a PNG file and encodes it as Amiga icon frame (cf. `pixmap::new_icon_frame`), by default with as few
+/// bitplanes as possible
+pub fn png_to_icon_frame(path : &Path, palette : &Palette, matching : PaletteMatching, bitplanes : Option<usize>) -> Result<(Vec<u8>, ColorMatch), String> {
+    let (pixmap, color_match) = read_png(path, palette, matching)?;
+    let bitplanes = bitplanes.unwrap_or(pixmap.min_bitplanes().max(1));
+    return Ok((pixmap::encode_icon_frame(&pixmap, bitplanes)?, color_match));
+}
+
+#[cfg(test)]
+#[test]
+fn test_png_roundtrip() {
+    let palette = Palette::test_palette();
+    let pixmap = IndexedPixmap { width : 5, height : 3, pixels : (0..15).collect() };
+    let rgba : Vec<png_codec::Rgba> = palette.colors.iter().map(|c| png_codec::Rgba::new(c.r, c.g, c.b, c.a)).collect();
+    let png = png_codec::IndexedImage {
+	width : 5,
+	height : 3,
+	pixels : &pixmap.pixels,
+	palette : &rgba,
+    }.encode(5).unwrap();
+
+    let (decoded, color_match) = decode_png(&png, &palette, PaletteMatching::Exact).unwrap();
+    assert!(color_match.is_exact());
+    assert_eq!(pixmap.pixels, decoded.pixels);
+    let frame = pixmap::encode_icon_frame(&decoded, 4).unwrap();
+    assert_eq!(pixmap.pixels, pixmap::new_icon_frame(&frame).pixels);
+
+    let mut other = palette.clone();
+    other.colors[3] = Color::rgb(0x10, 0xaa, 0xaa);
+    assert!(decode_png(&png, &other, PaletteMatching::Exact).is_err());
+    let (decoded, color_match) = decode_png(&png, &other, PaletteMatching::Nearest).unwrap();
+    assert_eq!(3, decoded.pixels[3]);
+    assert_eq!(ColorMatch { inexact_pixels : 1, max_distance : 0x10 * 0x10 }, color_match);
+}
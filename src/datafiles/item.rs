@@ -10,10 +10,13 @@ use crate::{ptrace, pdebug, pinfo, pwarn, perror};
 
 use std::fmt::Display;
 
+use serde::{Deserialize, Serialize};
+
 use super::{decode, string_fragment_table::StringFragmentTable, attr::{Attributed, Attr}};
+use super::chardata::CharData;
 
 // --------------------------------------------------------------------------------
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct KeyID {
     pub id : usize,
 }
@@ -35,7 +38,7 @@ impl Display for KeyID {
 }
 
 // --------------------------------------------------------------------------------
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ItemType {
     ty : u8,
 }
@@ -75,7 +78,7 @@ impl Display for ItemType {
 }
 
 // --------------------------------------------------------------------------------
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct EquipSlot {
     slot : usize,
 }
@@ -123,27 +126,29 @@ impl Display for EquipSlot {
 }
 
 // --------------------------------------------------------------------------------
-#[derive(Debug, Clone)]
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item {
-    icon : usize,
-    item_type : ItemType,
-    num_hands : usize,
-    num_fingers : usize,
-    allowed_classes : u32,
-    bonus_shield_defense : isize,
-    bonus_damage : isize,
-    equip_slot : EquipSlot,
-    buy_price : usize,
-    weight : usize,
-    key_id : KeyID,
+    pub icon : usize,
+    pub item_type : ItemType,
+    pub num_hands : usize,
+    pub num_fingers : usize,
+    pub allowed_classes : u16, // bit set; bit order not known yet
+    pub bonus_shield_defense : isize,
+    pub bonus_damage : isize,
+    pub equip_slot : EquipSlot,
+    pub buy_price : usize,
+    pub weight : usize,
+    pub key_id : KeyID,
     pub name : String,
-    unknowns : [u8; 23],
+    /// Bytes whose meaning is not known yet, at `Item::UNKNOWN_OFFSETS`
+    pub unknowns : [u8; 23],
 }
 
 impl Attributed for Item {
     fn attributes(&self) -> attr::AttrIterator {
-	let mut unknowns = self.unknowns.iter().enumerate().filter(|(_i, v)| **v > 0)
-	    .map(|(i, b)| Attr::string(&format!("[{i:02x}]"), format!("0x{b:02x}")));
+	let mut unknowns = self.unknowns.iter().zip(Item::UNKNOWN_OFFSETS).filter(|(v, _)| **v > 0)
+	    .map(|(b, offset)| Attr::string(&format!("[{offset:02x}]"), format!("0x{b:02x}")));
 
 
 	Box::new(chain![
@@ -152,7 +157,7 @@ impl Attributed for Item {
 	    attr::uhex("icon-nr", self.icon),
 	    attr::usize_if_nonzero("num-hands", self.num_hands),
 	    attr::usize_if_nonzero("num-fingers", self.num_fingers),
-	    attr::uhex("allowed-classes", self.allowed_classes),
+	    attr::uhex_if_nonzero("allowed-classes", self.allowed_classes as usize),
 	    attr::isize_if_nonzero("bonus-shield", self.bonus_shield_defense),
 	    attr::isize_if_nonzero("bonus-damage", self.bonus_damage),
 	    attr::formatted_if(self.equip_slot.is_equip_slot(),
@@ -169,13 +174,39 @@ impl Attributed for Item {
 impl Item {
     pub const BYTE_SIZE : usize = 0x28;
 
+    pub const UNKNOWN_OFFSETS : [usize; 23] = [
+	0x02, // ammo use ID?
+	0x03, // gender use?
+	0x06, // LP_max?
+	0x07, // SP_max?
+	0x08, // attribute?
+	0x09, // attribute_normal?
+	0x0a, // skill?
+	0x0b, // skill_normal?
+	0x0c, // spell numer?
+	0x0d, // spell charges?
+	0x13,
+	0x14,
+	0x15,
+	0x16,
+	0x17,
+	0x18,
+	0x19,
+	0x1a,
+	0x1b,
+	0x1c,
+	0x1d,
+	0x1e,
+	0x1f,
+    ];
+
     pub fn new(fragment_table : &StringFragmentTable, data : &[u8]) -> Self {
 	Item {
 	    icon : data[0x00] as usize,
 	    item_type : ItemType::new(data[0x01]),
 	    num_hands : data[0x04] as usize,
 	    num_fingers : data[0x05] as usize,
-	    allowed_classes : decode::u16(data, 0x0e) as u32, // should this be 0x20?
+	    allowed_classes : decode::u16(data, 0x0e),
 	    bonus_shield_defense : data[0x10] as isize,
 	    bonus_damage : data[0x11] as isize,
 	    equip_slot : EquipSlot::new(data[0x12]),
@@ -183,34 +214,15 @@ impl Item {
 	    weight : decode::u16(data, 0x22) as usize,
 	    key_id : KeyID::new(decode::u16(data, 0x24) as usize),
 	    name : fragment_table.get(decode::u16(data, 0x26)),
-	    unknowns : [
-		data[0x02], // ammo use ID?
-		data[0x03], // gender use?
-		data[0x06], // LP_max?
-		data[0x07], // SP_max?
-		data[0x08], // attribute?
-		data[0x09], // attribute_normal?
-		data[0x0a], // skill?
-		data[0x0b], // skill_normal?
-		data[0x0c], // spell numer?
-		data[0x0d], // spell charges?
-		data[0x13],
-		data[0x14],
-		data[0x15],
-		data[0x16],
-		data[0x17],
-		data[0x18],
-		data[0x19],
-		data[0x1a],
-		data[0x1b],
-		data[0x1c],
-		data[0x1d],
-		data[0x1e],
-		data[0x1f],
-	    ],
+	    unknowns : Item::UNKNOWN_OFFSETS.map(|offset| data[offset]),
 	}
     }
 
+    /// Key that this item acts as (e.g., for `InteractionTrigger::Show`); id 0 if none
+    pub fn key_id(&self) -> KeyID {
+	self.key_id
+    }
+
     pub fn show_short(&self) -> String {
 	return format!("{} ({}) : {}",
 		       self.name,
@@ -218,3 +230,47 @@ impl Item {
 		       self.item_type);
     }
 }
+
+// --------------------------------------------------------------------------------
+// The global item table in AMBERDEV.UDO has not been located yet, so we only know the items that
+// characters carry.
+
+/// All distinct items (by name) in character inventories, in the order in which they first occur
+pub fn inventory_items(chardata : &[CharData]) -> Vec<Item> {
+    let mut result : Vec<Item> = vec![];
+    for (_, item) in chardata.iter().flat_map(|c| c.items.iter()).filter(|(count, _)| *count > 0) {
+	if !result.iter().any(|i| i.name == item.name) {
+	    result.push(item.clone());
+	}
+    }
+    return result;
+}
+
+#[cfg(test)]
+fn test_item_bytes(name : u16, price : u16) -> Vec<u8> {
+    let mut data = vec![0; Item::BYTE_SIZE];
+    data[0x01] = ItemType::MELEE_WEAPON;
+    data[0x03] = 1;
+    data[0x04] = 1;
+    decode::put_u16(&mut data, 0x0e, 0b110);
+    data[0x11] = 0xfe;
+    data[0x12] = EquipSlot::MAIN_HAND;
+    data[0x1f] = 0x42;
+    decode::put_u16(&mut data, 0x20, price);
+    decode::put_u16(&mut data, 0x26, name);
+    return data;
+}
+
+#[cfg(test)]
+#[test]
+fn test_decode_item() {
+    let mut fragments = StringFragmentTable::new(&[]);
+    let sword = fragments.push("Sword").unwrap();
+    let item = Item::new(&fragments, &test_item_bytes(sword, 100));
+    assert_eq!("Sword", item.name);
+    assert_eq!(0x0006, item.allowed_classes);
+    assert_eq!(254, item.bonus_damage);
+    assert_eq!(100, item.buy_price);
+    assert_eq!(1, item.unknowns[1]);
+    assert_eq!(0x42, item.unknowns[22]);
+}
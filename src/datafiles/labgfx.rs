@@ -7,6 +7,7 @@ use log::{Level, log_enabled, trace, debug, info, warn, error};
 use crate::{ptrace, pdebug, pinfo, pwarn, perror};
 
 use super::{DataFile, pixmap::{IndexedPixmap, Pixmap}, palette::Palette};
+#[cfg(feature = "sdl")]
 use sdl2::render::{Texture, TextureCreator, BlendMode};
 use crate::datafiles::{decode, pixmap};
 
@@ -338,6 +339,7 @@ impl<T> LabBlock<T> {
 }
 
 
+#[cfg(feature = "sdl")]
 impl LabBlock<Pixmap> {
     pub fn as_textures<'a, T>(&self, tc: &'a TextureCreator<T>) -> LabBlock<Texture<'a>> {
 	self.map(&|i| { let mut t = i.as_texture(tc); t.set_blend_mode(BlendMode::Blend); t})
@@ -387,6 +389,11 @@ impl LabData {
 }
 
 impl LabInfo {
+    /// Index into the lab palettes for the first lab that uses the given LabBlock
+    pub fn default_palette_index(&self, labblock_nr : usize) -> Option<usize> {
+	self.labdata.iter().find(|d| d.labblocks.contains(&labblock_nr)).map(|d| d.palette_index)
+    }
+
     pub fn load(labblock_f : &mut DataFile, lab_data_f : &mut DataFile) -> LabInfo {
 	let labblocks : Vec<LabBlock<IndexedPixmap>> = (0..labblock_f.num_entries).map(|i| LabBlock::load(i as usize, &labblock_f.decode(i))).collect();
 	let labdata : Vec<LabData> = (0..lab_data_f.num_entries).map(|i| LabData::load(&lab_data_f.decode(i))).collect();
@@ -0,0 +1,218 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Backend-independent renderer for the first-person ("3D") view of lab maps
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use super::AmberstarFiles;
+use super::animation::AnimationOrder;
+use super::daycycle::TimeOfDay;
+use super::labgfx::{LabBlock, LabPixmap};
+use super::map::{Illumination, LabRef, Map, MapDir};
+use super::palette::{Color, Palette};
+use super::pixmap::IndexedPixmap;
+
+/// Colour index that is transparent in lab blocks and floor / ceiling images
+pub const TRANSPARENT_INDEX : u8 = 0;
+/// Colour index in floor / ceiling images through which the sky shows on maps with daylight
+pub const BG_SKY_INDEX : u8 = 11;
+/// Colour index of the sky in rendered views (past the end of the 16-colour lab palettes)
+pub const SKY_INDEX : u8 = 16;
+
+/// Maximum distance (in tiles) that the player can see
+const MAX_DISTANCE : usize = 3;
+
+/// One thing to draw: what, how far ahead of the player, and how far to the right (-1, 0, 1)
+pub type LabViewEntry = (LabRef, usize, isize);
+
+/// Everything visible from (x, y) in direction `dir`, in drawing order (far to near).
+/// NPCs (with their tile positions) are drawn on top of all lab blocks at the same distance.
+pub fn draw_order(map : &Map, x : usize, y : usize, dir : MapDir, npcs : &[(LabRef, (usize, usize))]) -> Vec<LabViewEntry> {
+    let mut npcs_at_distance = vec![vec![]; MAX_DISTANCE + 1];
+    for (labref, (npc_x, npc_y)) in npcs {
+	for dist in 0..=MAX_DISTANCE {
+	    if (*npc_x as isize, *npc_y as isize) == (x as isize + dir.xvec() * dist as isize,
+						      y as isize + dir.yvec() * dist as isize) {
+		npcs_at_distance[dist].push(*labref);
+		break;
+	    }
+	}
+    }
+
+    let mut views = vec![];
+    let mut last_distance = MAX_DISTANCE as isize;
+    for map_view in map.lab_view(x as isize, y as isize, dir) {
+	let (_, distance, _) = map_view;
+	while (distance as isize) < last_distance {
+	    views.extend(npcs_at_distance[last_distance as usize].iter().map(|l| (*l, last_distance as usize, 0)));
+	    last_distance -= 1;
+	}
+	views.push(map_view);
+    }
+    while last_distance >= 0 {
+	views.extend(npcs_at_distance[last_distance as usize].iter().map(|l| (*l, last_distance as usize, 0)));
+	last_distance -= 1;
+    }
+    return views;
+}
+
+/// Renders lab views into IndexedPixmaps, using the colour indices of the lab palette
+pub struct LabViewRenderer<'a> {
+    /// Lab blocks, indexed by LabRef image number - 1
+    pub labblocks : Vec<&'a LabBlock<IndexedPixmap>>,
+    /// Ceiling and floor image, if any
+    pub background : Option<(&'a IndexedPixmap, &'a IndexedPixmap)>,
+    /// Replace BG_SKY_INDEX in the background by SKY_INDEX
+    pub sky : bool,
+}
+
+impl<'a> LabViewRenderer<'a> {
+    /// Renderer for the given lab map at the given time of day
+    pub fn for_map(data : &'a AmberstarFiles, map : &Map, time : TimeOfDay) -> LabViewRenderer<'a> {
+	let labdata = &data.labgfx.labdata[map.tileset];
+	let labblocks = labdata.labblocks.iter().map(|n| &data.labgfx.labblocks[*n]).collect();
+
+	// Same pairing of floors and ceilings as in the map viewer: index 0 is for the night, 1 for the day
+	let background = match (data.bg_pictures.get(labdata.bg_floor_index), data.bg_pictures.get(labdata.bg_ceiling_index)) {
+	    (Some(floors), Some(ceilings)) if floors.len() > 0 && ceilings.len() > 0 => {
+		let bg_index = usize::min(floors.len() * ceilings.len() - 1, if time.is_daytime() { 1 } else { 0 });
+		Some((&ceilings[bg_index % ceilings.len()], &floors[bg_index / ceilings.len()]))
+	    },
+	    _ => None,
+	};
+
+	LabViewRenderer {
+	    labblocks,
+	    background,
+	    sky : map.illumination == Illumination::Daylight,
+	}
+    }
+
+    /// Animation frame of one perspective; `anim_step` advances once per LAB_FRAME_MILLIS
+    fn frame<'b>(pixmaps : &'b [LabPixmap<IndexedPixmap>], labref : &LabRef, anim_step : usize) -> Option<&'b LabPixmap<IndexedPixmap>> {
+	let order = if labref.flags.anim_back_and_forth() { AnimationOrder::BackAndForth } else { AnimationOrder::Loop };
+	let sequence = order.sequence(pixmaps.len());
+	if sequence.len() == 0 {
+	    return None;
+	}
+	return Some(&pixmaps[sequence[anim_step % sequence.len()]]);
+    }
+
+    /// All pixmaps to draw for the given views, in order
+    fn layers(&self, views : &[LabViewEntry], anim_step : usize) -> Vec<&'a LabPixmap<IndexedPixmap>> {
+	let mut layers = vec![];
+	for (labref, dist, x) in views {
+	    for img in [labref.bg_image, labref.fg_image] {
+		let labblock = match img.checked_sub(1).and_then(|i| self.labblocks.get(i)) {
+		    Some(l) => *l,
+		    None    => { if img != 0 { pwarn!("No lab block for image {img}"); }
+				 continue; },
+		};
+		let (facing, orthogonal) = labblock.image_for(*dist, *x);
+		for perspective in facing.iter().chain(orthogonal.iter()) {
+		    layers.extend(perspective.base_pixmap.iter());
+		    layers.extend(LabViewRenderer::frame(&perspective.pixmaps, labref, anim_step));
+		}
+	    }
+	}
+	return layers;
+    }
+
+    /// Draws the background and then all views (as from `draw_order`), clipped to the background
+    /// (or to the lab blocks, if there is no background)
+    pub fn render(&self, views : &[LabViewEntry], anim_step : usize) -> IndexedPixmap {
+	let layers = self.layers(views, anim_step);
+	let (width, height) = match self.background {
+	    Some((ceiling, floor)) => (usize::max(ceiling.width, floor.width), ceiling.height + floor.height),
+	    None                   => (layers.iter().map(|l| l.x_extent()).max().unwrap_or(0),
+				       layers.iter().map(|l| l.y_extent()).max().unwrap_or(0)),
+	};
+	let mut result = IndexedPixmap::empty(width, height);
+
+	if let Some((ceiling, floor)) = self.background {
+	    result.blit_transparent_into(ceiling, 0, 0, TRANSPARENT_INDEX);
+	    result.blit_transparent_into(floor, 0, ceiling.height, TRANSPARENT_INDEX);
+	    if self.sky {
+		for pixel in result.pixels.iter_mut() {
+		    if *pixel == BG_SKY_INDEX {
+			*pixel = SKY_INDEX;
+		    }
+		}
+	    }
+	}
+
+	for layer in layers {
+	    result.blit_transparent_into(&layer.pixmap, layer.xoffset, layer.yoffset, TRANSPARENT_INDEX);
+	}
+	return result;
+    }
+}
+
+/// Lab palette extended by a transparent SKY_INDEX
+pub fn view_palette(data : &AmberstarFiles, map : &Map) -> Palette {
+    let mut palette = data.lab_palettes[data.labgfx.labdata[map.tileset].palette_index].clone();
+    palette.colors.resize(usize::max(palette.len(), SKY_INDEX as usize + 1), Color::BLACK);
+    palette.colors[SKY_INDEX as usize] = Color::rgba(0, 0, 0, 0);
+    return palette;
+}
+
+/// Renders the first-person view from (x, y) in direction `dir`, with NPCs at the start of their
+/// movement cycle.  Returns None for maps that are not first-person.
+pub fn render_lab_view(data : &AmberstarFiles, map : &Map, x : usize, y : usize, dir : MapDir,
+		       time : TimeOfDay, anim_step : usize) -> Option<(IndexedPixmap, Palette)> {
+    if !map.first_person {
+	return None;
+    }
+    let npcs : Vec<(LabRef, (usize, usize))> = map.npcs.iter()
+	.filter_map(|npc| npc.pos_at_cycle(0).map(|pos| (npc.lab_ref(map), pos)))
+	.collect();
+    let views = draw_order(map, x, y, dir, &npcs);
+    let pixmap = LabViewRenderer::for_map(data, map, time).render(&views, anim_step);
+    return Some((pixmap, view_palette(data, map)));
+}
+
+#[cfg(test)]
+fn test_furniture(color : u8) -> LabBlock<IndexedPixmap> {
+    use super::labgfx::{LabBlockType, LabImage};
+    // perspectives[3 - distance], each 4 pixels wide; farther images are further right
+    let perspectives = (0..=MAX_DISTANCE).map(|p| LabImage {
+	base_pixmap : None,
+	pixmaps : vec![LabPixmap {
+	    xoffset : 3 - p,
+	    yoffset : 1,
+	    pixmap : IndexedPixmap { width : 4, height : 1, pixels : vec![color, TRANSPARENT_INDEX, color, color] },
+	}],
+    }).collect();
+    LabBlock {
+	perspectives,
+	num_frames_distant : 1,
+	id : 0,
+	block_type : LabBlockType::Furniture,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_render() {
+    use super::tile::TileFlags;
+    let near = test_furniture(5);
+    let far = test_furniture(7);
+    let ceiling = IndexedPixmap { width : 8, height : 1, pixels : vec![BG_SKY_INDEX, 1, 1, 1, 1, 1, 1, 1] };
+    let floor = IndexedPixmap { width : 8, height : 2, pixels : vec![2; 16] };
+    let renderer = LabViewRenderer {
+	labblocks : vec![&near, &far],
+	background : Some((&ceiling, &floor)),
+	sky : true,
+    };
+    let labref = |img| LabRef { flags : TileFlags { flags : 0 }, fg_image : img, bg_image : 0, magic : 0 };
+    let view = renderer.render(&[(labref(2), 1, 0), (labref(1), 0, 0), (labref(1), 1, 1)], 0);
+    assert_eq!((8, 3), (view.width, view.height));
+    assert_eq!(vec![SKY_INDEX, 1, 1, 1, 1, 1, 1, 1], view.pixels[0..8]);
+    // far (distance 1) at x=1, then near (distance 0) at x=0 on top; transparent pixels let the far block show
+    assert_eq!(vec![5, 7, 5, 5, 7, 2, 2, 2], view.pixels[8..16]);
+    assert_eq!(vec![2; 8], view.pixels[16..24]);
+}
@@ -186,7 +186,7 @@ type MapIndex = usize;
 type ChestIndex = usize; // number of CHESTDATA.AMB entry
 type ChestFlagID = usize; // flag to store whether chest has been emptied
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum EventOp {
     LockedDoor(usize), // lock pick difficulty
     PopupMessage(Option<ImageIndex>, MapMessageIndex),
@@ -217,6 +217,12 @@ impl Event {
 	_cond : EventCondition::Enter,
 	_program : vec![],
     };
+
+    /// What the event does, in order
+    pub fn program(&self) -> &[EventOp] {
+	&self._program
+    }
+
     fn new(data : &[u8]) -> Option<Event> {
 	const OP_TELEPORT	: u8 = 0x01;
 	const OP_LOCKED_DOOR	: u8 = 0x02;
@@ -364,13 +370,13 @@ pub struct LabRef {
 type NPCSpriteIndex = usize;
 type NPCIndex = usize;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum NPCAction {
     PopupMessage(MapMessageIndex),
     Chat(NPCIndex),
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum NPCMovement {
     Attack,                     // move towards player and trigger fight when on same tile
     Stationary,
@@ -399,6 +405,14 @@ impl MapNPC {
 	return map.lab_info[self.sprite - 1];
     }
 
+    /// Tile position at the given step of the movement cycle; None if the NPC is "out"
+    pub fn pos_at_cycle(&self, cycle_pos : usize) -> Option<(usize, usize)> {
+	match &self.movement {
+	    NPCMovement::Cycle(cycle) => cycle[cycle_pos % cycle.len()],
+	    _                         => Some(self.start_pos),
+	}
+    }
+
     pub fn hostile(&self) -> bool {
 	return 0 != self.flags & MapNPC::FLAG_CHASE_AND_ATTACK;
     }
@@ -812,3 +826,28 @@ pub fn new(map_nr : usize, src : &[u8]) -> Map {
     pinfo!("}}");
     return map;
 }
+
+/// 2D map with the given events (raw event table entries) and hotspots (one event number per tile, 0 for none), for tests
+#[cfg(test)]
+pub(crate) fn test_map(width : usize, height : usize, events : &[[u8; EVENT_TABLE_ENTRY_SIZE]], hotspots : &[u8]) -> Map {
+    Map {
+	name : "TEST".to_string(),
+	width,
+	height,
+	num_layers : 0,
+	tiles : vec![],
+	hotspots : MapLayer::new(width, height, hotspots, NonZeroU8::new),
+	event_table : events.iter().map(|e| Event::new(e).unwrap_or(Event::EMPTY)).collect(),
+	lab_info : vec![],
+	tileset : 0,
+	song_nr : 0,
+	flags : 0,
+	first_person : false,
+	npcs : vec![],
+	can_rest : false,
+	can_mapshow : false,
+	illumination : Illumination::Always,
+	environment : Environment::City,
+	data : vec![],
+    }
+}
@@ -1,6 +1,15 @@
+use std::collections::HashMap;
+
 use super::string_fragment_table::StringFragmentTable;
 use crate::datafiles::decode;
 
+/// Does the decoder put a space in front of this fragment (unless it is the first in its string)?
+fn implies_space(firstchar : char) -> bool {
+    return (firstchar >= '0' && firstchar <= '9')
+	|| (firstchar >= 'A' && firstchar <= 'Z')
+	|| (firstchar == '-' || firstchar == '~');
+}
+
 pub struct MapStringTable {
     pub strings : Vec<String>
 }
@@ -23,9 +32,7 @@ impl MapStringTable {
 		    let token = &fragments.get(str_index);
 		    if k > 0 && token.len() > 0 {
 			let firstchar = token.chars().nth(0).unwrap();
-			if (firstchar >= '0' && firstchar <= '9')
-			    || (firstchar >= 'A' && firstchar <= 'Z')
-			    || (firstchar == '-' || firstchar == '~') {
+			if implies_space(firstchar) {
 			    s.push(' ');
 			}
 		    }
@@ -38,3 +45,152 @@ impl MapStringTable {
     }
 }
 
+/// Splits a string into fragments such that decoding the fragments (as in `MapStringTable::new`)
+/// yields the string again.  Words, runs of punctuation, and line breaks become separate fragments;
+/// spaces in front of fragments that start with a digit, capital letter, '-' or '~' are left implicit.
+pub fn tokenize(s : &str) -> Vec<String> {
+    let chars : Vec<char> = s.chars().collect();
+    let is_word = |c : char| c.is_alphanumeric() || c == '\'';
+    let mut tokens : Vec<String> = vec![];
+    let mut current = String::new();
+    // Last character was an implicit space, so the next character must start a new fragment
+    let mut after_implicit_space = false;
+
+    for (i, c) in chars.iter().copied().enumerate() {
+	let next = chars.get(i + 1).copied();
+	if c == ' ' && next.map_or(false, implies_space) && !(tokens.is_empty() && current.is_empty()) {
+	    if !current.is_empty() {
+		tokens.push(std::mem::take(&mut current));
+	    }
+	    after_implicit_space = true;
+	    continue;
+	}
+	if after_implicit_space {
+	    after_implicit_space = false;
+	    current.push(c);
+	    continue;
+	}
+	if implies_space(c) {
+	    // Must not start a new fragment, or the decoder would insert a space
+	    if current.is_empty() {
+		if let Some(last) = tokens.pop() {
+		    current = last;
+		}
+	    }
+	} else if c == '\n' || current.ends_with('\n')
+	    || (!current.is_empty() && is_word(c) != current.chars().last().map_or(false, is_word)) {
+	    if !current.is_empty() {
+		tokens.push(std::mem::take(&mut current));
+	    }
+	}
+	current.push(c);
+    }
+    if !current.is_empty() {
+	tokens.push(current);
+    }
+    return tokens;
+}
+
+/// Turns Unicode strings into fragment indices, adding new fragments to the table where needed
+pub struct TextEncoder {
+    pub fragments : StringFragmentTable,
+    /// Index of the first fragment for each fragment text
+    index : HashMap<String, u16>,
+    num_original_fragments : usize,
+}
+
+impl TextEncoder {
+    pub fn new(fragments : &StringFragmentTable) -> TextEncoder {
+	let mut index = HashMap::new();
+	for i in (1..fragments.len()).rev() {
+	    index.insert(fragments.get_str(i as u16).to_string(), i as u16);
+	}
+	TextEncoder {
+	    fragments : fragments.clone(),
+	    index,
+	    num_original_fragments : fragments.len(),
+	}
+    }
+
+    /// Fragments added so far
+    pub fn num_new_fragments(&self) -> usize {
+	self.fragments.len() - self.num_original_fragments
+    }
+
+    fn fragment_index(&mut self, token : &str) -> Result<u16, String> {
+	if let Some(i) = self.index.get(token) {
+	    return Ok(*i);
+	}
+	let i = self.fragments.push(token)?;
+	self.index.insert(token.to_string(), i);
+	return Ok(i);
+    }
+
+    pub fn encode_string(&mut self, s : &str) -> Result<Vec<u16>, String> {
+	tokenize(s).iter().map(|t| self.fragment_index(t)).collect()
+    }
+
+    /// Encodes strings in the layout of MAPTEXT.AMB / CODETXT.AMB entries:
+    /// number of strings, a padding byte, the start of each string (plus the end of the last one)
+    /// in fragment indices, and then the fragment indices themselves
+    pub fn encode_table(&mut self, strings : &[String]) -> Result<Vec<u8>, String> {
+	if strings.len() > 0xff {
+	    return Err(format!("Too many strings in one table: {}", strings.len()));
+	}
+	let mut body = vec![];
+	let mut positions = vec![0];
+	for s in strings {
+	    body.extend(self.encode_string(s)?);
+	    if body.len() > u16::MAX as usize {
+		return Err("String table too long".to_string());
+	    }
+	    positions.push(body.len() as u16);
+	}
+	let mut result = vec![strings.len() as u8, 0];
+	if strings.is_empty() {
+	    return Ok(result);
+	}
+	for pos in positions {
+	    decode::push_u16(&mut result, pos);
+	}
+	for fragment in body {
+	    decode::push_u16(&mut result, fragment);
+	}
+	return Ok(result);
+    }
+}
+
+#[cfg(test)]
+fn test_fragments() -> StringFragmentTable {
+    let mut fragments = StringFragmentTable::new(&[]);
+    for f in ["HELLO", ",", "WORLD", "!", "\n"] {
+	fragments.push(f).unwrap();
+    }
+    return fragments;
+}
+
+#[cfg(test)]
+#[test]
+fn test_tokenize() {
+    assert_eq!(vec!["HELLO", ",", "WORLD", "!"], tokenize("HELLO, WORLD!"));
+    assert_eq!(vec!["Hello", " ", "world"], tokenize("Hello world"));
+    assert_eq!(vec!["A", "\n", "B"], tokenize("A\n B"));
+    assert_eq!(vec!["A", "\nB"], tokenize("A\nB"));
+    assert_eq!(vec!["1234", ".", "5"], tokenize("1234. 5"));
+    assert_eq!(vec![" A", " ", "B"], tokenize(" A  B"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_encode_roundtrip() {
+    let fragments = test_fragments();
+    let strings : Vec<String> = ["HELLO, WORLD!", "", "WORLD\n HELLO", "HELLO, NEW WORLD! Hi there.", " A  B", "A\nB", "1234. 5"]
+	.iter().map(|s| s.to_string()).collect();
+    let mut encoder = TextEncoder::new(&fragments);
+    assert_eq!(vec![1, 2, 3, 4], encoder.encode_string("HELLO, WORLD!").unwrap());
+    let bytes = encoder.encode_table(&strings).unwrap();
+    assert!(encoder.num_new_fragments() > 0);
+    let decoded = MapStringTable::new(&bytes, &encoder.fragments);
+    assert_eq!(strings, decoded.strings);
+    assert!(encoder.encode_string("\u{263a}").is_err());
+}
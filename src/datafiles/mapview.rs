@@ -0,0 +1,363 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Backend-independent top-down renderer for whole maps, with optional debug overlays
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use super::AmberstarFiles;
+use super::labgfx::{LabBlock, LabBlockType};
+use super::map::{LabRef, Map, MapNPC, NPCMovement};
+use super::palette::{Color, Palette};
+use super::pixmap::IndexedPixmap;
+use super::tile::Tileset;
+
+/// Width and height of one map tile, in pixels
+pub const TILE_SIZE : usize = 16;
+
+/// Colour indices for the overlays, past the end of the 16-colour map palettes
+pub const HOTSPOT_INDEX : u8 = 0x1c;
+pub const EVENT_INDEX : u8 = 0x1d;
+pub const NPC_INDEX : u8 = 0x1e;
+pub const ROUTE_INDEX : u8 = 0x1f;
+const OVERLAY_COLORS : [(u8, Color); 4] = [
+    (HOTSPOT_INDEX, Color::rgb(0xff, 0xff, 0x00)),
+    (EVENT_INDEX,   Color::WHITE),
+    (NPC_INDEX,     Color::rgb(0x00, 0xff, 0xff)),
+    (ROUTE_INDEX,   Color::rgb(0x00, 0xff, 0x00)),
+];
+
+const TRANSPARENT_INDEX : u8 = 0;
+
+/// Debug information to draw on top of the map
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Overlays {
+    /// Outline all tiles with hotspots
+    pub hotspots : bool,
+    /// Print the event number of each hotspot
+    pub events : bool,
+    /// Outline NPCs at their start positions
+    pub npcs : bool,
+    /// Draw NPC movement cycles; crosses mark where NPCs leave the map
+    pub routes : bool,
+}
+
+/// How to draw the icons for a map's tile numbers
+enum TileSource<'a> {
+    Tiles(&'a Tileset<IndexedPixmap>),
+    Lab(&'a [LabRef], Vec<&'a LabBlock<IndexedPixmap>>),
+}
+
+impl<'a> TileSource<'a> {
+    fn for_map(data : &'a AmberstarFiles, map : &'a Map) -> (TileSource<'a>, Palette) {
+	if map.first_person {
+	    let labdata = &data.labgfx.labdata[map.tileset];
+	    let labblocks = labdata.labblocks.iter().map(|n| &data.labgfx.labblocks[*n]).collect();
+	    return (TileSource::Lab(&map.lab_info, labblocks),
+		    data.lab_palettes[labdata.palette_index].clone());
+	}
+	let tileset = &data.tiles_indexed[map.tileset];
+	return (TileSource::Tiles(tileset), tileset.palette.clone());
+    }
+
+    /// Draws the first animation frame of the tile with the given (1-based) number
+    fn draw(&self, dest : &mut IndexedPixmap, tile_id : usize, x : usize, y : usize) {
+	match self {
+	    TileSource::Tiles(tileset) => {
+		if let Some(icon) = tile_id.checked_sub(1).and_then(|i| tileset.tile_icons.get(i)) {
+		    if icon.flags.draw_with_transparency() {
+			dest.blit_transparent_into(&icon.frames[0], x, y, TRANSPARENT_INDEX);
+		    } else {
+			dest.blit_into(&icon.frames[0], x, y);
+		    }
+		}
+	    },
+	    TileSource::Lab(labrefs, labblocks) => {
+		if let Some(labref) = tile_id.checked_sub(1).and_then(|i| labrefs.get(i)) {
+		    for img in [labref.bg_image, labref.fg_image] {
+			if let Some(labblock) = img.checked_sub(1).and_then(|i| labblocks.get(i)) {
+			    if let Some(icon) = lab_icon(labblock) {
+				dest.blit_transparent_into(&icon, x, y, TRANSPARENT_INDEX);
+			    }
+			}
+		    }
+		}
+	    },
+	}
+    }
+}
+
+/// A TILE_SIZE x TILE_SIZE thumbnail of a lab block, picked as in the map viewer
+fn lab_icon(labblock : &LabBlock<IndexedPixmap>) -> Option<IndexedPixmap> {
+    let perspectives = &labblock.perspectives;
+    let mut perspective_nr = perspectives.len().checked_sub(2)?;
+    if labblock.block_type != LabBlockType::Furniture {
+	perspective_nr = usize::min(8, perspective_nr);
+    }
+    let src = &perspectives[perspective_nr].pixmaps.first()?.pixmap;
+    return Some(scale_to_fit(src, TILE_SIZE));
+}
+
+/// Nearest-neighbour scaling into a size x size pixmap, preserving the aspect ratio
+fn scale_to_fit(src : &IndexedPixmap, size : usize) -> IndexedPixmap {
+    let mut result = IndexedPixmap::empty(size, size);
+    let src_size = usize::max(src.width, src.height);
+    if src_size == 0 {
+	return result;
+    }
+    let (width, height) = (src.width * size / src_size, src.height * size / src_size);
+    let (xoffset, yoffset) = ((size - width) >> 1, (size - height) >> 1);
+    for y in 0..height {
+	for x in 0..width {
+	    result.pixels[xoffset + x + (yoffset + y) * size] = src.pixels[x * src_size / size + (y * src_size / size) * src.width];
+	}
+    }
+    return result;
+}
+
+fn set_pixel(dest : &mut IndexedPixmap, x : isize, y : isize, color : u8) {
+    if x >= 0 && y >= 0 && (x as usize) < dest.width && (y as usize) < dest.height {
+	dest.pixels[x as usize + y as usize * dest.width] = color;
+    }
+}
+
+fn draw_line(dest : &mut IndexedPixmap, (x0, y0) : (isize, isize), (x1, y1) : (isize, isize), color : u8) {
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+    let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+    loop {
+	set_pixel(dest, x, y, color);
+	if (x, y) == (x1, y1) {
+	    return;
+	}
+	if 2 * err >= dy { err += dy; x += sx; }
+	if 2 * err <= dx { err += dx; y += sy; }
+    }
+}
+
+fn draw_outline(dest : &mut IndexedPixmap, x : usize, y : usize, size : usize, color : u8) {
+    let (x0, y0, x1, y1) = (x as isize, y as isize, (x + size - 1) as isize, (y + size - 1) as isize);
+    draw_line(dest, (x0, y0), (x1, y0), color);
+    draw_line(dest, (x1, y0), (x1, y1), color);
+    draw_line(dest, (x1, y1), (x0, y1), color);
+    draw_line(dest, (x0, y1), (x0, y0), color);
+}
+
+/// Height of the built-in digits for event numbers and atlas labels (the in-game font has not been located)
+const DIGIT_HEIGHT : usize = 5;
+/// Horizontal distance between digits
+const DIGIT_ADVANCE : usize = 4;
+/// 3x5 glyphs for hexadecimal digits, one row per byte (bit 2 is the leftmost column)
+const DIGITS : [[u8; DIGIT_HEIGHT]; 16] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111], [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111], [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001], [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111], [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111], [0b111, 0b101, 0b111, 0b001, 0b111],
+    [0b010, 0b101, 0b111, 0b101, 0b101], [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011], [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b111, 0b100, 0b111], [0b111, 0b100, 0b111, 0b100, 0b100],
+];
+
+/// Draws (hexadecimal) digits with the top left corner at (x, y); skips other characters and clips at the borders
+fn draw_digits(dest : &mut IndexedPixmap, text : &str, x : usize, y : usize, color : u8) {
+    for (char_nr, c) in text.chars().enumerate() {
+	let Some(glyph) = c.to_digit(16).map(|d| &DIGITS[d as usize]) else {
+	    continue;
+	};
+	for (row_nr, row) in glyph.iter().enumerate() {
+	    for col in 0..3 {
+		let (px, py) = (x + char_nr * DIGIT_ADVANCE + col, y + row_nr);
+		if row & (0b100 >> col) != 0 && px < dest.width && py < dest.height {
+		    dest.pixels[px + py * dest.width] = color;
+		}
+	    }
+	}
+    }
+}
+
+/// Pixel position of the centre of a tile
+fn tile_centre((x, y) : (usize, usize)) -> (isize, isize) {
+    ((x * TILE_SIZE + TILE_SIZE / 2) as isize, (y * TILE_SIZE + TILE_SIZE / 2) as isize)
+}
+
+fn draw_route(dest : &mut IndexedPixmap, npc : &MapNPC) {
+    let cycle = match &npc.movement {
+	NPCMovement::Cycle(cycle) => cycle,
+	_                         => return,
+    };
+    for (i, pos) in cycle.iter().enumerate() {
+	let (x, y) = match pos {
+	    Some(p) => tile_centre(*p),
+	    None    => continue,
+	};
+	match cycle[(i + 1) % cycle.len()] {
+	    Some(next) => draw_line(dest, (x, y), tile_centre(next), ROUTE_INDEX),
+	    None       => {
+		// NPC disappears here
+		let d = (TILE_SIZE / 4) as isize;
+		draw_line(dest, (x - d, y - d), (x + d, y + d), ROUTE_INDEX);
+		draw_line(dest, (x - d, y + d), (x + d, y - d), ROUTE_INDEX);
+	    },
+	}
+    }
+}
+
+/// Renders all layers of the map plus the NPCs at their start positions, at one pixel per pixel.
+/// The palette is the map's palette, extended by the overlay colours.
+pub fn render_map(data : &AmberstarFiles, map : &Map, overlays : Overlays) -> (IndexedPixmap, Palette) {
+    let (tiles, mut palette) = TileSource::for_map(data, map);
+    let mut result = IndexedPixmap::empty(map.width * TILE_SIZE, map.height * TILE_SIZE);
+
+    for layer in 0..map.num_layers {
+	for y in 0..map.height {
+	    for x in 0..map.width {
+		if let Some(tile_id) = map.tile_at(layer, x, y) {
+		    tiles.draw(&mut result, tile_id, x * TILE_SIZE, y * TILE_SIZE);
+		}
+	    }
+	}
+    }
+
+    for npc in &map.npcs {
+	let (x, y) = npc.start_pos;
+	tiles.draw(&mut result, npc.sprite, x * TILE_SIZE, y * TILE_SIZE);
+    }
+
+    if overlays.routes {
+	for npc in &map.npcs {
+	    draw_route(&mut result, npc);
+	}
+    }
+    if overlays.npcs {
+	for npc in &map.npcs {
+	    let (x, y) = npc.start_pos;
+	    draw_outline(&mut result, x * TILE_SIZE, y * TILE_SIZE, TILE_SIZE, NPC_INDEX);
+	}
+    }
+    if overlays.hotspots || overlays.events {
+	for y in 0..map.height {
+	    for x in 0..map.width {
+		if let Some(hotspot) = map.hotspot_at(x, y).filter(|h| *h > 0) {
+		    if overlays.hotspots {
+			draw_outline(&mut result, x * TILE_SIZE, y * TILE_SIZE, TILE_SIZE, HOTSPOT_INDEX);
+		    }
+		    if overlays.events {
+			draw_digits(&mut result, &format!("{hotspot:02x}"), x * TILE_SIZE + 1, y * TILE_SIZE + 1, EVENT_INDEX);
+		    }
+		}
+	    }
+	}
+    }
+
+    palette.colors.resize(usize::max(palette.len(), 0x20), Color::BLACK);
+    for (index, color) in OVERLAY_COLORS {
+	palette.colors[index as usize] = color;
+    }
+    return (result, palette);
+}
+
+// ----------------------------------------
+// Atlas of several maps (each with its own palette), as true-colour image
+
+pub struct Atlas {
+    pub width : usize,
+    pub height : usize,
+    pub pixels : Vec<Color>,
+}
+
+/// Space for the map number above each map
+const LABEL_HEIGHT : usize = DIGIT_HEIGHT + 2;
+/// Space between maps
+const ATLAS_SPACING : usize = TILE_SIZE;
+
+impl Atlas {
+    /// Lays out the (pixmap, palette, label) triples in a roughly square grid of equally sized cells
+    pub fn new(maps : &[(IndexedPixmap, Palette, String)]) -> Atlas {
+	let cell_width = maps.iter().map(|(p, _, _)| p.width).max().unwrap_or(0) + ATLAS_SPACING;
+	let cell_height = maps.iter().map(|(p, _, _)| p.height).max().unwrap_or(0) + ATLAS_SPACING + LABEL_HEIGHT;
+	let columns = usize::max(1, (maps.len() as f64).sqrt().ceil() as usize);
+	let rows = (maps.len() + columns - 1) / columns;
+	let mut atlas = Atlas {
+	    width : columns * cell_width,
+	    height : rows * cell_height,
+	    pixels : vec![Color::BLACK; columns * cell_width * rows * cell_height],
+	};
+	for (i, (pixmap, palette, label)) in maps.iter().enumerate() {
+	    let (x, y) = ((i % columns) * cell_width, (i / columns) * cell_height);
+	    let mut label_pixmap = IndexedPixmap::empty(cell_width - ATLAS_SPACING, DIGIT_HEIGHT);
+	    draw_digits(&mut label_pixmap, label, 0, 0, 1);
+	    let label_palette = Palette { colors : vec![Color::BLACK, Color::WHITE] };
+	    atlas.blit(&label_pixmap, &label_palette, x, y);
+	    atlas.blit(pixmap, palette, x, y + LABEL_HEIGHT);
+	}
+	return atlas;
+    }
+
+    fn blit(&mut self, src : &IndexedPixmap, palette : &Palette, x : usize, y : usize) {
+	for sy in 0..src.height {
+	    for sx in 0..src.width {
+		self.pixels[x + sx + (y + sy) * self.width] = palette.get(src.pixels[sx + sy * src.width] as usize);
+	    }
+	}
+    }
+
+    pub fn write_png(&self, path : &Path) -> io::Result<()> {
+	let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width as u32, self.height as u32);
+	encoder.set_color(png::ColorType::Rgb);
+	encoder.set_depth(png::BitDepth::Eight);
+	let mut writer = encoder.write_header().map_err(png_err)?;
+	let data : Vec<u8> = self.pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+	writer.write_image_data(&data).map_err(png_err)?;
+	return writer.finish().map_err(png_err);
+    }
+}
+
+fn png_err(e : png::EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// All maps, in map number order, labelled with their numbers
+pub fn render_atlas(data : &AmberstarFiles, overlays : Overlays) -> Atlas {
+    let maps : Vec<(IndexedPixmap, Palette, String)> = data.maps.iter().enumerate().map(|(nr, map)| {
+	let (pixmap, palette) = render_map(data, map, overlays);
+	(pixmap, palette, format!("{nr:03}"))
+    }).collect();
+    return Atlas::new(&maps);
+}
+
+#[cfg(test)]
+#[test]
+fn test_draw_helpers() {
+    let mut pixmap = IndexedPixmap::empty(4, 4);
+    draw_line(&mut pixmap, (0, 0), (3, 3), 1);
+    draw_line(&mut pixmap, (3, 0), (3, 2), 2);
+    assert_eq!(vec![1, 0, 0, 2,
+		    0, 1, 0, 2,
+		    0, 0, 1, 2,
+		    0, 0, 0, 1], pixmap.pixels);
+
+    let src = IndexedPixmap { width : 4, height : 2, pixels : vec![1, 1, 2, 2,
+								     3, 3, 4, 4] };
+    assert_eq!(vec![0, 0, 0, 0,
+		    1, 1, 2, 2,
+		    3, 3, 4, 4,
+		    0, 0, 0, 0], scale_to_fit(&src, 4).pixels);
+    assert_eq!(vec![1, 2,
+		    0, 0], scale_to_fit(&src, 2).pixels);
+
+    let mut digits = IndexedPixmap::empty(7, 5);
+    draw_digits(&mut digits, "1c", 0, 0, 1);
+    assert_eq!(vec![0, 1, 0, 0, 0, 1, 1,
+		    1, 1, 0, 0, 1, 0, 0,
+		    0, 1, 0, 0, 1, 0, 0,
+		    0, 1, 0, 0, 1, 0, 0,
+		    1, 1, 1, 0, 0, 1, 1], digits.pixels);
+}
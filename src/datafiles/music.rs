@@ -41,7 +41,7 @@ impl fmt::Display for BasicSample {
 }
 
 /// Multiple samples that we "slide through" while playing
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct SlidingSample {
     pub bounds : SampleRange, // Will stop once it moves into those bounds
     pub subsample_start : SampleRange,
@@ -59,7 +59,7 @@ impl fmt::Display for SlidingSample {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum InstrumentOp {
     WaitTicks(usize),        // delay before next step
     Loop(Vec<InstrumentOp>),
@@ -140,7 +140,7 @@ impl fmt::Display for InstrumentOp {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Instrument {
     pub ops : Vec<InstrumentOp>,
 }
@@ -169,7 +169,7 @@ impl fmt::Display for Instrument {
 // ================================================================================
 // Timbres
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Vibrato {
     pub slope : isize,
     pub depth : isize,
@@ -181,7 +181,7 @@ impl fmt::Display for Vibrato {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct VolumeSpec {
     pub volume   : u8,    // 0-64
     pub duration : usize, // ticks to hold before moving on
@@ -193,7 +193,7 @@ impl fmt::Display for VolumeSpec {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct VolumeEnvelope {
     pub attack   : Vec<VolumeSpec>,
     pub sustain  : Vec<VolumeSpec>,
@@ -209,7 +209,7 @@ impl fmt::Display for VolumeEnvelope {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Timbre {
     pub envelope_speed : u8, // default ticks per step in the volume envelope
     pub instrument     : Option<u8>, // Default instrument
@@ -232,20 +232,20 @@ impl fmt::Display for Timbre {
 // ================================================================================
 // Monopatterns
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct MPTimbre {
     pub timbre : usize,
     pub instrument : Option<usize>,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct MPNote {
     pub note : isize,
     pub timbre : Option<MPTimbre>,
     pub portando : Option<isize>
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct MPOp {
     pub note : Option<MPNote>,       // Hold, if None
     pub pticks : usize,
@@ -275,7 +275,7 @@ impl fmt::Display for MPOp {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Monopattern {
     pub ops : Vec<MPOp>,
 }
@@ -289,7 +289,7 @@ impl fmt::Display for Monopattern {
 // ================================================================================
 // Divisions
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum DivisionEffect {
     TimbreAdjust(usize),
     FullStop,
@@ -308,7 +308,7 @@ impl fmt::Display for DivisionEffect {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct DivisionChannel {
     pub monopat   : usize,
     pub transpose : isize,
@@ -331,7 +331,7 @@ impl DivisionChannel {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Division {
     pub channels : [DivisionChannel; 4],
 }
@@ -349,7 +349,7 @@ impl fmt::Display for Division {
 // ================================================================================
 // Divisions
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct SongInfo {
     pub first_division : usize,
     pub last_division : usize,
@@ -368,6 +368,15 @@ impl fmt::Display for SongInfo {
 // ================================================================================
 // Song
 
+/// Where a song was loaded from, and the bytes it was loaded from
+#[derive(Clone)]
+struct SongOrigin {
+    offset : usize,   // offset of the COSO block within the data it was found in
+    slot_len : usize, // bytes available at `offset`
+    raw : Vec<u8>,    // COSO block that the song was last decoded from or stored as
+}
+
+#[derive(Clone)]
 pub struct Song {
     pub basic_samples : Vec<BasicSample>,
     //   pub slide_samples : Vec<Vec<SampleRange>>, // Samples used by Slide instrument effects
@@ -376,6 +385,7 @@ pub struct Song {
     pub monopatterns : Vec<Monopattern>,
     pub divisions : Vec<Division>,
     pub songinfo : SongInfo,
+    origin : Option<SongOrigin>,
 }
 
 impl Song {
@@ -417,6 +427,141 @@ impl fmt::Display for Song {
     }
 }
 
+// --------------------------------------------------------------------------------
+// Editing
+
+/// Editing operations check that the result is still well-formed and can be encoded,
+/// and leave the song unchanged if not.
+impl Song {
+    /// Offset of the COSO block within the data that the song was loaded from
+    pub fn source_offset(&self) -> Option<usize> {
+	self.origin.as_ref().map(|o| o.offset)
+    }
+
+    /// Space (in bytes) available for storing the song at `source_offset()`
+    pub fn source_len(&self) -> usize {
+	self.origin.as_ref().map_or(0, |o| o.slot_len)
+    }
+
+    /// Records that the song is now stored as `coso`, so that later encodings reproduce it exactly
+    pub(super) fn set_stored(&mut self, coso : Vec<u8>) {
+	if let Some(origin) = self.origin.as_mut() {
+	    origin.raw = coso;
+	}
+    }
+
+    pub fn insert_monopattern_op(&mut self, monopat : usize, index : usize, op : MPOp) -> Result<(), String> {
+	self.check_mpop(&op)?;
+	let pat = self.monopattern_mut(monopat)?;
+	if index > pat.ops.len() {
+	    return Err(format!("Cannot insert at {index}: monopattern #{monopat} has {} ops", pat.ops.len()));
+	}
+	pat.ops.insert(index, op);
+	Ok(())
+    }
+
+    pub fn replace_monopattern_op(&mut self, monopat : usize, index : usize, op : MPOp) -> Result<MPOp, String> {
+	self.check_mpop(&op)?;
+	let pat = self.monopattern_mut(monopat)?;
+	match pat.ops.get_mut(index) {
+	    None     => Err(format!("No op #{index} in monopattern #{monopat}")),
+	    Some(o)  => Ok(std::mem::replace(o, op)),
+	}
+    }
+
+    pub fn delete_monopattern_op(&mut self, monopat : usize, index : usize) -> Result<MPOp, String> {
+	let pat = self.monopattern_mut(monopat)?;
+	if index >= pat.ops.len() {
+	    return Err(format!("No op #{index} in monopattern #{monopat}"));
+	}
+	Ok(pat.ops.remove(index))
+    }
+
+    pub fn set_instrument(&mut self, nr : usize, instrument : Instrument) -> Result<(), String> {
+	encode_instrument(&instrument, &self.basic_samples)?;
+	match self.instruments.get_mut(nr) {
+	    None    => Err(format!("No instrument #{nr}")),
+	    Some(i) => { *i = instrument; Ok(()) },
+	}
+    }
+
+    pub fn set_timbre(&mut self, nr : usize, timbre : Timbre) -> Result<(), String> {
+	if let Some(i) = timbre.instrument {
+	    if i as usize >= self.instruments.len() {
+		return Err(format!("Timbre refers to instrument #{i}, but there are only {}", self.instruments.len()));
+	    }
+	}
+	encode_timbre(&timbre)?;
+	match self.timbres.get_mut(nr) {
+	    None    => Err(format!("No timbre #{nr}")),
+	    Some(t) => { *t = timbre; Ok(()) },
+	}
+    }
+
+    pub fn set_volume_envelope(&mut self, timbre_nr : usize, vol : VolumeEnvelope) -> Result<(), String> {
+	let mut timbre = match self.timbres.get(timbre_nr) {
+	    None    => return Err(format!("No timbre #{timbre_nr}")),
+	    Some(t) => t.clone(),
+	};
+	timbre.vol = vol;
+	self.set_timbre(timbre_nr, timbre)
+    }
+
+    pub fn set_division(&mut self, nr : usize, division : Division) -> Result<(), String> {
+	for chan in division.channels.iter() {
+	    if chan.monopat >= self.monopatterns.len() {
+		return Err(format!("Division refers to monopattern #{}, but there are only {}",
+				   chan.monopat, self.monopatterns.len()));
+	    }
+	}
+	encode_division(&division)?;
+	match self.divisions.get_mut(nr) {
+	    None    => Err(format!("No division #{nr}")),
+	    Some(d) => { *d = division; Ok(()) },
+	}
+    }
+
+    /// Moves division `from` to position `to`, shifting the divisions in between
+    pub fn move_division(&mut self, from : usize, to : usize) -> Result<(), String> {
+	let len = self.divisions.len();
+	if from >= len || to >= len {
+	    return Err(format!("Cannot move division #{from} to #{to}: there are only {len}"));
+	}
+	let division = self.divisions.remove(from);
+	self.divisions.insert(to, division);
+	Ok(())
+    }
+
+    pub fn set_songinfo(&mut self, songinfo : SongInfo) -> Result<(), String> {
+	if songinfo.first_division > songinfo.last_division || songinfo.last_division >= self.divisions.len() {
+	    return Err(format!("Bad division range {songinfo} for {} divisions", self.divisions.len()));
+	}
+	encode_songinfo(&songinfo)?;
+	self.songinfo = songinfo;
+	Ok(())
+    }
+
+    fn monopattern_mut(&mut self, monopat : usize) -> Result<&mut Monopattern, String> {
+	let len = self.monopatterns.len();
+	self.monopatterns.get_mut(monopat).ok_or(format!("No monopattern #{monopat} (have {len})"))
+    }
+
+    fn check_mpop(&self, op : &MPOp) -> Result<(), String> {
+	if let Some(MPNote { timbre : Some(MPTimbre { timbre, instrument }), .. }) = op.note {
+	    if timbre >= self.timbres.len() {
+		return Err(format!("Note refers to timbre #{timbre}, but there are only {}", self.timbres.len()));
+	    }
+	    if let Some(i) = instrument {
+		if i >= self.instruments.len() {
+		    return Err(format!("Note refers to instrument #{i}, but there are only {}", self.instruments.len()));
+		}
+	    }
+	}
+	let mut duration = 0;
+	encode_mpop(op, &mut duration, &mut vec![])
+    }
+}
+
 struct TableIndexedData<'a> {
     data : &'a [u8],
     count : usize,
@@ -991,6 +1136,453 @@ impl<'a> TableIndexedElement<'a> {
 
 
 
+// ================================================================================
+// Encoding
+
+const COSO_HEADER_SIZE : usize = 52;
+
+const INSTR_OP_LOOP : u8          = 0xe0;
+const INSTR_OP_COMPLETED : u8     = 0xe1;
+const INSTR_OP_SAMPLE : u8        = 0xe2;
+const INSTR_OP_SLIDER : u8        = 0xe5;
+const INSTR_OP_SAMPLE_VOL : u8    = 0xe7;
+
+const TIMBRE_OP_LOOP : u8         = 0xe0;
+const TIMBRE_OP_COMPLETED : u8    = 0xe1;
+const TIMBRE_OP_SUSTAIN : u8      = 0xe8;
+
+const MP_OP_END : u8              = 0xff;
+const MP_OP_SET_SPEED : u8        = 0xfe;
+const MP_OP_SET_SPEED_WAIT : u8   = 0xfd;
+
+fn encode_sample(sample : &BasicSample) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    let (loop_offset, repeat) = match sample.looping {
+	None    => (0, 1),
+	Some(l) => (l.start.wrapping_sub(sample.attack.start), l.len >> 1),
+    };
+    if sample.attack.start > 0xffffffff || sample.attack.len > 0x1ffff || loop_offset > 0xffff || repeat > 0xffff {
+	return Err(format!("Sample {sample} out of range"));
+    }
+    decode::push_u32(&mut out, sample.attack.start as u32);
+    decode::push_u16(&mut out, (sample.attack.len >> 1) as u16);
+    decode::push_u16(&mut out, loop_offset as u16);
+    decode::push_u16(&mut out, repeat as u16);
+    Ok(out)
+}
+
+fn sample_index(sample : &BasicSample, basic_samples : &[BasicSample]) -> Result<u8, String> {
+    match basic_samples.iter().position(|s| s == sample) {
+	Some(i) if i <= 0xff => Ok(i as u8),
+	_ => Err(format!("{sample} is not among the song's basic samples")),
+    }
+}
+
+/// Re-encodes the stringified form of an instrument op that we do not interpret
+fn encode_unsupported_instrument_op(op : &str) -> Result<Vec<u8>, String> {
+    let err = || format!("Cannot encode unsupported instrument op '{op}'");
+    let (name, args) = op.split_once('(').ok_or_else(err)?;
+    let args : Vec<i64> = args.strip_suffix(')').ok_or_else(err)?
+	.split(',')
+	.map(|a| a.trim().parse::<i64>())
+	.collect::<Result<_, _>>()
+	.map_err(|_| err())?;
+    match (name, &args[..]) {
+	("E3", [speed, depth])       => Ok(vec![0xe3, *speed as u8, *depth as u8]),
+	("E4", [sample])             => Ok(vec![0xe4, *sample as u8]),
+	("E6", [len, delta, speed])  => {
+	    let mut out = vec![0xe6];
+	    decode::push_u16(&mut out, (*len as u16) >> 1);
+	    decode::push_u16(&mut out, ((*delta as i16) >> 1) as u16);
+	    out.push(*speed as u8);
+	    Ok(out)
+	},
+	("E8", [delay])              => Ok(vec![0xe8, *delay as u8]),
+	("E9", [sample, index])      => Ok(vec![0xe9, *sample as u8, *index as u8]),
+	_                            => Err(err()),
+    }
+}
+
+fn encode_instrument_ops(ops : &[InstrumentOp], basic_samples : &[BasicSample], out : &mut Vec<u8>) -> Result<(), String> {
+    let mut i = 0;
+    while i < ops.len() {
+	let next = ops.get(i + 1);
+	match (&ops[i], next) {
+	    (InstrumentOp::StopSample, Some(InstrumentOp::Sample(sample))) => {
+		out.push(INSTR_OP_SAMPLE);
+		out.push(sample_index(sample, basic_samples)?);
+		i += 2;
+	    },
+	    (InstrumentOp::Sample(sample), Some(InstrumentOp::ResetVolume)) => {
+		out.push(INSTR_OP_SAMPLE_VOL);
+		out.push(sample_index(sample, basic_samples)?);
+		i += 2;
+	    },
+	    (InstrumentOp::Slide(slide), Some(InstrumentOp::ResetVolume)) => {
+		let index = match basic_samples.iter().position(|s| s.attack == slide.bounds) {
+		    Some(i) if i <= 0xff => i,
+		    _ => return Err(format!("{slide} does not slide within a basic sample")),
+		};
+		let loop_pos = slide.subsample_start.start.wrapping_sub(slide.bounds.start);
+		if loop_pos >= 0x1fffe || loop_pos & 1 == 1 || slide.subsample_start.len > 0x1ffff
+		    || slide.delta & 1 == 1 || (slide.delta >> 1) < i16::MIN as isize || (slide.delta >> 1) > i16::MAX as isize
+		    || slide.delay_ticks > 0xff {
+			return Err(format!("{slide} out of range"));
+		    }
+		out.push(INSTR_OP_SLIDER);
+		out.push(index as u8);
+		decode::push_u16(out, (loop_pos >> 1) as u16);
+		decode::push_u16(out, (slide.subsample_start.len >> 1) as u16);
+		decode::push_u16(out, (slide.delta >> 1) as i16 as u16);
+		out.push(slide.delay_ticks as u8);
+		i += 2;
+	    },
+	    (InstrumentOp::Unsupported(op), _) => {
+		out.extend(encode_unsupported_instrument_op(op)?);
+		i += 1;
+	    },
+	    (op @ (InstrumentOp::Pitch(_) | InstrumentOp::FixedNote(_)), next) => {
+		let byte = match op {
+		    InstrumentOp::Pitch(p) if *p >= 0 => *p as u8,
+		    InstrumentOp::FixedNote(n) if *n < 0x60 || (*n > 0x69 && *n < 0x80) => *n | 0x80,
+		    _ => return Err(format!("Instrument op {op} out of range")),
+		};
+		// Each pitch byte lasts one tick, so longer waits repeat the byte
+		match next {
+		    None => {
+			out.push(byte);
+			i += 1;
+		    },
+		    Some(InstrumentOp::WaitTicks(ticks)) if *ticks > 0 => {
+			out.extend(std::iter::repeat_n(byte, *ticks));
+			i += 2;
+		    },
+		    Some(_) => return Err(format!("Instrument op {op} must be followed by a wait")),
+		}
+	    },
+	    (op, _) => return Err(format!("Cannot encode instrument op {op} at this position")),
+	}
+    }
+    Ok(())
+}
+
+fn encode_instrument(instrument : &Instrument, basic_samples : &[BasicSample]) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    match instrument.ops.split_last() {
+	Some((InstrumentOp::Loop(body), prefix)) => {
+	    encode_instrument_ops(prefix, basic_samples, &mut out)?;
+	    let label = out.len();
+	    if body.is_empty() || label > 0xff {
+		return Err(format!("Cannot encode loop in instrument {instrument}"));
+	    }
+	    encode_instrument_ops(body, basic_samples, &mut out)?;
+	    out.push(INSTR_OP_LOOP);
+	    out.push(label as u8);
+	},
+	_ => {
+	    encode_instrument_ops(&instrument.ops, basic_samples, &mut out)?;
+	    out.push(INSTR_OP_COMPLETED);
+	},
+    }
+    Ok(out)
+}
+
+fn encode_volume_specs(specs : &[VolumeSpec], duration : &mut usize, out : &mut Vec<u8>) -> Result<(), String> {
+    for spec in specs {
+	if spec.volume > 64 || spec.duration > 0xff {
+	    return Err(format!("Volume envelope step {spec} out of range"));
+	}
+	if spec.duration != *duration {
+	    out.push(TIMBRE_OP_SUSTAIN);
+	    out.push(spec.duration as u8);
+	    *duration = spec.duration;
+	}
+	out.push(spec.volume);
+    }
+    Ok(())
+}
+
+fn encode_timbre(timbre : &Timbre) -> Result<Vec<u8>, String> {
+    let vibrato = timbre.vibrato;
+    if timbre.instrument == Some(0x80) || timbre.vibrato_delay > 0xff
+	|| i8::try_from(vibrato.slope).is_err() || i8::try_from(vibrato.depth).is_err() {
+	    return Err(format!("Timbre {timbre} out of range"));
+	}
+    if timbre.vol.attack.is_empty() && timbre.vol.sustain.is_empty() {
+	return Err("Timbre needs a non-empty volume envelope".to_string());
+    }
+    let mut out = vec![timbre.envelope_speed,
+		       timbre.instrument.unwrap_or(0x80),
+		       vibrato.slope as i8 as u8,
+		       vibrato.depth as i8 as u8,
+		       timbre.vibrato_delay as u8];
+    let mut duration = timbre.envelope_speed as usize;
+    encode_volume_specs(&timbre.vol.attack, &mut duration, &mut out)?;
+    if timbre.vol.sustain.is_empty() {
+	out.push(TIMBRE_OP_COMPLETED);
+    } else {
+	// Loop labels are offset by 5 against the start of the timbre
+	let label = out.len() + 5;
+	if label > 0xff {
+	    return Err(format!("Attack envelope too long in timbre {timbre}"));
+	}
+	encode_volume_specs(&timbre.vol.sustain, &mut duration, &mut out)?;
+	out.push(TIMBRE_OP_LOOP);
+	out.push(label as u8);
+    }
+    Ok(out)
+}
+
+/// `duration` tracks the current note duration within the monopattern
+fn encode_mpop(op : &MPOp, duration : &mut usize, out : &mut Vec<u8>) -> Result<(), String> {
+    if op.pticks == 0 || op.pticks > 0x100 {
+	return Err(format!("Monopattern op {op} out of range"));
+    }
+    let note = match op.note {
+	None => {
+	    out.push(MP_OP_SET_SPEED_WAIT);
+	    out.push((op.pticks - 1) as u8);
+	    *duration = op.pticks;
+	    return Ok(());
+	},
+	Some(note) => note,
+    };
+    // Notes 0xfd-0xff would be read as monopattern ops
+    if note.note < i8::MIN as isize || note.note > -4 && note.note < 0 || note.note > i8::MAX as isize {
+	return Err(format!("Monopattern op {op}: note out of range"));
+    }
+    let mut raw_timbre = 0;
+    let mut effect = None;
+    if note.note > 0 {
+	let timbre = match note.timbre {
+	    Some(t) if t.timbre < 0x20 => t,
+	    _ => return Err(format!("Monopattern op {op} needs a timbre in 0..32")),
+	};
+	raw_timbre = timbre.timbre as u8;
+	if let Some(i) = timbre.instrument {
+	    if i > i8::MAX as usize {
+		return Err(format!("Monopattern op {op}: instrument out of range"));
+	    }
+	    raw_timbre |= 0x40;
+	    effect = Some(i as isize);
+	}
+	if let Some(p) = note.portando {
+	    if effect.is_some_and(|e| e != -p) || -p < i8::MIN as isize || -p > i8::MAX as isize {
+		return Err(format!("Monopattern op {op}: portando incompatible with instrument"));
+	    }
+	    raw_timbre |= 0x20;
+	    effect = Some(-p);
+	}
+    } else if note.timbre.is_some() || note.portando.is_some() {
+	return Err(format!("Monopattern op {op}: only positive notes can set timbres"));
+    }
+    if op.pticks != *duration {
+	out.push(MP_OP_SET_SPEED);
+	out.push((op.pticks - 1) as u8);
+	*duration = op.pticks;
+    }
+    out.push(note.note as i8 as u8);
+    out.push(raw_timbre);
+    if let Some(e) = effect {
+	out.push(e as i8 as u8);
+    }
+    Ok(())
+}
+
+fn encode_monopattern(monopattern : &Monopattern) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    let mut duration = 1;
+    for op in monopattern.ops.iter() {
+	encode_mpop(op, &mut duration, &mut out)?;
+    }
+    out.push(MP_OP_END);
+    Ok(out)
+}
+
+fn encode_division(division : &Division) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    for chan in division.channels.iter() {
+	let effect = match chan.effect {
+	    DivisionEffect::TimbreAdjust(t) if t < 0x80                     => Some(t as u8),
+	    DivisionEffect::FullStop                                        => Some(0x80),
+	    DivisionEffect::ChannelSpeed(s) if (1..=0x10).contains(&s)      => Some(0xe0 | (s - 1) as u8),
+	    DivisionEffect::ChannelVolume(v) if (0x31..=0x40).contains(&v)  => Some(0xf0 | (64 - v) as u8),
+	    _                                                               => None,
+	};
+	match effect {
+	    Some(effect) if chan.monopat <= 0xff && i8::try_from(chan.transpose).is_ok() => {
+		out.push(chan.monopat as u8);
+		out.push(chan.transpose as i8 as u8);
+		out.push(effect);
+	    },
+	    _ => return Err(format!("Division channel {chan} out of range")),
+	}
+    }
+    Ok(out)
+}
+
+fn encode_songinfo(songinfo : &SongInfo) -> Result<Vec<u8>, String> {
+    if songinfo.first_division > 0xffff || songinfo.last_division > 0xffff || songinfo.speed > 0xffff {
+	return Err(format!("Song info {songinfo} out of range"));
+    }
+    let mut out = vec![];
+    decode::push_u16(&mut out, songinfo.first_division as u16);
+    decode::push_u16(&mut out, songinfo.last_division as u16);
+    decode::push_u16(&mut out, songinfo.speed as u16);
+    Ok(out)
+}
+
+// --------------------------------------------------------------------------------
+// Section encoding
+
+/// Encodes a section of fixed-size entries.  Entries that are unchanged from the original
+/// are copied from there, as is the entire section if nothing changed.
+fn encode_fixed_section<T : PartialEq>(entries : &[T],
+				       size : usize,
+				       original : Option<(&[u8], Vec<T>)>,
+				       encode : impl Fn(&T) -> Result<Vec<u8>, String>) -> Result<Vec<u8>, String> {
+    if let Some((raw, decoded)) = &original {
+	if decoded[..] == *entries {
+	    return Ok(raw.to_vec());
+	}
+    }
+    let mut out = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+	match &original {
+	    Some((raw, decoded)) if decoded.get(i) == Some(entry) => {
+		out.extend_from_slice(&raw[i * size..(i + 1) * size]);
+	    }
+	    _ => out.extend(encode(entry)?),
+	}
+    }
+    Ok(out)
+}
+
+/// Encodes a table-indexed section that will be placed at `pos`, returning the section and
+/// the number of table entries.
+fn encode_table_section<T : PartialEq>(pos : usize,
+				       entries : &[T],
+				       original : Option<(&RawSong, RawSection, Vec<T>)>,
+				       encode : impl Fn(&T) -> Result<Vec<u8>, String>) -> Result<(Vec<u8>, usize), String> {
+    let mut unchanged = vec![];
+    if let Some((rawsong, sec, decoded)) = &original {
+	let table = rawsong.table_index(*sec);
+	if decoded[..] == *entries {
+	    // Unchanged: copy, but the table offsets are relative to the COSO block
+	    let mut out = rawsong.data[sec.pos..sec.end].to_vec();
+	    for i in 0..sec.num {
+		let offset = (table.offset_of(i) + pos).checked_sub(sec.pos).filter(|&o| o <= 0xffff);
+		match offset {
+		    Some(offset) => decode::put_u16(&mut out, 2 * i, offset as u16),
+		    None         => return Err("Song too large for 16 bit table offsets".to_string()),
+		}
+	    }
+	    return Ok((out, sec.num));
+	}
+	for (element, entry) in table.into_iter().zip(decoded.iter()) {
+	    unchanged.push((entry, &rawsong.data[element.start..element.end()]));
+	}
+    }
+    let mut bodies = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+	match unchanged.get(i) {
+	    Some((orig, raw)) if *orig == entry => bodies.push(raw.to_vec()),
+	    _                                  => bodies.push(encode(entry)?),
+	}
+    }
+    let mut table = vec![];
+    let mut offset = pos + 2 * entries.len();
+    for body in bodies.iter() {
+	if offset > 0xffff {
+	    return Err("Song too large for 16 bit table offsets".to_string());
+	}
+	decode::push_u16(&mut table, offset as u16);
+	offset += body.len();
+    }
+    table.extend(bodies.concat());
+    Ok((table, entries.len()))
+}
+
+impl Song {
+    /// Encodes the song as a COSO block.  Parts of the song that have not changed since it
+    /// was loaded are reproduced byte by byte.
+    pub fn to_coso(&self) -> Result<Vec<u8>, String> {
+	if self.instruments.is_empty() || self.timbres.is_empty() || self.monopatterns.is_empty() || self.divisions.is_empty() {
+	    return Err("Songs need at least one instrument, timbre, monopattern, and division".to_string());
+	}
+	let original = self.origin.as_ref().map(|o| RawSong::new(o.offset, &o.raw));
+	let original = original.as_ref();
+	let mut out = match original {
+	    Some(raw) => raw.data[..raw.instruments.pos].to_vec(),
+	    None      => {
+		let mut header = vec![0; COSO_HEADER_SIZE];
+		header[0..4].copy_from_slice(b"COSO");
+		header[32..36].copy_from_slice(b"TFMX");
+		header
+	    }
+	};
+	let orig_samples = original.map(|raw| raw.basic_samples());
+	let samples_unchanged = orig_samples.as_ref() == Some(&self.basic_samples);
+
+	// Instruments refer to samples by index, so we can only copy them if the samples are unchanged
+	let instruments_pos = out.len();
+	let (instruments, num_instruments) = encode_table_section(
+	    instruments_pos, &self.instruments,
+	    original.filter(|_| samples_unchanged).map(|raw| (raw, raw.instruments, raw.instruments(&self.basic_samples))),
+	    |i| encode_instrument(i, &self.basic_samples))?;
+	out.extend(instruments);
+
+	let timbres_pos = out.len();
+	let (timbres, num_timbres) = encode_table_section(
+	    timbres_pos, &self.timbres,
+	    original.map(|raw| (raw, raw.timbres, raw.timbres())),
+	    encode_timbre)?;
+	out.extend(timbres);
+
+	let monopatterns_pos = out.len();
+	let (monopatterns, num_monopatterns) = encode_table_section(
+	    monopatterns_pos, &self.monopatterns,
+	    original.map(|raw| (raw, raw.monopatterns, raw.monopatterns())),
+	    encode_monopattern)?;
+	out.extend(monopatterns);
+
+	let divisions_pos = out.len();
+	out.extend(encode_fixed_section(
+	    &self.divisions, 12,
+	    original.map(|raw| (&raw.data[raw.divisions.pos..raw.divisions.end], raw.divisions())),
+	    encode_division)?);
+
+	// Only the first subsong is part of our model; keep any others as they were
+	let subsongs_pos = out.len();
+	let mut subsongs = original.map_or(vec![], |raw| raw.songs());
+	match subsongs.first_mut() {
+	    Some(s) => *s = self.songinfo,
+	    None    => subsongs.push(self.songinfo),
+	}
+	out.extend(encode_fixed_section(
+	    &subsongs, 6,
+	    original.map(|raw| (&raw.data[raw.subsongs.pos..raw.subsongs.end], raw.songs())),
+	    encode_songinfo)?);
+
+	let samples_pos = out.len();
+	out.extend(encode_fixed_section(
+	    &self.basic_samples, 10,
+	    original.zip(orig_samples).map(|(raw, s)| (&raw.data[raw.samples.pos..raw.samples.end], s)),
+	    encode_sample)?);
+
+	let end_pos = out.len();
+	for (offset, pos) in [(4, instruments_pos), (8, timbres_pos), (12, monopatterns_pos),
+			      (16, divisions_pos), (20, subsongs_pos), (24, samples_pos), (28, end_pos)] {
+	    decode::put_u32(&mut out, offset, pos as u32);
+	}
+	for (offset, num) in [(36, num_instruments - 1), (38, num_timbres - 1), (40, num_monopatterns - 1),
+			      (42, self.divisions.len() - 1), (48, subsongs.len()), (50, self.basic_samples.len())] {
+	    decode::put_u16(&mut out, offset, num as u16);
+	}
+	Ok(out)
+    }
+}
+
 // --------------------------------------------------------------------------------
 // Finding song data
 
@@ -1045,6 +1637,8 @@ impl<'a> SongSeeker<'a> {
 	    }
 	}
 
+	let slot_len = usize::min(decode::u32(data, 28) as usize, data.len());
+
 	// Found a song header!
 	return Some(Song{
 	    basic_samples,
@@ -1053,6 +1647,142 @@ impl<'a> SongSeeker<'a> {
 	    monopatterns,
 	    divisions,
 	    songinfo : songs[0],
+	    origin : Some(SongOrigin {
+		offset : npos,
+		slot_len,
+		raw : data[..slot_len].to_vec(),
+	    }),
 	});
     }
 }
+
+// ================================================================================
+// Tests
+
+/// Small song that uses every kind of song data, for tests
+#[cfg(test)]
+pub(crate) fn test_song() -> Song {
+    let sample0 = BasicSample { attack : SampleRange::new(0x100, 0x200), looping : Some(SampleRange::new(0x180, 0x80)) };
+    let sample1 = BasicSample { attack : SampleRange::new(0x400, 0x40), looping : None };
+    let note = |note, timbre : Option<usize>, instrument, portando, pticks| MPOp {
+	note : Some(MPNote { note, timbre : timbre.map(|timbre| MPTimbre { timbre, instrument }), portando }),
+	pticks,
+    };
+    let chan = |monopat, transpose, effect| DivisionChannel { monopat, transpose, effect };
+    Song {
+	basic_samples : vec![sample0, sample1],
+	instruments : vec![
+	    Instrument { ops : vec![InstrumentOp::StopSample, InstrumentOp::Sample(sample0),
+				    InstrumentOp::Pitch(0), InstrumentOp::WaitTicks(1),
+				    InstrumentOp::Pitch(12)] },
+	    Instrument { ops : vec![InstrumentOp::Sample(sample1), InstrumentOp::ResetVolume,
+				    InstrumentOp::Loop(vec![InstrumentOp::FixedNote(0x30), InstrumentOp::WaitTicks(1),
+							    InstrumentOp::Pitch(1), InstrumentOp::WaitTicks(1)])] },
+	],
+	timbres : vec![
+	    Timbre { envelope_speed : 2, instrument : Some(0), vibrato : Vibrato { slope : 1, depth : -2 }, vibrato_delay : 3,
+		     vol : VolumeEnvelope {
+			 attack : vec![VolumeSpec { volume : 64, duration : 2 }, VolumeSpec { volume : 48, duration : 4 }],
+			 sustain : vec![VolumeSpec { volume : 32, duration : 4 }, VolumeSpec { volume : 16, duration : 4 }],
+		     } },
+	    Timbre { envelope_speed : 1, instrument : Some(1), vibrato : Vibrato { slope : 0, depth : 0 }, vibrato_delay : 0,
+		     vol : VolumeEnvelope {
+			 attack : vec![VolumeSpec { volume : 40, duration : 1 }],
+			 sustain : vec![],
+		     } },
+	],
+	monopatterns : vec![
+	    Monopattern { ops : vec![note(24, Some(0), Some(1), None, 4),
+				     MPOp { note : None, pticks : 2 },
+				     note(0, None, None, None, 2),
+				     note(26, Some(0), None, Some(-3), 2)] },
+	],
+	divisions : vec![
+	    Division { channels : [chan(0, 0, DivisionEffect::TimbreAdjust(0)),
+				   chan(0, -12, DivisionEffect::ChannelSpeed(3)),
+				   chan(0, 5, DivisionEffect::ChannelVolume(60)),
+				   chan(0, 0, DivisionEffect::TimbreAdjust(1))] },
+	    Division { channels : [chan(0, 2, DivisionEffect::TimbreAdjust(1)); 4] },
+	    // Not played (after songinfo.last_division)
+	    Division { channels : [DivisionChannel::empty(); 4] },
+	],
+	songinfo : SongInfo { first_division : 0, last_division : 1, speed : 6 },
+	origin : None,
+    }
+}
+
+#[cfg(test)]
+fn assert_same_song(expected : &Song, actual : &Song) {
+    assert_eq!(expected.basic_samples, actual.basic_samples);
+    assert_eq!(expected.instruments, actual.instruments);
+    assert_eq!(expected.timbres, actual.timbres);
+    assert_eq!(expected.monopatterns, actual.monopatterns);
+    assert_eq!(expected.divisions, actual.divisions);
+    assert_eq!(expected.songinfo, actual.songinfo);
+}
+
+#[cfg(test)]
+#[test]
+fn test_coso_roundtrip() {
+    let song = test_song();
+    let coso = song.to_coso().unwrap();
+    let decoded = seeker(&coso, 0).next().unwrap();
+    assert_same_song(&song, &decoded);
+    assert_eq!(decoded.source_offset(), Some(0));
+    assert_eq!(decoded.source_len(), coso.len());
+    assert_eq!(decoded.to_coso().unwrap(), coso);
+}
+
+#[cfg(test)]
+#[test]
+fn test_coso_unmodified_is_byte_exact() {
+    let mut coso = test_song().to_coso().unwrap();
+    // Bytes that we don't model: unknown header field, and an unsupported instrument op
+    // (E4(5) in place of the two pitches)
+    coso[44] = 0x12;
+    let instr0 = decode::u16(&coso, decode::u32(&coso, 4) as usize) as usize;
+    coso[instr0 + 2] = 0xe4;
+    coso[instr0 + 3] = 5;
+
+    let mut song = seeker(&coso, 0).next().unwrap();
+    assert_eq!(song.instruments[0].ops.last(), Some(&InstrumentOp::Unsupported("E4(5)".to_string())));
+    assert_eq!(song.to_coso().unwrap(), coso);
+
+    // Editing a monopattern keeps the other bytes intact
+    song.delete_monopattern_op(0, 3).unwrap();
+    let edited = song.to_coso().unwrap();
+    let monopatterns_pos = decode::u32(&coso, 12) as usize;
+    assert_eq!(edited.len(), coso.len() - 3);
+    assert_eq!(edited[44], 0x12);
+    assert_eq!(edited[COSO_HEADER_SIZE..monopatterns_pos], coso[COSO_HEADER_SIZE..monopatterns_pos]);
+    assert_eq!(edited[edited.len() - 40..], coso[coso.len() - 40..]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_song_editing() {
+    let mut song = test_song();
+    let op = MPOp { note : Some(MPNote { note : 30, timbre : Some(MPTimbre { timbre : 0, instrument : None }), portando : None }),
+		    pticks : 3 };
+    song.insert_monopattern_op(0, 1, op).unwrap();
+    song.move_division(1, 0).unwrap();
+    let mut vol = song.timbres[0].vol.clone();
+    vol.sustain.clear();
+    song.set_volume_envelope(0, vol).unwrap();
+
+    let decoded = seeker(&song.to_coso().unwrap(), 0).next().unwrap();
+    assert_same_song(&song, &decoded);
+    assert_eq!(decoded.monopatterns[0].ops[1], op);
+    assert_eq!(decoded.divisions[0].channels[0].transpose, 2);
+
+    // Rejected edits leave the song unchanged
+    let bad_timbre = MPOp { note : Some(MPNote { note : 30, timbre : Some(MPTimbre { timbre : 2, instrument : None }), portando : None }),
+			    pticks : 3 };
+    assert!(song.insert_monopattern_op(0, 0, bad_timbre).is_err());
+    let bad_note = MPOp { note : Some(MPNote { note : -1, timbre : None, portando : None }), pticks : 1 };
+    assert!(song.insert_monopattern_op(0, 0, bad_note).is_err());
+    assert!(song.delete_monopattern_op(0, 5).is_err());
+    assert!(song.move_division(0, 3).is_err());
+    assert!(song.set_instrument(0, Instrument { ops : vec![InstrumentOp::ResetVolume] }).is_err());
+    assert_same_song(&song, &decoded);
+}
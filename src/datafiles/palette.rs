@@ -1,11 +1,39 @@
 // Copyright (C) 2022 Christoph Reichenbach (creichen@gmail.com)
 // Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
 
-use sdl2::pixels::Color;
 use crate::datafiles::decode;
 
 use super::amberdev::Amberdev;
 
+/// 8 bit RGBA colour
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r : u8,
+    pub g : u8,
+    pub b : u8,
+    pub a : u8,
+}
+
+impl Color {
+    pub const BLACK : Color = Color::rgb(0, 0, 0);
+    pub const WHITE : Color = Color::rgb(0xff, 0xff, 0xff);
+
+    pub const fn rgb(r : u8, g : u8, b : u8) -> Color {
+	Color { r, g, b, a : 0xff }
+    }
+
+    pub const fn rgba(r : u8, g : u8, b : u8, a : u8) -> Color {
+	Color { r, g, b, a }
+    }
+}
+
+#[cfg(feature = "sdl")]
+impl From<Color> for sdl2::pixels::Color {
+    fn from(c : Color) -> Self {
+	sdl2::pixels::Color::RGBA(c.r, c.g, c.b, c.a)
+    }
+}
+
 #[derive(Clone,Debug)]
 pub struct Palette {
     pub colors : Vec<Color>,
@@ -113,6 +141,31 @@ impl Palette {
 
     pub fn len(&self) -> usize { self.colors.len() }
 
+    /// Index of the palette colour closest to `col`, plus the squared RGBA distance (0 if exact).
+    /// Transparent colours (alpha < 0x80) only match transparent palette entries, if there are any,
+    /// and then match exactly; otherwise they match the nearest opaque colour, never exactly.
+    pub fn nearest(&self, col : &Color) -> (usize, u32) {
+	let transparent = col.a < 0x80;
+	let has_transparency = self.colors.iter().any(|c| c.a < 0x80);
+	let mut best = (0, u32::MAX);
+	for (i, c) in self.colors.iter().enumerate() {
+	    if has_transparency && transparent != (c.a < 0x80) {
+		continue;
+	    }
+	    let dist = if transparent && has_transparency { 0 } else {
+		let dr = c.r as i32 - col.r as i32;
+		let dg = c.g as i32 - col.g as i32;
+		let db = c.b as i32 - col.b as i32;
+		let da = c.a as i32 - col.a as i32;
+		(dr * dr + dg * dg + db * db + da * da) as u32
+	    };
+	    if dist < best.1 {
+		best = (i, dist);
+	    }
+	}
+	return best;
+    }
+
     pub fn copy_into(&mut self, other: &Palette) {
 	assert!(self.len() == other.len());
 	self.colors.copy_from_slice(&other.colors);
@@ -214,3 +267,19 @@ impl Palette {
 	Self { colors: Self::TEST_PALETTE_COLORS.to_vec(), }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn test_nearest_transparent() {
+    let opaque = Palette { colors : vec![Color { r : 0, g : 0, b : 0, a : 0xff },
+					 Color { r : 0xff, g : 0xff, b : 0xff, a : 0xff }] };
+    let clear = Color { r : 0xff, g : 0xff, b : 0xff, a : 0 };
+    let (index, dist) = opaque.nearest(&clear);
+    assert_eq!(1, index);
+    assert!(dist > 0);
+
+    let mut with_transparency = opaque.clone();
+    with_transparency.colors.push(Color { r : 0, g : 0, b : 0, a : 0 });
+    assert_eq!((2, 0), with_transparency.nearest(&clear));
+    assert_eq!((1, 0), with_transparency.nearest(&opaque.colors[1]));
+}
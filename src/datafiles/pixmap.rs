@@ -6,13 +6,13 @@ use log::{Level, log_enabled, trace, debug, info, warn, error};
 #[allow(unused)]
 use crate::{ptrace, pdebug, pinfo, pwarn, perror};
 
-use sdl2::{render::{Texture, TextureCreator}, pixels::PixelFormatEnum};
-
 use crate::datafiles::decode;
-use crate::datafiles::palette::Palette;
+use crate::datafiles::palette::{Palette, Color};
+
+use serde::{Deserialize, Serialize};
 
 /// An indexed pixel map without a palette
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct IndexedPixmap {
     pub width : usize,
     pub height : usize,
@@ -53,6 +53,51 @@ pub fn new(src : &[u8], width : usize, height : usize, bitplanes : usize) -> Ind
     return result;
 }
 
+/// Inverse of `new`: encodes into interleaved bitplanes (one 16 bit word per bitplane and 16 pixels)
+pub fn encode(pixmap : &IndexedPixmap, bitplanes : usize) -> Result<Vec<u8>, String> {
+    if !(1..=8).contains(&bitplanes) {
+	return Err(format!("Cannot encode {bitplanes} bitplanes (must be 1 to 8)"));
+    }
+    if let Some(max) = pixmap.pixels.iter().max() {
+	if (*max as usize) >= (1 << bitplanes) {
+	    return Err(format!("Colour index {max} does not fit into {bitplanes} bitplanes"));
+	}
+    }
+    let width = pixmap.width;
+    let words_per_line = (width + 15) >> 4;
+    let mut result = vec![];
+    for y in 0..pixmap.height {
+	for xword_index in 0..words_per_line {
+	    for bp in 0..bitplanes {
+		let bitplane_value : u8 = 1 << bp;
+		let mut word = 0;
+		for xrel in 0..16 {
+		    let x = (xword_index * 16) + xrel;
+		    word <<= 1;
+		    if x < width && pixmap.pixels[y * width + x] & bitplane_value != 0 {
+			word |= 1;
+		    }
+		}
+		decode::push_u16(&mut result, word);
+	    }
+	}
+    }
+    return Ok(result);
+}
+
+/// Inverse of `new_icon_frame`: encodes with width, height, #bitplanes header
+pub fn encode_icon_frame(pixmap : &IndexedPixmap, bitplanes : usize) -> Result<Vec<u8>, String> {
+    if pixmap.width == 0 || pixmap.height == 0 || pixmap.width > 0x10000 || pixmap.height > 0x10000 {
+	return Err(format!("Cannot encode icon of size {}x{}", pixmap.width, pixmap.height));
+    }
+    let mut result = vec![];
+    decode::push_u16(&mut result, (pixmap.width - 1) as u16);
+    decode::push_u16(&mut result, (pixmap.height - 1) as u16);
+    decode::push_u16(&mut result, bitplanes as u16);
+    result.append(&mut encode(pixmap, bitplanes)?);
+    return Ok(result);
+}
+
 pub fn icon_header(src : &[u8]) -> (usize, usize, usize, usize) {
     let width = 1 + decode::u16(src, 0) as usize;
     let height = 1 + decode::u16(src, 2) as usize;
@@ -80,6 +125,21 @@ pub fn icon_len(src : &[u8]) -> usize {
     return size;
 }
 
+/// How closely a true-colour image matched the palette it was mapped onto
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ColorMatch {
+    /// Number of pixels whose colour is not in the palette
+    pub inexact_pixels : usize,
+    /// Largest squared RGB distance between a pixel and its palette colour
+    pub max_distance : u32,
+}
+
+impl ColorMatch {
+    pub fn is_exact(&self) -> bool {
+	return self.inexact_pixels == 0;
+    }
+}
+
 impl IndexedPixmap {
     pub fn empty(width : usize, height: usize) -> IndexedPixmap {
 	IndexedPixmap {
@@ -108,6 +168,27 @@ impl IndexedPixmap {
 	}
     }
 
+    /// Maps true-colour pixels onto the nearest palette colours
+    pub fn from_colors(width : usize, height : usize, colors : &[Color], palette : &Palette) -> (IndexedPixmap, ColorMatch) {
+	assert_eq!(width * height, colors.len());
+	let mut color_match = ColorMatch::default();
+	let pixels = colors.iter().map(|c| {
+	    let (index, dist) = palette.nearest(c);
+	    if dist > 0 {
+		color_match.inexact_pixels += 1;
+		color_match.max_distance = u32::max(color_match.max_distance, dist);
+	    }
+	    index as u8
+	}).collect();
+	return (IndexedPixmap { width, height, pixels }, color_match);
+    }
+
+    /// Smallest number of bitplanes that can represent all colour indices in use
+    pub fn min_bitplanes(&self) -> usize {
+	let max = self.pixels.iter().max().map(|m| *m as usize).unwrap_or(0);
+	return (usize::BITS - max.leading_zeros()) as usize;
+    }
+
     pub fn print(&self) {
 	for y in 0..self.height {
 	    for x in 0..self.width {
@@ -157,10 +238,29 @@ impl IndexedPixmap {
 	dest.blit_into(src, x, y);
 	return dest;
     }
+
+    /// Like blit_into, but skips all source pixels with the transparent colour index
+    pub fn blit_transparent_into(&mut self, src : &IndexedPixmap, x : usize, y : usize, transparent : u8) {
+	if x >= self.width || y >= self.height {
+	    return;
+	}
+	let src_width = usize::min(self.width - x, src.width);
+	let src_height = usize::min(self.height - y, src.height);
+	for h in 0..src_height {
+	    let dest_start = x + (y + h) * self.width;
+	    let src_start = src.width * h;
+	    for (dest, pixel) in self.pixels[dest_start..dest_start + src_width].iter_mut().zip(&src.pixels[src_start..src_start + src_width]) {
+		if *pixel != transparent {
+		    *dest = *pixel;
+		}
+	    }
+	}
+    }
 }
 
 // ================================================================================
 
+/// True-colour pixel map; four bytes per pixel, RGBA8888 in little-endian order (i.e., A, B, G, R)
 #[derive(Clone)]
 pub struct Pixmap {
     pub width : usize,
@@ -168,12 +268,44 @@ pub struct Pixmap {
     pub data : Vec<u8>,
 }
 
+#[cfg(feature = "sdl")]
 impl Pixmap {
-    pub fn as_texture<'a, T>(&self, tc : &'a TextureCreator<T>) -> Texture<'a> {
-	let mut texture = tc.create_texture_static(PixelFormatEnum::RGBA8888, self.width as u32, self.height as u32).unwrap();
+    pub fn as_texture<'a, T>(&self, tc : &'a sdl2::render::TextureCreator<T>) -> sdl2::render::Texture<'a> {
+	let mut texture = tc.create_texture_static(sdl2::pixels::PixelFormatEnum::RGBA8888, self.width as u32, self.height as u32).unwrap();
 	let data = self.data.clone();
 	texture.update(None, &data, (self.width * 4) as usize).unwrap();
 	return texture;
     }
 }
 
+
+#[cfg(test)]
+#[test]
+fn test_encode_roundtrip() {
+    let width = 19;
+    let height = 3;
+    let pixels : Vec<u8> = (0..width * height).map(|i| ((i * 7) % 32) as u8).collect();
+    let pixmap = IndexedPixmap { width, height, pixels };
+    assert_eq!(5, pixmap.min_bitplanes());
+
+    let data = encode_icon_frame(&pixmap, 5).unwrap();
+    assert_eq!(data.len(), icon_len(&data));
+    let decoded = new_icon_frame(&data);
+    assert_eq!((width, height), (decoded.width, decoded.height));
+    assert_eq!(pixmap.pixels, decoded.pixels);
+
+    assert!(encode(&pixmap, 4).is_err());
+    assert!(encode(&pixmap, 0).is_err());
+    assert!(encode(&pixmap, 9).is_err());
+    assert!(encode_icon_frame(&pixmap, 64).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_from_colors() {
+    let palette = Palette::test_palette().with_transparency(0);
+    let colors = [Color::rgba(0, 0, 0, 0), Color::rgb(0xaa, 0, 0), Color::rgb(0xff, 0xff, 0xf0)];
+    let (pixmap, color_match) = IndexedPixmap::from_colors(3, 1, &colors, &palette);
+    assert_eq!(vec![0, 4, 0xf], pixmap.pixels);
+    assert_eq!(ColorMatch { inexact_pixels : 1, max_distance : 0xf * 0xf }, color_match);
+}
@@ -0,0 +1,333 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Progression: which keywords, items and quest flags enable which interactions, and which can be obtained at all
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use serde_json::{json, Value};
+
+use super::AmberstarFiles;
+use super::chardata::{CharData, InteractionTrigger, Reaction};
+use super::map::{EventOp, Map, NPCAction};
+use super::string_fragment_table::StringFragmentTable;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Node {
+    /// Character that the party can meet on some map
+    Character(usize),
+    /// (map, event); events are numbered from 1, as in hotspots
+    Event(usize, usize),
+    /// (character, interaction list, index)
+    Interaction(usize, usize, usize),
+    /// Dictionary word, by text: several string fragments can have the same text
+    Keyword(String),
+    /// Key ID of an item
+    Key(usize),
+    /// Interaction status flag, set by `Reaction::CompleteQuest`
+    QuestFlag(usize),
+}
+
+impl Node {
+    pub fn id(&self) -> String {
+	match self {
+	    Node::Character(c)          => format!("char_{c}"),
+	    Node::Event(map, e)         => format!("event_{map}_{e}"),
+	    Node::Interaction(c, l, i)  => format!("interaction_{c}_{l}_{i}"),
+	    Node::Keyword(w)            => format!("keyword_{w}"),
+	    Node::Key(k)                => format!("key_{k}"),
+	    Node::QuestFlag(f)          => format!("flag_{f}"),
+	}
+    }
+
+    fn kind(&self) -> &'static str {
+	match self {
+	    Node::Character(_)          => "character",
+	    Node::Event(..)             => "event",
+	    Node::Interaction(..)       => "interaction",
+	    Node::Keyword(_)            => "keyword",
+	    Node::Key(_)                => "key",
+	    Node::QuestFlag(_)          => "quest-flag",
+	}
+    }
+
+    /// Interactions need all of their predecessors (the character, and the keyword / item / flag that
+    /// triggers them); everything else needs any one
+    fn needs_all(&self) -> bool {
+	matches!(self, Node::Interaction(..))
+    }
+
+    /// Something that the party obtains and that other nodes may require
+    fn is_requirement(&self) -> bool {
+	matches!(self, Node::Keyword(_) | Node::Key(_) | Node::QuestFlag(_))
+    }
+}
+
+/// Dependency graph: an edge (a, b) means that a is needed for, or leads to, b.  Events and characters that
+/// appear on maps are the starting points.  Chest contents are not decoded yet, so items from chests count as
+/// unobtainable.
+#[derive(Default)]
+pub struct ProgressionGraph {
+    pub labels : BTreeMap<Node, String>,
+    pub edges : BTreeSet<(Node, Node)>,
+    roots : BTreeSet<Node>,
+    pub reachable : BTreeSet<Node>,
+}
+
+impl ProgressionGraph {
+    pub fn new(data : &AmberstarFiles) -> ProgressionGraph {
+	return ProgressionGraph::build(&data.amberdev.string_fragments, &data.maps, &data.chardata);
+    }
+
+    fn build(fragments : &StringFragmentTable, maps : &[Map], chardata : &[CharData]) -> ProgressionGraph {
+	let mut graph = ProgressionGraph::default();
+	let keyword = |w : u16| Node::Keyword(fragments.get(w));
+
+	for c in chardata.iter() {
+	    for (_, item) in c.items.iter().filter(|(count, item)| *count > 0 && item.key_id().id != 0) {
+		graph.add_node(Node::Key(item.key_id().id), &item.name);
+	    }
+	}
+
+	for (nr, map) in maps.iter().enumerate() {
+	    for (i, event) in map.event_table.iter().enumerate() {
+		for op in event.program() {
+		    if let EventOp::LearnKeyword(k) = op {
+			let event_node = Node::Event(nr, i + 1);
+			graph.add_node(event_node.clone(), &format!("map {nr} ({}) event {:02x}", map.name, i + 1));
+			graph.roots.insert(event_node.clone());
+			graph.add_node(keyword(*k as u16), &fragments.get(*k as u16));
+			graph.add_edge(event_node, keyword(*k as u16));
+		    }
+		}
+	    }
+	    for npc in map.npcs.iter() {
+		match npc.talk_action {
+		    NPCAction::Chat(c) if !npc.hostile() && c < chardata.len() => {
+			graph.roots.insert(Node::Character(c));
+		    },
+		    _ => {},
+		}
+	    }
+	}
+
+	for (nr, c) in chardata.iter().enumerate() {
+	    if c.interactions.iter().all(|l| l.is_empty()) {
+		continue;
+	    }
+	    let char_node = Node::Character(nr);
+	    graph.add_node(char_node.clone(), &c.name);
+	    for (list, interactions) in c.interactions.iter().enumerate() {
+		for (index, interaction) in interactions.iter().enumerate() {
+		    let node = Node::Interaction(nr, list, index);
+		    graph.add_node(node.clone(), &format!("{}: {}", c.name, interaction.trigger.show(fragments)));
+		    graph.add_edge(char_node.clone(), node.clone());
+		    if list == 1 && c.interaction_status_flag != 0 {
+			graph.add_node(Node::QuestFlag(c.interaction_status_flag), &format!("flag {:x}", c.interaction_status_flag));
+			graph.add_edge(Node::QuestFlag(c.interaction_status_flag), node.clone());
+		    }
+		    match interaction.trigger {
+			InteractionTrigger::Ask(w) => {
+			    graph.add_node(keyword(w), &fragments.get(w));
+			    graph.add_edge(keyword(w), node.clone());
+			},
+			InteractionTrigger::Show(k)
+			| InteractionTrigger::Give(k) if k.id != 0 => {
+			    graph.add_node(Node::Key(k.id), &format!("key {:x}", k.id));
+			    graph.add_edge(Node::Key(k.id), node.clone());
+			},
+			_ => {},
+		    }
+		    for reaction in &interaction.reactions {
+			match *reaction {
+			    Reaction::TeachWord(w) => {
+				graph.add_node(keyword(w), &fragments.get(w));
+				graph.add_edge(node.clone(), keyword(w));
+			    },
+			    Reaction::GiveItem(i) => match c.items.get(i) {
+				Some((_, item)) if item.key_id().id != 0 => {
+				    graph.add_node(Node::Key(item.key_id().id), &item.name);
+				    graph.add_edge(node.clone(), Node::Key(item.key_id().id));
+				},
+				_ => {},
+			    },
+			    Reaction::CompleteQuest(f) => {
+				graph.add_node(Node::QuestFlag(f), &format!("flag {f:x}"));
+				graph.add_edge(node.clone(), Node::QuestFlag(f));
+			    },
+			    _ => {},
+			}
+		    }
+		}
+	    }
+	}
+	graph.compute_reachable();
+	return graph;
+    }
+
+    /// Adds a node, unless it already exists
+    fn add_node(&mut self, node : Node, label : &str) {
+	self.labels.entry(node).or_insert_with(|| label.to_string());
+    }
+
+    fn add_edge(&mut self, from : Node, to : Node) {
+	self.edges.insert((from, to));
+    }
+
+    fn compute_reachable(&mut self) {
+	let mut preds : BTreeMap<&Node, Vec<&Node>> = BTreeMap::new();
+	for (from, to) in self.edges.iter() {
+	    preds.entry(to).or_default().push(from);
+	}
+	let mut reachable : BTreeSet<Node> = self.roots.clone();
+	let mut changed = true;
+	while changed {
+	    changed = false;
+	    for (node, node_preds) in preds.iter() {
+		if reachable.contains(*node) {
+		    continue;
+		}
+		let enabled = if node.needs_all() {
+		    node_preds.iter().all(|p| reachable.contains(*p))
+		} else {
+		    node_preds.iter().any(|p| reachable.contains(*p))
+		};
+		if enabled {
+		    reachable.insert((*node).clone());
+		    changed = true;
+		}
+	    }
+	}
+	self.reachable = reachable;
+    }
+
+    fn label(&self, node : &Node) -> &str {
+	self.labels.get(node).map_or("", |s| s.as_str())
+    }
+
+    /// Keywords, items and quest flags that something requires, but that the party cannot obtain
+    pub fn unreachable(&self) -> Vec<&Node> {
+	let required : BTreeSet<&Node> = self.edges.iter().map(|(from, _)| from).filter(|n| n.is_requirement()).collect();
+	return required.into_iter().filter(|n| !self.reachable.contains(*n)).collect();
+    }
+
+    /// What requires the node
+    pub fn required_by(&self, node : &Node) -> Vec<&Node> {
+	self.edges.iter().filter(|(from, _)| from == node).map(|(_, to)| to).collect()
+    }
+
+    pub fn describe(&self, node : &Node) -> String {
+	format!("{} '{}'", node.kind(), self.label(node))
+    }
+
+    pub fn to_dot(&self) -> String {
+	let mut s = String::new();
+	writeln!(s, "digraph progression {{").unwrap();
+	for (node, label) in self.labels.iter() {
+	    let shape = match node {
+		Node::Character(_) | Node::Event(..) => "box",
+		Node::Interaction(..)                => "ellipse",
+		_                                    => "diamond",
+	    };
+	    let style = if self.reachable.contains(node) { "" } else { ", style=dashed, color=red" };
+	    writeln!(s, "  {} [label={}, shape={shape}{style}];", dot_string(&node.id()), dot_string(label)).unwrap();
+	}
+	for (from, to) in self.edges.iter() {
+	    writeln!(s, "  {} -> {};", dot_string(&from.id()), dot_string(&to.id())).unwrap();
+	}
+	writeln!(s, "}}").unwrap();
+	return s;
+    }
+
+    pub fn to_json(&self) -> Value {
+	let nodes : Vec<Value> = self.labels.iter().map(|(node, label)| json!({
+	    "id" : node.id(),
+	    "kind" : node.kind(),
+	    "label" : label,
+	    "reachable" : self.reachable.contains(node),
+	})).collect();
+	let edges : Vec<Value> = self.edges.iter().map(|(from, to)| json!({
+	    "from" : from.id(),
+	    "to" : to.id(),
+	})).collect();
+	let unreachable : Vec<String> = self.unreachable().iter().map(|n| n.id()).collect();
+	return json!({
+	    "nodes" : nodes,
+	    "edges" : edges,
+	    "unreachable" : unreachable,
+	});
+    }
+}
+
+/// Quoted and escaped for Graphviz (IDs contain keyword text)
+fn dot_string(s : &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', " "))
+}
+
+#[cfg(test)]
+#[test]
+fn test_reachability() {
+    use super::chardata::{self, TestCharBuilder};
+    use super::map::{self, MapNPC, NPCMovement};
+    let mut fragments = StringFragmentTable::new(&[]);
+    for f in ["Hello", "Thank", " ", "you"] {
+	fragments.push(f).unwrap();
+    }
+    let sword = fragments.push("SWORD").unwrap();
+    let king = fragments.push("KING").unwrap();
+    // Character 0 uses a second fragment with the text "SWORD"
+    let sword_again = fragments.push("SWORD").unwrap();
+    let crown = fragments.push("CROWN").unwrap();
+
+    // Character 0: asking about SWORD teaches KING; giving key 5 completes quest 7, enabling interaction list 1.
+    // Character 1: asking about KING gives key 5; asking about CROWN says hello.
+    // Character 2 is not on the map: asking about KING would teach CROWN.
+    let chars = [
+	chardata::test_char(&fragments, (sword_again, king)),
+	TestCharBuilder::new()
+	    .item(0, 1, 5, 0)
+	    .interaction(0, InteractionTrigger::ASK, king, &[(Reaction::GIVE_ITEM, 1)])
+	    .interaction(1, InteractionTrigger::ASK, crown, &[(Reaction::SAY, 0)])
+	    .build(&fragments),
+	TestCharBuilder::new()
+	    .interaction(0, InteractionTrigger::ASK, king, &[(Reaction::TEACH_WORD, crown)])
+	    .build(&fragments),
+    ];
+    // Event 1 teaches SWORD
+    let mut test_map = map::test_map(2, 1, &[[3, 0, 0, 0, 0, 0, 0, sword as u8, 0, 0]], &[1, 0]);
+    for (c, pos) in [(0, (0, 0)), (1, (1, 0))] {
+	test_map.npcs.push(MapNPC { sprite : 1, talk_action : NPCAction::Chat(c), flags : 0, start_pos : pos,
+				    movement : NPCMovement::Stationary });
+    }
+    let graph = ProgressionGraph::build(&fragments, &[test_map], &chars);
+
+    let sword = Node::Keyword("SWORD".to_string());
+    let (king, crown) = (Node::Keyword("KING".to_string()), Node::Keyword("CROWN".to_string()));
+    for edge in [(Node::Event(0, 1), sword.clone()),
+		 (sword.clone(), Node::Interaction(0, 0, 0)),
+		 (Node::Interaction(0, 0, 0), king.clone()),
+		 (Node::Interaction(1, 0, 0), Node::Key(5)),
+		 (Node::Key(5), Node::Interaction(0, 0, 1)),
+		 (Node::Interaction(0, 0, 1), Node::QuestFlag(7)),
+		 (Node::QuestFlag(7), Node::Interaction(0, 1, 0))] {
+	assert!(graph.edges.contains(&edge), "missing edge {edge:?}");
+    }
+    for node in [&king, &Node::Key(5), &Node::QuestFlag(7), &Node::Interaction(0, 1, 0)] {
+	assert!(graph.reachable.contains(node), "{node:?} should be reachable");
+    }
+    assert!(!graph.reachable.contains(&Node::Character(2)));
+    assert!(!graph.reachable.contains(&Node::Interaction(1, 0, 1)));
+    assert_eq!(vec![&crown], graph.unreachable());
+    assert_eq!(vec![&Node::Interaction(1, 0, 1)], graph.required_by(&crown));
+
+    let dot = graph.to_dot();
+    assert!(dot.contains("\"keyword_CROWN\" [label=\"CROWN\", shape=diamond, style=dashed, color=red];"));
+    assert!(dot.contains("\"keyword_SWORD\" -> \"interaction_0_0_0\";"));
+    assert_eq!(json!(["keyword_CROWN"]), graph.to_json()["unreachable"]);
+}
@@ -0,0 +1,336 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Full-text and cross-reference search across all decoded game data
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use std::collections::HashMap;
+
+use super::AmberstarFiles;
+use super::chardata::{CharData, InteractionTrigger, Reaction};
+use super::map::{EventOp, Map, NPCAction};
+use super::string_fragment_table::StringFragmentTable;
+use super::translation;
+
+/// What an index entry is about
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Subject {
+    /// Dictionary word (string fragment index)
+    Keyword(u16),
+    /// Key ID of an item, as used by `InteractionTrigger::Show` and `Give`
+    Key(usize),
+    /// Item, by name
+    Item(String),
+    /// (map, string index) in MAPTEXT.AMB
+    MapMessage(usize, usize),
+    /// (character, message index)
+    CharMessage(usize, usize),
+    /// Entry of CHARDATA.AMB
+    Character(usize),
+    /// Entry of CHESTDATA.AMB (whose contents we do not decode yet)
+    Chest(usize),
+    /// Any other text, by its stable ID (cf. `translation::collect_text`)
+    Text(String),
+}
+
+/// How the subject is used at the location
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Usage {
+    /// The location defines the text
+    Defines,
+    /// Message is shown
+    Shows,
+    /// Keyword is learned
+    Teaches,
+    /// Keyword / key is needed to trigger an interaction
+    Requires,
+    /// Item is given to the party
+    Gives,
+    /// Item is in a character's inventory
+    Carries,
+    /// Chest is opened
+    Opens,
+    /// Map NPC is the character
+    Represents,
+}
+
+impl Usage {
+    fn verb(&self) -> &'static str {
+	match self {
+	    Usage::Defines    => "is defined in",
+	    Usage::Shows      => "is shown by",
+	    Usage::Teaches    => "is taught by",
+	    Usage::Requires   => "is required by",
+	    Usage::Gives      => "is given by",
+	    Usage::Carries    => "is carried by",
+	    Usage::Opens      => "is opened by",
+	    Usage::Represents => "appears as",
+	}
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Location {
+    /// Map event (1-based, as in hotspots) and the tiles that trigger it
+    Event { map : usize, event : usize, positions : Vec<(usize, usize)> },
+    MapNPC { map : usize, npc : usize },
+    /// Interaction `index` in interaction list `list` of a character
+    Interaction { character : usize, list : usize, index : usize },
+    Inventory { character : usize, slot : usize },
+    /// Text by its stable ID
+    Text(String),
+}
+
+pub struct IndexEntry {
+    pub subject : Subject,
+    pub usage : Usage,
+    pub location : Location,
+    /// Text of the subject (keyword, item name(s), message etc.), for full-text search
+    pub text : String,
+}
+
+/// Cross-reference index over maps, events, map NPCs, character interactions and inventories,
+/// and all text.  Chest contents (CHESTDAT.AMB) are not decoded yet and hence not indexed.
+#[derive(Default)]
+pub struct SearchIndex {
+    pub entries : Vec<IndexEntry>,
+    map_names : Vec<String>,
+    char_names : Vec<String>,
+    /// For each character, the (map, NPC) pairs that chat as this character
+    npcs_for_char : HashMap<usize, Vec<(usize, usize)>>,
+}
+
+impl SearchIndex {
+    pub fn new(data : &AmberstarFiles) -> SearchIndex {
+	let fragments = &data.amberdev.string_fragments;
+	let mut index = SearchIndex {
+	    map_names : data.maps.iter().map(|m| m.name.clone()).collect(),
+	    char_names : data.chardata.iter().map(|c| c.name.clone()).collect(),
+	    ..SearchIndex::default()
+	};
+	let mut item_names : HashMap<usize, Vec<String>> = HashMap::new();
+	for c in data.chardata.iter() {
+	    for (_, item) in c.items.iter().filter(|(count, item)| *count > 0 && item.key_id().id != 0) {
+		let names = item_names.entry(item.key_id().id).or_default();
+		if !names.contains(&item.name) {
+		    names.push(item.name.clone());
+		}
+	    }
+	}
+
+	for (nr, map) in data.maps.iter().enumerate() {
+	    let map_text = data.map_text.get(nr).map_or(&[][..], |t| &t.strings[..]);
+	    index.add_map(nr, map, map_text, fragments);
+	}
+	for (nr, c) in data.chardata.iter().enumerate() {
+	    index.add_char(nr, c, fragments, &item_names);
+	}
+	for (id, text) in translation::collect_text(data) {
+	    let subject = match id.split('.').collect::<Vec<_>>()[..] {
+		[translation::MAP_TEXT_PREFIX, map, s] => Subject::MapMessage(map.parse().unwrap(), s.parse().unwrap()),
+		["char", c, "msg", m]                  => Subject::CharMessage(c.parse().unwrap(), m.parse().unwrap()),
+		_                                      => Subject::Text(id.clone()),
+	    };
+	    index.add(subject, Usage::Defines, Location::Text(id), text);
+	}
+	return index;
+    }
+
+    fn add(&mut self, subject : Subject, usage : Usage, location : Location, text : String) {
+	self.entries.push(IndexEntry { subject, usage, location, text });
+    }
+
+    fn add_map(&mut self, nr : usize, map : &Map, map_text : &[String], fragments : &StringFragmentTable) {
+	let message = |m : usize| map_text.get(m).cloned().unwrap_or_default();
+	let mut positions : HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+	for y in 0..map.height {
+	    for x in 0..map.width {
+		if let Some(event) = map.hotspot_at(x, y).filter(|e| *e > 0) {
+		    positions.entry(event).or_default().push((x, y));
+		}
+	    }
+	}
+
+	for (i, event) in map.event_table.iter().enumerate() {
+	    let location = Location::Event { map : nr, event : i + 1, positions : positions.remove(&(i + 1)).unwrap_or_default() };
+	    for op in event.program() {
+		match op {
+		    EventOp::PopupMessage(_, m)        => self.add(Subject::MapMessage(nr, *m), Usage::Shows, location.clone(), message(*m)),
+		    EventOp::LearnKeyword(k)           => self.add(Subject::Keyword(*k as u16), Usage::Teaches, location.clone(),
+								  fragments.get(*k as u16)),
+		    EventOp::ChestAccess(chest, _, m)  => {
+			self.add(Subject::Chest(*chest), Usage::Opens, location.clone(), String::new());
+			if *m != 0 {
+			    self.add(Subject::MapMessage(nr, *m), Usage::Shows, location.clone(), message(*m));
+			}
+		    },
+		    _                                  => {},
+		}
+	    }
+	}
+
+	for (i, npc) in map.npcs.iter().enumerate() {
+	    let location = Location::MapNPC { map : nr, npc : i };
+	    match npc.talk_action {
+		NPCAction::PopupMessage(m)             => self.add(Subject::MapMessage(nr, m), Usage::Shows, location, message(m)),
+		// Hostile NPCs refer to monster groups, not characters
+		NPCAction::Chat(c) if !npc.hostile()   => {
+		    self.npcs_for_char.entry(c).or_default().push((nr, i));
+		    let name = self.char_names.get(c).cloned().unwrap_or_default();
+		    self.add(Subject::Character(c), Usage::Represents, location, name);
+		},
+		NPCAction::Chat(_)                     => {},
+	    }
+	}
+    }
+
+    fn add_char(&mut self, nr : usize, c : &CharData, fragments : &StringFragmentTable, item_names : &HashMap<usize, Vec<String>>) {
+	let key_text = |key : usize| item_names.get(&key).map_or(String::new(), |names| names.join(", "));
+	for (list, interactions) in c.interactions.iter().enumerate() {
+	    for (index, interaction) in interactions.iter().enumerate() {
+		let location = Location::Interaction { character : nr, list, index };
+		match interaction.trigger {
+		    InteractionTrigger::Ask(w)  => self.add(Subject::Keyword(w), Usage::Requires, location.clone(), fragments.get(w)),
+		    InteractionTrigger::Show(k)
+		    | InteractionTrigger::Give(k) => self.add(Subject::Key(k.id), Usage::Requires, location.clone(), key_text(k.id)),
+		    _                           => {},
+		}
+		for reaction in &interaction.reactions {
+		    match *reaction {
+			Reaction::Say(m)       => self.add(Subject::CharMessage(nr, m), Usage::Shows, location.clone(),
+							   c.messages.get(m).cloned().unwrap_or_default()),
+			Reaction::TeachWord(w) => self.add(Subject::Keyword(w), Usage::Teaches, location.clone(), fragments.get(w)),
+			Reaction::GiveItem(i)  => if let Some((_, item)) = c.items.get(i) {
+			    self.add(Subject::Item(item.name.clone()), Usage::Gives, location.clone(), item.name.clone());
+			},
+			_                      => {},
+		    }
+		}
+	    }
+	}
+	for (slot, (count, item)) in c.items.iter().enumerate() {
+	    if *count == 0 {
+		continue;
+	    }
+	    let location = Location::Inventory { character : nr, slot };
+	    self.add(Subject::Item(item.name.clone()), Usage::Carries, location.clone(), item.name.clone());
+	    if item.key_id().id != 0 {
+		self.add(Subject::Key(item.key_id().id), Usage::Carries, location, item.name.clone());
+	    }
+	}
+    }
+
+    /// Finds all entries for the query: `keyword:N`, `key:N`, `char:N` and `chest:N` (decimal or 0x-prefixed hex)
+    /// search by ID; anything else searches the text of all entries (ignoring case)
+    pub fn search(&self, query : &str) -> Vec<&IndexEntry> {
+	let by_id = query.split_once(':').and_then(|(kind, n)| {
+	    let n = match n.strip_prefix("0x") {
+		Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+		None      => n.parse::<usize>().ok()?,
+	    };
+	    match kind {
+		"keyword" => Some(Subject::Keyword(n as u16)),
+		"key"     => Some(Subject::Key(n)),
+		"char"    => Some(Subject::Character(n)),
+		"chest"   => Some(Subject::Chest(n)),
+		_         => None,
+	    }
+	});
+	if let Some(subject) = by_id {
+	    return self.entries.iter().filter(|e| e.subject == subject
+					      || matches!((&subject, &e.location), (Subject::Character(c), Location::Interaction { character, .. }
+										     | Location::Inventory { character, .. })
+							  if c == character)).collect();
+	}
+	let query = query.to_lowercase();
+	return self.entries.iter().filter(|e| e.text.to_lowercase().contains(&query)).collect();
+    }
+
+    fn map_name(&self, map : usize) -> String {
+	format!("map {map} ({})", self.map_names.get(map).map_or("?", |s| s.as_str()))
+    }
+
+    fn char_name(&self, character : usize) -> String {
+	format!("{} (char {character})", self.char_names.get(character).map_or("?", |s| s.as_str()))
+    }
+
+    pub fn describe_subject(&self, entry : &IndexEntry) -> String {
+	let text = entry.text.replace('\n', " ");
+	match &entry.subject {
+	    Subject::Keyword(w)         => format!("keyword '{text}' (0x{w:x})"),
+	    Subject::Key(k)             => format!("key 0x{k:x} ('{text}')"),
+	    Subject::Item(name)         => format!("item '{name}'"),
+	    Subject::MapMessage(m, s)   => format!("message {s} of {}: '{text}'", self.map_name(*m)),
+	    Subject::CharMessage(c, s)  => format!("message {s} of {}: '{text}'", self.char_name(*c)),
+	    Subject::Character(c)       => format!("character {}", self.char_name(*c)),
+	    Subject::Chest(chest)       => format!("chest {chest}"),
+	    Subject::Text(_)            => format!("'{text}'"),
+	}
+    }
+
+    pub fn describe_location(&self, location : &Location) -> String {
+	match location {
+	    Location::Event { map, event, positions } => {
+		let at = if positions.is_empty() { "no hotspot".to_string() }
+		         else { positions.iter().map(|(x, y)| format!("({x}, {y})")).collect::<Vec<_>>().join(", ") };
+		format!("event 0x{event:02x} on {} at {at}", self.map_name(*map))
+	    },
+	    Location::MapNPC { map, npc }       => format!("NPC {npc} on {}", self.map_name(*map)),
+	    Location::Interaction { character, list, index } => {
+		let npcs = self.npcs_for_char.get(character).map_or(vec![], |v| v.iter()
+		    .map(|(map, npc)| format!(", NPC {npc} on {}", self.map_name(*map))).collect());
+		format!("{}{} (interaction {list}.{index})", self.char_name(*character), npcs.join(""))
+	    },
+	    Location::Inventory { character, slot } => format!("inventory slot {slot} of {}", self.char_name(*character)),
+	    Location::Text(id)                  => id.clone(),
+	}
+    }
+
+    /// E.g. "keyword 'SWORD' (0x123) is taught by event 0x04 on map 12 (TWINLAKE) at (3, 7)"
+    pub fn describe(&self, entry : &IndexEntry) -> String {
+	format!("{} {} {}", self.describe_subject(entry), entry.usage.verb(), self.describe_location(&entry.location))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_search() {
+    use super::{chardata, map};
+    let mut fragments = StringFragmentTable::new(&[]);
+    let sword = fragments.push("SWORD").unwrap();
+    let king = fragments.push("KING").unwrap();
+    let character = chardata::test_char(&fragments, (sword, king));
+    // Event 1: message 1, then learn SWORD; event 2: chest 3, without message
+    let events = [[3, 0, 1, 0, 0, 0, 0, sword as u8, 0, 0],
+		  [4, 0, 0, 0, 0, 0, 0, 3, 0, 0]];
+    let test_map = map::test_map(4, 2, &events, &[0, 1, 0, 0,
+						  0, 0, 0, 2]);
+    let map_text = ["Welcome".to_string(), "A sign".to_string()];
+
+    let mut index = SearchIndex {
+	map_names : vec!["TWINLAKE".to_string()],
+	char_names : vec!["GANDALF".to_string()],
+	..SearchIndex::default()
+    };
+    index.npcs_for_char.insert(0, vec![(0, 3)]);
+    index.add_map(0, &test_map, &map_text, &fragments);
+    index.add_char(0, &character, &fragments, &HashMap::from([(5, vec!["Key".to_string()])]));
+
+    let by_sword : Vec<String> = index.search(&format!("keyword:{sword:#x}")).iter().map(|e| index.describe(e)).collect();
+    assert_eq!(vec![format!("keyword 'SWORD' ({sword:#x}) is taught by event 0x01 on map 0 (TWINLAKE) at (1, 0)"),
+		    format!("keyword 'SWORD' ({sword:#x}) is required by GANDALF (char 0), NPC 3 on map 0 (TWINLAKE) (interaction 0.0)")],
+	       by_sword[..2]);
+    assert_eq!(1, index.search(&format!("keyword:{king}")).len());
+    assert_eq!(vec!["chest 3 is opened by event 0x02 on map 0 (TWINLAKE) at (3, 1)"],
+	       index.search("chest:3").iter().map(|e| index.describe(e)).collect::<Vec<_>>());
+    assert_eq!("message 1 of map 0 (TWINLAKE): 'A sign' is shown by event 0x01 on map 0 (TWINLAKE) at (1, 0)",
+	       index.describe(index.search("sign")[0]));
+    assert_eq!(1, index.search("key:5").iter().filter(|e| e.usage == Usage::Requires).count());
+    assert_eq!(1, index.search("key:5").iter().filter(|e| e.usage == Usage::Carries).count());
+    assert!(index.search("char:0").len() >= 4);
+    assert!(index.search("chest:1").is_empty());
+}
@@ -6,6 +6,12 @@ use log::{Level, log_enabled, trace, debug, info, warn, error};
 
 use super::amber_string;
 
+/// Fragment text that stands for a line break
+const NEWLINE_FRAGMENT : &str = "#";
+/// Fragments are stored with a one-byte length that includes the length byte itself
+pub const MAX_FRAGMENT_LEN : usize = 0xfe;
+
+#[derive(Clone)]
 pub struct StringFragmentTable {
     fragments : Vec<String>
 }
@@ -23,7 +29,7 @@ impl StringFragmentTable {
 	    }
 	    let str_vec = &bytes[offset+1..(offset+str_len)];
 	    let str : String = amber_string::from_bytes(&str_vec);
-	    if str == "#" {
+	    if str == NEWLINE_FRAGMENT {
 		result.fragments.push("\n".to_string());
 	    } else {
 		result.fragments.push(str);
@@ -52,4 +58,53 @@ impl StringFragmentTable {
 	}
 	return &self.fragments[index as usize];
     }
+
+    /// Index of the first fragment with exactly this text
+    pub fn index_of(&self, fragment : &str) -> Option<u16> {
+	return self.fragments.iter().skip(1).position(|f| f == fragment).map(|i| (i + 1) as u16);
+    }
+
+    /// Appends a new fragment and returns its index
+    pub fn push(&mut self, fragment : &str) -> Result<u16, String> {
+	if self.fragments.len() > u16::MAX as usize {
+	    return Err("Too many string fragments".to_string());
+	}
+	let bytes = amber_string::try_to_bytes(fragment)?;
+	if bytes.len() == 0 || bytes.len() > MAX_FRAGMENT_LEN {
+	    return Err(format!("Fragment \"{fragment}\" has unsupported length {}", bytes.len()));
+	}
+	self.fragments.push(fragment.to_string());
+	return Ok((self.fragments.len() - 1) as u16);
+    }
+
+    /// Encodes in the format read by `new`, including the terminating zero byte
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+	let mut result = vec![];
+	for fragment in self.fragments.iter().skip(1) {
+	    let bytes = if fragment == "\n" { NEWLINE_FRAGMENT.as_bytes().to_vec() } else { amber_string::try_to_bytes(fragment)? };
+	    if bytes.len() == 0 || bytes.len() > MAX_FRAGMENT_LEN {
+		return Err(format!("Fragment \"{fragment}\" has unsupported length {}", bytes.len()));
+	    }
+	    result.push((bytes.len() + 1) as u8);
+	    result.extend_from_slice(&bytes);
+	}
+	result.push(0);
+	return Ok(result);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_encode_roundtrip() {
+    let mut table = StringFragmentTable::new(&[]);
+    assert_eq!(Ok(1), table.push("HELLO"));
+    assert_eq!(Ok(2), table.push("\n"));
+    assert_eq!(Ok(3), table.push("GRÜßE"));
+    assert!(table.push("").is_err());
+    let bytes = table.encode().unwrap();
+    assert_eq!(vec![6, b'H', b'E', b'L', b'L', b'O', 2, b'#'], bytes[..8]);
+    let decoded = StringFragmentTable::new(&bytes);
+    assert_eq!(table.fragments, decoded.fragments);
+    assert_eq!(Some(3), decoded.index_of("GRÜßE"));
+    assert_eq!(None, decoded.index_of("<?-NUL-?>"));
 }
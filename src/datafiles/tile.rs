@@ -6,10 +6,12 @@ use log::{Level, log_enabled, trace, debug, info, warn, error};
 #[allow(unused)]
 use crate::{ptrace, pdebug, pinfo, pwarn, perror};
 
-use sdl2::{pixels::Color, render::{TextureCreator, Texture, BlendMode, Canvas, RenderTarget}, rect::Rect};
+#[cfg(feature = "sdl")]
+use sdl2::{render::{TextureCreator, Texture, BlendMode, Canvas, RenderTarget}, rect::Rect};
 use crate::datafiles::{palette, decode, pixmap};
+use crate::datafiles::palette::Color;
 
-use super::pixmap::Pixmap;
+use super::pixmap::{Pixmap, IndexedPixmap};
 
 // ----------------------------------------
 // TileFlags describe properties of 2D tiles and LabInfo blocks
@@ -101,71 +103,112 @@ impl<T : Clone> Tileset<T> {
 }
 
 pub fn new(src: &[u8]) -> Tileset<Pixmap> {
+    return new_indexed(src).with_palette();
+}
+
+/// Decodes a tileset without applying its palette, e.g. for export
+pub fn new_indexed(src: &[u8]) -> Tileset<IndexedPixmap> {
+    let num_icons = src[OFFSET_TILE_NUM_ANIM_FRAMES..].iter().position(|x|  *x == 0); // Always 250, I think?
+    assert_eq!(num_icons, Some(250), "num_icons != 250 is possible, but I haven't observed it anywhere");
+    return try_new_indexed(src).unwrap();
+}
+
+/// Like `new_indexed`, but for data whose layout we are less sure of: accepts any number of icons and
+/// reports data that does not fit the layout as an error instead of panicking
+pub fn try_new_indexed(src: &[u8]) -> Result<Tileset<IndexedPixmap>, String> {
+    const ICON_HEADER_SIZE : usize = 6;
     let mut tile_icons = vec![];
     let mut tile_index_start = vec![];
-    let num_icons = src[OFFSET_TILE_NUM_ANIM_FRAMES..].iter().position(|x|  *x == 0); // Always 250, I think?
+    let num_icons = src.get(OFFSET_TILE_NUM_ANIM_FRAMES..).and_then(|counts| counts.iter().position(|x|  *x == 0))
+	.ok_or("No end marker after the icon frame counts")?;
     let player_icon_index = decode::u16(src, 0) as usize;
 
-    assert_eq!(num_icons, Some(250), "num_icons != 250 is possible, but I haven't observed it anywhere");
-    if let Some(num_icons) = num_icons {
-	let palette_offset = num_icons * 8; // anim_type(u8), anim_start(u16), magic_flags1(u32), magic_flags2(u8)
-	assert!(src.len() >= palette_offset + PALETTE_SIZE);
-	let base = &src[2..];
-	let opaque_palette = palette::new_with_header(&base[palette_offset..], PALETTE_BRIGHTNESS);
-	let transparent_palette = opaque_palette.with_transparency(COLOR_INDEX_FOR_TRANSPARENCY);
-	let anim_start_base = &base[num_icons * 1..];
-	let magic_flags1_base = &base[num_icons * 3..];
-	let map_color_index_base = &base[num_icons * 7..];
-
-	let mut frame_start = vec![0];
-	let frame_base = &base[palette_offset + PALETTE_SIZE..];
-	let mut frame_pos = 0;
-	while frame_pos + 6 < frame_base.len() {
-	    let image_len = pixmap::icon_len(&frame_base[frame_pos..]);
-	    frame_start.push(frame_pos);
-	    frame_pos += image_len;
-	}
+    let palette_offset = num_icons * 8; // anim_type(u8), anim_start(u16), magic_flags1(u32), magic_flags2(u8)
+    let base = &src[2..];
+    if base.len() < palette_offset + PALETTE_SIZE {
+	return Err(format!("{} bytes are too few for {num_icons} icons and a palette", src.len()));
+    }
+    let num_colors = decode::u16(base, palette_offset) as usize;
+    if 2 + num_colors * 4 > PALETTE_SIZE {
+	return Err(format!("Palette with {num_colors} colours does not fit the tileset layout"));
+    }
+    let opaque_palette = palette::new_with_header(&base[palette_offset..], PALETTE_BRIGHTNESS);
+    let anim_start_base = &base[num_icons * 1..];
+    let magic_flags1_base = &base[num_icons * 3..];
+    let map_color_index_base = &base[num_icons * 7..];
 
-	for i in 0..num_icons {
-	    let num_frames = base[i] as usize;
-	    let anim_start = decode::u16(&anim_start_base, i * 2) as usize;
-	    let anim_end = anim_start + num_frames;
-	    let flags = TileFlags::new(&magic_flags1_base[i * 4..(i+1) * 4]);
-	    let map_color_index = map_color_index_base[i];
+    let mut frame_start = vec![0];
+    let frame_base = &base[palette_offset + PALETTE_SIZE..];
+    let mut frame_pos = 0;
+    while frame_pos + ICON_HEADER_SIZE < frame_base.len() {
+	let image_len = pixmap::icon_len(&frame_base[frame_pos..]);
+	frame_start.push(frame_pos);
+	frame_pos += image_len;
+    }
 
-	    let mut frames = vec![];
-	    for image_index in anim_start..anim_end {
-		// if Some(img) = images.
-		let pos = frame_start[image_index];
-		let frame = pixmap::new_icon_frame(&frame_base[pos..]);
-		let palette = if flags.draw_with_transparency() {
-		    &transparent_palette
-		} else {
-		    &opaque_palette
-		};
-		let frame = frame.with_palette(&palette);
-		frames.push(frame);
+    for i in 0..num_icons {
+	let num_frames = base[i] as usize;
+	let anim_start = decode::u16(&anim_start_base, i * 2) as usize;
+	let anim_end = anim_start + num_frames;
+	let flags = TileFlags::new(&magic_flags1_base[i * 4..(i+1) * 4]);
+	let map_color_index = map_color_index_base[i] as usize;
+	if map_color_index >= opaque_palette.len() {
+	    return Err(format!("Icon {i}: map colour {map_color_index} is not in the palette"));
+	}
+
+	let mut frames = vec![];
+	for image_index in anim_start..anim_end {
+	    let pos = *frame_start.get(image_index).ok_or(format!("Icon {i}: no frame {image_index}"))?;
+	    if pos + ICON_HEADER_SIZE > frame_base.len() || pos + pixmap::icon_len(&frame_base[pos..]) > frame_base.len() {
+		return Err(format!("Icon {i}: frame {image_index} extends past the end of the data"));
 	    }
-	    tile_index_start.push(anim_start);
-	    tile_icons.push(TileIcon {
-		frames,
-		flags,
-		map_color : opaque_palette.get(map_color_index as usize),
-	    });
+	    frames.push(pixmap::new_icon_frame(&frame_base[pos..]));
 	}
+	tile_index_start.push(anim_start);
+	tile_icons.push(TileIcon {
+	    frames,
+	    flags,
+	    map_color : opaque_palette.get(map_color_index),
+	});
+    }
+    return Ok(Tileset {
+	palette : opaque_palette,
+	tile_icons,
+	tile_index_start,
+	player_icon_index,
+    });
+}
+
+impl Tileset<IndexedPixmap> {
+    /// Applies the tileset's own palette, honouring DRAW_WITH_TRANSPARENCY
+    pub fn with_palette(&self) -> Tileset<Pixmap> {
+	let opaque_palette = &self.palette;
+	let transparent_palette = opaque_palette.with_transparency(COLOR_INDEX_FOR_TRANSPARENCY);
+	let tile_icons = self.tile_icons.iter().map(|t| {
+	    let palette = if t.flags.draw_with_transparency() {
+		&transparent_palette
+	    } else {
+		opaque_palette
+	    };
+	    TileIcon {
+		frames : t.frames.iter().map(|f| f.with_palette(palette)).collect(),
+		flags : t.flags,
+		map_color : t.map_color,
+	    }
+	}).collect();
 	return Tileset {
-	    palette : opaque_palette,
 	    tile_icons,
-	    tile_index_start,
-	    player_icon_index,
+	    tile_index_start : self.tile_index_start.clone(),
+	    palette : self.palette.clone(),
+	    player_icon_index : self.player_icon_index,
 	}
     }
-    panic!("Could not determine number of tileset icons");
 }
 
 // ----------------------------------------
 // TileTextures
 
+#[cfg(feature = "sdl")]
 #[allow(unused)]
 impl Tileset<Pixmap> {
     pub fn as_textures<'a, T>(&self, tc: &'a TextureCreator<T>) -> Tileset<Texture<'a>> {
@@ -195,6 +238,7 @@ impl Tileset<Pixmap> {
 #[allow(unused)]
 const TILE_SIZE : u32 = 16;
 
+#[cfg(feature = "sdl")]
 #[allow(unused)]
 impl<'a> Tileset<Texture<'a>> {
     pub fn draw<T>(&self, canvas : &mut Canvas<T>, tile_index : usize,
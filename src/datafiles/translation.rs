@@ -0,0 +1,395 @@
+// Copyright (C) 2024 Christoph Reichenbach (creichen@gmail.com)
+// Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
+
+// Translation workflow: all game text as gettext PO or XLIFF 1.2, keyed by stable IDs
+
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+#[allow(unused)]
+use crate::{ptrace, pdebug, pinfo, pwarn, perror};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use super::AmberstarFiles;
+use super::amber_string;
+use super::map_string_table::{self, MapStringTable};
+use super::string_fragment_table::MAX_FRAGMENT_LEN;
+
+// ----------------------------------------
+// Stable IDs:
+//   map.MMM.SSS        string SSS of MAPTEXT.AMB entry MMM
+//   code.CCC.SSS       string SSS of CODETXT.AMB entry CCC
+//   char.CCC.name      name of CHARDATA.AMB entry CCC
+//   char.CCC.msg.NN    message NN of character CCC
+//   char.CCC.item.NN   name of the item in inventory slot NN of character CCC
+//   spell.S.NN         spell NN of magic school S
+//   merchant.NNN       merchant names
+//   song.NN            song names
+
+pub const MAP_TEXT_PREFIX : &str = "map";
+pub const CODE_TEXT_PREFIX : &str = "code";
+
+/// All text in the game files, with stable IDs, in a fixed order
+pub fn collect_text(data : &AmberstarFiles) -> Vec<(String, String)> {
+    let mut result = vec![];
+    for (prefix, tables) in [(MAP_TEXT_PREFIX, &data.map_text), (CODE_TEXT_PREFIX, &data.code_text)] {
+	for (i, table) in tables.iter().enumerate() {
+	    for (k, s) in table.strings.iter().enumerate() {
+		result.push((format!("{prefix}.{i:03}.{k:03}"), s.clone()));
+	    }
+	}
+    }
+    for (i, c) in data.chardata.iter().enumerate() {
+	result.push((format!("char.{i:03}.name"), c.name.clone()));
+	for (k, msg) in c.messages.iter().enumerate() {
+	    result.push((format!("char.{i:03}.msg.{k:02}"), msg.clone()));
+	}
+	for (k, (count, item)) in c.items.iter().enumerate() {
+	    if *count > 0 {
+		result.push((format!("char.{i:03}.item.{k:02}"), item.name.clone()));
+	    }
+	}
+    }
+    for (school, spells) in data.amberdev.spell_names.iter().enumerate() {
+	for (k, spell) in spells.iter().enumerate() {
+	    result.push((format!("spell.{school}.{k:02}"), spell.clone()));
+	}
+    }
+    for (i, name) in data.amberdev.merchant_names.iter().enumerate() {
+	result.push((format!("merchant.{i:03}"), name.clone()));
+    }
+    for (i, name) in data.amberdev.song_names.iter().enumerate() {
+	result.push((format!("song.{i:02}"), name.clone()));
+    }
+    return result;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextEntry {
+    pub id : String,
+    pub source : String,
+    /// The same text in the reference release, if any
+    pub reference : Option<String>,
+    /// Empty if not translated yet
+    pub translation : String,
+}
+
+/// Pairs up the text of the source release with that of the reference release (by ID)
+pub fn pair(source : &[(String, String)], reference : Option<&[(String, String)]>) -> Vec<TextEntry> {
+    let reference : HashMap<&str, &str> = reference.unwrap_or(&[]).iter().map(|(id, s)| (id.as_str(), s.as_str())).collect();
+    return source.iter().map(|(id, s)| TextEntry {
+	id : id.clone(),
+	source : s.clone(),
+	reference : reference.get(id.as_str()).map(|r| r.to_string()),
+	translation : String::new(),
+    }).collect();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextFormat {
+    Po,
+    Xliff,
+}
+
+impl TextFormat {
+    /// Guesses the format from the file extension
+    pub fn from_path(path : &Path) -> Option<TextFormat> {
+	match path.extension()?.to_str()?.to_lowercase().as_str() {
+	    "po" | "pot"     => Some(TextFormat::Po),
+	    "xlf" | "xliff"  => Some(TextFormat::Xliff),
+	    _                => None,
+	}
+    }
+
+    pub fn write(&self, entries : &[TextEntry], source_lang : &str, reference_lang : Option<&str>) -> String {
+	match self {
+	    TextFormat::Po    => write_po(entries, reference_lang),
+	    TextFormat::Xliff => write_xliff(entries, source_lang, reference_lang),
+	}
+    }
+
+    pub fn read(&self, text : &str) -> Result<Vec<TextEntry>, String> {
+	match self {
+	    TextFormat::Po    => read_po(text),
+	    TextFormat::Xliff => read_xliff(text),
+	}
+    }
+}
+
+// ----------------------------------------
+// gettext PO: the ID is the msgctxt, the reference text an extracted comment
+
+fn po_escape(s : &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+	match c {
+	    '"'  => result.push_str("\\\""),
+	    '\\' => result.push_str("\\\\"),
+	    '\n' => result.push_str("\\n"),
+	    '\t' => result.push_str("\\t"),
+	    _    => result.push(c),
+	}
+    }
+    return result;
+}
+
+fn po_unescape(s : &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+	if c != '\\' {
+	    result.push(c);
+	    continue;
+	}
+	match chars.next() {
+	    Some('n')  => result.push('\n'),
+	    Some('t')  => result.push('\t'),
+	    Some('"')  => result.push('"'),
+	    Some('\\') => result.push('\\'),
+	    other      => return Err(format!("Invalid escape sequence '\\{}'", other.map(|c| c.to_string()).unwrap_or_default())),
+	}
+    }
+    return Ok(result);
+}
+
+pub fn write_po(entries : &[TextEntry], reference_lang : Option<&str>) -> String {
+    let mut result = String::new();
+    writeln!(result, "msgid \"\"\nmsgstr \"\"\n\"Content-Type: text/plain; charset=UTF-8\\n\"\n").unwrap();
+    for entry in entries {
+	if let (Some(reference), Some(lang)) = (&entry.reference, reference_lang) {
+	    for line in reference.split('\n') {
+		writeln!(result, "#. {lang}: {line}").unwrap();
+	    }
+	}
+	writeln!(result, "msgctxt \"{}\"", po_escape(&entry.id)).unwrap();
+	writeln!(result, "msgid \"{}\"", po_escape(&entry.source)).unwrap();
+	writeln!(result, "msgstr \"{}\"\n", po_escape(&entry.translation)).unwrap();
+    }
+    return result;
+}
+
+pub fn read_po(text : &str) -> Result<Vec<TextEntry>, String> {
+    let mut result = vec![];
+    let (mut ctxt, mut id, mut msgstr) : (Option<String>, Option<String>, Option<String>) = (None, None, None);
+    // Which of the above continuation lines ("...") extend
+    let mut current = 0;
+
+    let mut finish = |ctxt : &mut Option<String>, id : &mut Option<String>, msgstr : &mut Option<String>| {
+	if let (Some(c), Some(i), Some(s)) = (ctxt.take(), id.take(), msgstr.take()) {
+	    result.push(TextEntry { id : c, source : i, reference : None, translation : s });
+	}
+	// the header has no msgctxt
+	*id = None;
+	*msgstr = None;
+    };
+
+    for (line_nr, line) in text.lines().enumerate() {
+	let line = line.trim();
+	let err = |e : String| format!("line {}: {e}", line_nr + 1);
+	let quoted = |s : &str| -> Result<String, String> {
+	    let s = s.trim();
+	    if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') {
+		return Err(err(format!("Expected quoted string: {s}")));
+	    }
+	    po_unescape(&s[1..s.len() - 1]).map_err(err)
+	};
+	if line.is_empty() || line.starts_with('#') {
+	    continue;
+	} else if let Some(rest) = line.strip_prefix("msgctxt ") {
+	    finish(&mut ctxt, &mut id, &mut msgstr);
+	    ctxt = Some(quoted(rest)?);
+	    current = 0;
+	} else if let Some(rest) = line.strip_prefix("msgid ") {
+	    if msgstr.is_some() {
+		finish(&mut ctxt, &mut id, &mut msgstr);
+	    }
+	    id = Some(quoted(rest)?);
+	    current = 1;
+	} else if let Some(rest) = line.strip_prefix("msgstr ") {
+	    msgstr = Some(quoted(rest)?);
+	    current = 2;
+	} else if line.starts_with('"') {
+	    let s = quoted(line)?;
+	    match current {
+		0 => ctxt.get_or_insert_with(String::new).push_str(&s),
+		1 => id.get_or_insert_with(String::new).push_str(&s),
+		_ => msgstr.get_or_insert_with(String::new).push_str(&s),
+	    }
+	} else {
+	    return Err(err(format!("Unsupported PO line: {line}")));
+	}
+    }
+    finish(&mut ctxt, &mut id, &mut msgstr);
+    return Ok(result);
+}
+
+// ----------------------------------------
+// XLIFF 1.2: one trans-unit per ID, the reference text as a note
+
+fn xml_escape(s : &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+fn xml_unescape(s : &str) -> String {
+    s.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+pub fn write_xliff(entries : &[TextEntry], source_lang : &str, reference_lang : Option<&str>) -> String {
+    let mut result = String::new();
+    writeln!(result, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+    writeln!(result, "<xliff version=\"1.2\" xmlns=\"urn:oasis:names:tc:xliff:document:1.2\">").unwrap();
+    writeln!(result, "  <file original=\"amberstar\" source-language=\"{source_lang}\" datatype=\"plaintext\">").unwrap();
+    writeln!(result, "    <body>").unwrap();
+    for entry in entries {
+	writeln!(result, "      <trans-unit id=\"{}\" xml:space=\"preserve\">", xml_escape(&entry.id)).unwrap();
+	writeln!(result, "        <source>{}</source>", xml_escape(&entry.source)).unwrap();
+	writeln!(result, "        <target>{}</target>", xml_escape(&entry.translation)).unwrap();
+	if let Some(reference) = &entry.reference {
+	    writeln!(result, "        <note from=\"{}\">{}</note>", reference_lang.unwrap_or("reference"), xml_escape(reference)).unwrap();
+	}
+	writeln!(result, "      </trans-unit>").unwrap();
+    }
+    writeln!(result, "    </body>\n  </file>\n</xliff>").unwrap();
+    return result;
+}
+
+/// Content of the first <tag ...>...</tag> (or "" for <tag/>) in `s`
+fn xml_element<'a>(s : &'a str, tag : &str) -> Option<&'a str> {
+    let start = s.find(&format!("<{tag}"))?;
+    let open_end = start + s[start..].find('>')?;
+    if s[..open_end].ends_with('/') {
+	return Some("");
+    }
+    let close = open_end + s[open_end..].find(&format!("</{tag}>"))?;
+    return Some(&s[open_end + 1..close]);
+}
+
+/// Reads the trans-units of XLIFF files as written by `write_xliff` (no full XML parser)
+pub fn read_xliff(text : &str) -> Result<Vec<TextEntry>, String> {
+    let mut result = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("<trans-unit") {
+	let end = rest[start..].find("</trans-unit>").ok_or("Unterminated trans-unit")? + start;
+	let unit = &rest[start..end];
+	let id_start = unit.find("id=\"").ok_or("trans-unit without id")? + 4;
+	let id_end = unit[id_start..].find('"').ok_or("Unterminated id")? + id_start;
+	let id = xml_unescape(&unit[id_start..id_end]);
+	let source = xml_element(unit, "source").ok_or(format!("{id}: no source"))?;
+	let target = xml_element(unit, "target").unwrap_or("");
+	result.push(TextEntry {
+	    id,
+	    source : xml_unescape(source),
+	    reference : None,
+	    translation : xml_unescape(target),
+	});
+	rest = &rest[end..];
+    }
+    return Ok(result);
+}
+
+// ----------------------------------------
+// Validation and import
+
+/// Checks translations against the game text: unknown IDs, changed source text, characters
+/// without Atari ST codepoint, and words that are too long for the fragment table.
+/// Returns one message per problem.
+pub fn validate(entries : &[TextEntry], game_text : &[(String, String)]) -> Vec<String> {
+    let known : HashMap<&str, &str> = game_text.iter().map(|(id, s)| (id.as_str(), s.as_str())).collect();
+    let mut problems = vec![];
+    for entry in entries {
+	match known.get(entry.id.as_str()) {
+	    None => problems.push(format!("{}: unknown ID", entry.id)),
+	    Some(s) if *s != entry.source => problems.push(format!("{}: source text differs from the game files", entry.id)),
+	    _ => {},
+	}
+	let mut reported = HashSet::new();
+	for c in entry.translation.chars().filter(|c| amber_string::try_to_byte(*c).is_none() && reported.insert(*c)) {
+	    problems.push(format!("{}: character '{c}' (U+{:04X}) does not exist in the Atari ST character set", entry.id, c as u32));
+	}
+	for token in map_string_table::tokenize(&entry.translation) {
+	    if token.chars().count() > MAX_FRAGMENT_LEN {
+		problems.push(format!("{}: \"{token}\" is longer than {MAX_FRAGMENT_LEN} characters", entry.id));
+	    }
+	}
+    }
+    return problems;
+}
+
+/// Translations by ID; untranslated entries are skipped
+pub fn translations(entries : &[TextEntry]) -> BTreeMap<String, String> {
+    entries.iter().filter(|e| !e.translation.is_empty()).map(|e| (e.id.clone(), e.translation.clone())).collect()
+}
+
+/// The strings of MAPTEXT.AMB or CODETXT.AMB (`prefix` is MAP_TEXT_PREFIX or CODE_TEXT_PREFIX), with translations applied
+pub fn translate_tables(tables : &[MapStringTable], prefix : &str, translations : &BTreeMap<String, String>) -> Vec<Vec<String>> {
+    return tables.iter().enumerate().map(|(i, table)| {
+	table.strings.iter().enumerate().map(|(k, s)| {
+	    translations.get(&format!("{prefix}.{i:03}.{k:03}")).unwrap_or(s).clone()
+	}).collect()
+    }).collect();
+}
+
+/// IDs of translations that cannot be written back yet: everything outside of MAPTEXT.AMB and CODETXT.AMB
+pub fn unsupported_ids(translations : &BTreeMap<String, String>) -> Vec<&str> {
+    translations.keys().map(|id| id.as_str()).filter(|id| {
+	![MAP_TEXT_PREFIX, CODE_TEXT_PREFIX].contains(&id.split('.').next().unwrap_or(""))
+    }).collect()
+}
+
+#[cfg(test)]
+fn test_entries() -> Vec<TextEntry> {
+    let source = vec![("map.000.000".to_string(), "DER \"ALTE\" MANN\n SAGT: <HALLO> & TSCHÜSS".to_string()),
+		      ("song.00".to_string(), "TITEL".to_string())];
+    let reference = vec![("map.000.000".to_string(), "THE OLD MAN\n SAYS".to_string())];
+    let mut entries = pair(&source, Some(&reference));
+    entries[0].translation = "THE OLD MAN\n SAYS: \\HELLO/".to_string();
+    return entries;
+}
+
+#[cfg(test)]
+#[test]
+fn test_po_roundtrip() {
+    let entries = test_entries();
+    assert_eq!(Some("THE OLD MAN\n SAYS".to_string()), entries[0].reference);
+    assert_eq!(None, entries[1].reference);
+    let po = write_po(&entries, Some("en"));
+    assert!(po.contains("#. en: THE OLD MAN\n#. en:  SAYS\n"));
+    let read = read_po(&po).unwrap();
+    assert_eq!(entries.iter().map(|e| TextEntry { reference : None, ..e.clone() }).collect::<Vec<_>>(), read);
+    assert!(read_po("msgctxt \"x\"\nmsgid \"\\q\"").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_xliff_roundtrip() {
+    let entries = test_entries();
+    let xliff = write_xliff(&entries, "de", Some("en"));
+    let read = read_xliff(&xliff).unwrap();
+    assert_eq!(entries.iter().map(|e| TextEntry { reference : None, ..e.clone() }).collect::<Vec<_>>(), read);
+}
+
+#[cfg(test)]
+#[test]
+fn test_validate() {
+    let mut entries = test_entries();
+    let game_text : Vec<(String, String)> = entries.iter().map(|e| (e.id.clone(), e.source.clone())).collect();
+    assert!(validate(&entries, &game_text).is_empty());
+    entries[1].translation = "TITLE \u{263a}-\u{263a}".to_string();
+    entries.push(TextEntry { id : "map.999.000".to_string(), source : String::new(), reference : None, translation : String::new() });
+    let problems = validate(&entries, &game_text);
+    assert_eq!(2, problems.len());
+    assert!(problems[0].contains("U+263A"));
+    assert!(problems[1].contains("unknown ID"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_unsupported_ids() {
+    let mut entries = test_entries();
+    entries[1].translation = "TITLE".to_string();
+    entries.push(TextEntry { id : "code.001.002".to_string(), source : "X".to_string(), reference : None, translation : "Y".to_string() });
+    entries.push(TextEntry { id : "char.003.name".to_string(), source : "X".to_string(), reference : None, translation : String::new() });
+    assert_eq!(vec!["song.00"], unsupported_ids(&translations(&entries)));
+}
@@ -1,957 +1,1274 @@
 // Copyright (C) 2022 Christoph Reichenbach (creichen@gmail.com)
 // Licenced under the GNU General Public Licence, v3.  Please refer to the file "COPYING" for details.
 
-//#[allow(unused)]
-//use log::{Level, log_enabled, trace, debug, info, warn, error};
-
-//use rustyline::error::ReadlineError;
-// use rustyline::Editor;
-// use std::{result::Result, sync::Arc, ops::Index, str::FromStr, rc::Rc};
-// use crate::{audio::{self, Mixer, amber, pcmplay::{StereoPCM, MonoPCM}, dsp::channel_sequencer::{AudioStream, ASMeta, ASAnnotation}}, datafiles};
-
-// // ================================================================================
-// // CLI commands
-
-// enum CmdInfo {
-//     Cmd(Command),
-//     Section(& 'static str),
-// }
-
-// struct Command {
-//     n : &'static str,
-//     a : &'static [(&'static str, FA)], // formal args
-//     oa : &'static [(&'static str, FA, &'static str)], // named optional args
-//     d : &'static str,
-//     f : fn(&mut CLI, &Args) -> (),
-// }
-
-// impl Command {
-//     // Length of name-plus-arguments string
-//     pub fn len(&self) -> usize {
-// 	return self.to_string().len();
-//     }
-
-//     pub fn matches(&self, s : &'_ str) -> bool {
-// 	return s == self.n;
-//     }
-
-//     pub fn run(&self, cli : &mut CLI, args : &Args) {
-// 	(self.f)(cli, args);
-//     }
-
-//     pub fn to_string(&self) -> String {
-// 	let mut s = format!("{} ", self.n);
-// 	for (aname, aty) in self.a {
-// 	    s = format!("{s} <{aname}:{}>", aty.str());
-// 	}
-// 	return s;
-//     }
-
-//     pub fn find_optarg<'a>(&self, name : &'a str) -> Option<FA> {
-// 	for (argname, argty, _) in self.oa {
-// 	    if name == *argname {
-// 		return Some(*argty);
-// 	    }
-// 	}
-// 	return None;
-//     }
-// }
-
-// const FA_TYPE_U_STR : &str = "num";
-// const FA_TYPE_S_STR : &str = "str";
-// const FA_TYPE_O_STR : &str = "offset";
-
-// #[derive(Clone, Copy)]
-// enum FA {
-//     U,
-//     S,
-//     O,
-// }
-
-// #[derive(Clone)]
-// enum AA {
-//     U(usize),
-//     S(String),
-//     O(AudioStreamIterator),
-//     Missing,
-// }
-
-// const COMMANDS : [CmdInfo; 11] = [
-//     CmdInfo::Section("System commands"),
-
-//     CmdInfo::Cmd(Command {
-// 	n : "echo",
-// 	f : cmd_echo, // added to shut up the various "unused" warnings
-// 	a : &[("MESSAGE", FA::S)],
-// 	oa : &[],
-// 	d : "Echoes the specified message" }),
-
-//     CmdInfo::Cmd(Command {
-// 	n : "exit",
-// 	f : cmd_quit,
-// 	a : &[],
-// 	oa : &[],
-// 	d : "Quit the program" }),
-
-//     CmdInfo::Cmd(Command {
-// 	n : "help",
-// 	f : cmd_help,
-// 	a : &[],
-// 	oa : &[],
-// 	d : "Print basic introductory help" }),
-
-//     CmdInfo::Cmd(Command {
-// 	n : "list",
-// 	f : cmd_list,
-// 	a : &[],
-// 	oa : &[],
-// 	d : "List all commands" }),
-
-//     CmdInfo::Section("Investigating songs"),
-
-//     CmdInfo::Cmd(Command {
-// 	n : "song",
-// 	f : cmd_set_song,
-// 	a : &[("SONGNR", FA::U)],
-// 	oa : &[
-// 	    ("linear-crossfade", FA::U, "Number of samples to apply linear crossfade to between ticks (default: 0)"),
-// 	    ("oversample", FA::U, "Powers of two to oversample (compensated via hermite downsampling) (default: 0)"),
-// 	    ("maxtick", FA::U, "Maximum tick to track"),
-// 	],
-// 	d : "Sets the current song to debug" }),
-
-//     CmdInfo::Cmd(Command {
-// 	n : "show",
-// 	f : cmd_show,
-// 	a : &[("OFFSET", FA::O)],
-// 	oa : &[
-// 	    ("warn", FA::U, "Threshold (percent) difference between adjacent samples at which to print warning marker (default 25)"),
-// 	],
-// 	d : "Show the specified offset range in the current song" }),
-
-//     CmdInfo::Cmd(Command {
-// 	n : "play",
-// 	f : cmd_play,
-// 	a : &[("OFFSET", FA::O)],
-// 	oa : &[
-// 	],
-// 	d : "Plays the specified channel / range" }),
-
-//     CmdInfo::Cmd(Command {
-// 	n : "plays",
-// 	f : cmd_plays,
-// 	a : &[],
-// 	oa : &[
-// 	    ("l", FA::O, "Left audio (can be specified more than once)"),
-// 	    ("r", FA::O, "Right audio (can be specified more than once)"),
-// 	    ("c", FA::O, "Left+right sides (can be specified more than once)"),
-// 	],
-// 	d : "Plays the specified channel / range" }),
-
-//     CmdInfo::Cmd(Command {
-// 	n : "write",
-// 	f : cmd_write,
-// 	a : &[("OFFSET", FA::O),
-// 	      ("FILENAME", FA::S)],
-// 	oa : &[
-// 	],
-// 	d : "Writes the specified file / range into a WAV file" }),
-// ];
-
-// // ----------------------------------------
-// // Command implementations
-
-// // --------------------
-// fn cmd_echo(_cli: &mut CLI, args : &Args) {
-//     println!("{}", args[0].s());
-// }
-
-// // --------------------
-// fn cmd_help(_cli: &mut CLI, _ : &Args) {
-//     println!("Debugger interface");
-//     println!("- 'quit' quits (as do Ctrl-C and Ctrl-D)");
-//     println!("- 'list' lists all available commands");
-//     println!("Commands may require arguments.  The following types are supported:");
-//     println!("    {FA_TYPE_U_STR}:\tnatural numbers");
-//     println!("    {FA_TYPE_S_STR}:\tany string");
-//     println!("    {FA_TYPE_O_STR}:\tstream channel and offset of the form:  #<chan>[t<tick>][p<pos>]  , possibly followed by ellipsis '..', '..<end>' or '..+<len>'.");
-//     println!("\t\t  Examples:");
-//     println!("\t\t\t#0\t\tChannel 0 in its entirety");
-//     println!("\t\t\t#0t4\t\tChannel 0, tick 4");
-//     println!("\t\t\t#0t4..\t\tChannel 0, tick 4 and all following");
-//     println!("\t\t\t#0t4..6\t\tChannel 0, ticks 4..5");
-//     println!("\t\t\t#0t4..+2\tChannel 0, ticks 4..5 (relative notation)");
-//     println!("\t\t\t#0t4p5..10\tChannel 0, tick 4, offsets 5..9 relative to that tick start");
-//     println!("\t\t\t#0p5..10\tChannel 0, offsets 5..9");
-//     println!();
-//     println!("Commands may also take optional keyword arguments, e.g. 'foo:num', specified AFTER the mandatory arguments.");
-// }
-
-// // --------------------
-// fn cmd_quit(_cli: &mut CLI, _ : &Args) {
-//     std::process::exit(0);
-// }
-
-// // --------------------
-// fn cmd_list(_cli: &mut CLI, _ : &Args) {
-//     let mut maxlen = 0;
-
-//     for c in COMMANDS {
-// 	if let CmdInfo::Cmd(cmd) = c {
-// 	    maxlen = usize::max(maxlen, cmd.len());
-// 	}
-//     }
-
-//     let pad = maxlen + 4;
-
-//     for c in COMMANDS {
-// 	match c {
-// 	    CmdInfo::Cmd(cmd)   => { println!("  {:2$}{}", cmd.to_string(), cmd.d, pad);
-// 	                             for (n, ty, descr) in cmd.oa {
-// 					 let lhs = format!("{n}:{}", ty.str());
-// 					 println!("      {lhs:<20}    {descr}");
-// 				     }
-// 	                           },
-// 	    CmdInfo::Section(s) => println!("---- {s}"),
-// 	}
-//     }
-// }
-
-// // --------------------
-// fn cmd_set_song(cli: &mut CLI, args : &Args) {
-//     let song_nr = args[0].u();
-//     if song_nr >= cli.data.songs.len() {
-// 	println!("Bad song number: 0..{}", cli.data.songs.len() - 1);
-// 	return;
-//     }
-//     let songit = cli.get_song(song_nr);
-//     let mut maxtick = None;
-//     if args.has_opt("maxtick") {
-// 	maxtick = Some(args.get_opt("maxtick").u());
-//     }
-//     cli.songinfo = format!("Song {}", song_nr);
-//     cli.song_nr = Some(song_nr);
-//     let channels = audio::dsp::channel_sequencer::sequence_sinc_linear(songit, cli.mixer.get_freq(),
-// 								       maxtick,
-// 								       args.get_opt("oversample").default_u(0),
-// 								       args.get_opt("linear-crossfade").default_u(0)
-//     );
-//     cli.channels = vec![];
-//     for c in channels {
-// 	cli.channels.push(Rc::new(c));
-//     }
-// }
-
-// // --------------------
-// fn cmd_show(_cli: &mut CLI, args : &Args) {
-//     let it = args[0].offset();
-
-//     let warn_threshold = args.get_opt("warn").default_u(25) as f32 * 0.02;
-
-//     let mut must_newline = true;
-//     let mut count = 0;
-//     const MAX_COUNT : usize = 50;
-//     let mut last_sample = None;
-//     for (pos, sample, meta) in it {
-// 	if count >= MAX_COUNT {
-// 	    must_newline = true;
-// 	}
-// 	if meta.len() > 0 {
-// 	    must_newline = true;
-// 	}
-
-// 	if must_newline {
-// 	    if count > 0 {
-// 		println!();
-// 	    }
-// 	    count = 0;
-// 	}
-
-// 	if meta.len() > 0 {
-// 	    for m in meta {
-// 		match m {
-// 		    ASMeta::A(ASAnnotation { subsys, event, data })  => println!("-- [{subsys}] {event} {data}"),
-// 		    ASMeta::TS(t)                                    => println!("--- tick t{t}"),
-// 		}}
-// 	}
-
-// 	if must_newline {
-// 	    print!("{:<7}: ", pos);
-// 	    must_newline = false;
-// 	}
-
-// 	let s = if sample < 0.0 {
-// 	    format!("-{}", (-(sample * 128.0)) as usize)
-// 	} else if sample > 0.0 {
-// 	    format!("+{}", ( (sample * 127.0)) as usize)
-// 	} else {
-// 	    " 0".to_string()
-// 	};
-// 	if last_sample.is_some() && f32::abs(last_sample.unwrap() - sample) > warn_threshold {
-// 	    print!("\x1b[41;33m!\x1b[0m{:>3}", s);
-// 	} else {
-// 	    print!(" {:>3}", s);
-// 	}
-// 	count += 1;
-
-// 	last_sample = Some(sample);
-//     }
-//     println!();
-// }
-
-// // --------------------
-// fn cmd_play(cli: &mut CLI, args : &Args) {
-//     let offset = args[0].offset();
-//     cli.mixer.play_pcm(StereoPCM::from(MonoPCM::from(offset)));
-// }
-
-// // --------------------
-// fn cmd_plays(cli: &mut CLI, args : &Args) {
-//     for l in args.get_opts("l") {
-// 	cli.mixer.play_pcm(MonoPCM::from(l.offset()).to_stereo(1.0, 0.0));
-//     }
-//     for r in args.get_opts("r") {
-// 	cli.mixer.play_pcm(MonoPCM::from(r.offset()).to_stereo(0.0, 1.0));
-//     }
-//     for c in args.get_opts("c") {
-// 	cli.mixer.play_pcm(MonoPCM::from(c.offset()).to_stereo(1.0, 1.0));
-//     }
-// }
-
-// // --------------------
-// fn cmd_write(cli: &mut CLI, args : &Args) {
-//     let offset = args[0].offset();
-//     let filename = args[1].s();
-//     let spec = hound::WavSpec {
-// 	channels: 1,
-// 	sample_rate: cli.mixer.get_freq() as u32,
-// 	bits_per_sample: 32,
-// 	sample_format : hound::SampleFormat::Float,
-//     };
-//     match hound::WavWriter::create(filename, spec) {
-// 	Err(s) => { println!("Error: {}", s); return; }
-// 	Ok(mut writer) => {
-// 	    for (_, s, _) in offset {
-// 		if let Err(e) = writer.write_sample(s) {
-// 		    println!("Error while writing: {e}");
-// 		    return;
-// 		}
-// 	    }
-// 	}
-//     }
-// }
-
-// // ================================================================================
-// // CLI implementation
-
-// // ----------------------------------------
-// // Audio offset
-
-// #[derive(Eq, PartialEq, Clone, Copy, Debug)]
-// pub(super) enum AOffsetEllipsis {
-//     Rest, // All the rest
-//     Relative(usize),
-//     Absolute(usize),
-// }
-
-// #[derive(Eq, PartialEq, Clone, Copy, Debug)]
-// pub(super) struct AOffset {
-//     channel : usize,
-//     tick : Option<usize>,
-//     start : Option<usize>,
-//     ellipsis : AOffsetEllipsis,
-// }
-
-// impl AOffset {
-//     pub fn new(channel : usize, tick : Option<usize>, start : Option<usize>, ellipsis : AOffsetEllipsis) -> AOffset {
-// 	return AOffset { channel, tick, start, ellipsis };
-//     }
-
-//     pub fn try_iter<'a>(&self, stream : Rc<AudioStream<ASMeta>>) -> Result<AudioStreamIterator, String> {
-// 	let start_pos = self.start.unwrap_or(0);
-// 	let start_tick = match self.tick {
-// 	    None    => 0,
-// 	    Some(t) => match stream.find_tick(t) {
-// 		           Some(o) => o,
-// 		           None    => { return Err(format!("Start tick {t} not found")); },
-// 	               },
-// 	};
-
-// 	let mut end = stream.audio.len(); // to the end
-
-// 	if self.start.is_none() {
-// 	    // Ellipsis refers to the tick
-// 	    if let Some(endtick) = match self.ellipsis {
-// 		AOffsetEllipsis::Rest        => None,
-// 		AOffsetEllipsis::Relative(n) => Some(n + self.tick.unwrap()), // parser ensures that this is Some
-// 		AOffsetEllipsis::Absolute(n) => Some(n),
-// 	    } { if let Some(e)  = stream.find_tick(endtick) {
-// 		end = e;
-// 	      } else {
-// 		println!("End tick {endtick} not found, defaulting to end of channel stream");
-// 	    }}
-// 	} else {
-// 	    // Ellipsis refers to the position
-// 	    if let Some(endpos) = match self.ellipsis {
-// 		AOffsetEllipsis::Rest        => None,
-// 		AOffsetEllipsis::Relative(n) => Some(start_pos + n),
-// 		AOffsetEllipsis::Absolute(n) => Some(n),
-// 	    } { end = endpos; }
-// 	}
-// 	let start = start_tick + start_pos;
-
-// 	return Ok(AudioStreamIterator::new(stream, start, end));
-//     }
-// }
-
-// /// Returns (number, destoffset) on success
-// pub(super) fn ascii_digit_slice(s : &[u8], start : usize) -> Option<(usize, usize)> {
-//     let mut end = start;
-//     while end < s.len() && s[end].is_ascii_digit() {
-// 	end += 1;
-//     }
-//     if end > start {
-// 	let number_str = match std::str::from_utf8(&s[start..end]) {
-// 	    Ok(v)  => v,
-// 	    Err(_) => return None,
-// 	};
-// 	let number = match str::parse::<usize>(number_str) {
-// 	    Ok(n)  => n,
-// 	    Err(_) => return None,
-// 	};
-// 	return Some((number, end));
-//     }
-//     return None;
-// }
-
-// impl FromStr for AOffset {
-//     type Err = &'static str;
-
-//     fn from_str(raw_s: &str) -> Result<Self, Self::Err> {
-// 	let s = raw_s.as_bytes();
-// 	let end = s.len();
-
-// 	if s.len() < 2 || s[0] as char != '#' {
-// 	    return Err("Must start with channel ID, e.g., '#0'");
-// 	}
-
-// 	if let Some((channel, next)) = ascii_digit_slice(s, 1) {
-// 	    if next == end {
-// 		return Ok(AOffset::new(channel, None, None, AOffsetEllipsis::Rest));
-// 	    }
-
-// 	    if s[next] as char == '.' {
-// 		return Err("Ellipsis not allowed for channel numbers")
-// 	    }
-
-// 	    let (tick, next) = if s[next] as char == 't' {
-// 		if let Some((tick_nr, next)) = ascii_digit_slice(s, next + 1) {
-// 		    (Some(tick_nr), next)
-// 		} else {
-// 		    return Err("Channel tick must be a number, e.g., '#0t42'")
-// 		}
-// 	    } else { (None, next) };
-
-// 	    if next == end {
-// 		return Ok(AOffset::new(channel, tick, None, AOffsetEllipsis::Relative(1)));
-// 	    }
-
-// 	    let (start, next) =
-// 		if s[next] as char == 'p'  {
-// 		    if let Some((start, next)) = ascii_digit_slice(s, next + 1) {
-// 			(Some(start), next)
-// 		    } else { return Err("Channel position must be a number, e.g., '#0p7'") }
-// 		} else { (None, next) };
-
-// 	    let mut next = next;
-// 	    let ellipsis = if next + 2 <= end
-// 		&& s[next] as char == '.'
-// 		&& s[next + 1] as char == '.' {
-// 		    next += 2;
-// 		    true
-// 		} else { false };
-
-// 	    if next == end {
-// 		return Ok(AOffset::new(channel, tick, start, if ellipsis { AOffsetEllipsis::Rest } else { AOffsetEllipsis::Relative(1) }));
-// 	    } else if !ellipsis {
-// 		return Err("Channel position ill-formed");
-// 	    }
-
-// 	    let relative = if s[next] as char == '+' {
-// 		next += 1;
-// 		true
-// 	    } else { false };
-
-// 	    if next == end {
-// 		return Err("Channel position ill-formed: trailing '..+'");
-// 	    }
-
-// 	    if let Some((offset, next)) = ascii_digit_slice(s, next) {
-// 		if next == end {
-// 		    return Ok(AOffset::new(channel, tick, start, if relative { AOffsetEllipsis::Relative(offset) } else { AOffsetEllipsis::Absolute(offset) }));
-// 		}
-// 	    }
-// 	    return Err("Channel position ill-formed: Ellipsis must end with number");
-// 	} else {
-// 	     return Err("Must start with channel ID, e.g., '#0'");
-// 	}
-//     }
-// }
-
-// // ----------------------------------------
-// // Tests
-// #[cfg(test)]
-// mod test {
-//     use crate::debug_audio::ascii_digit_slice;
-//     use super::AOffset;
-//     use super::AOffsetEllipsis::*;
-
-//     fn expect_fail(outcome : Result<AOffset, &'static str>) {
-// 	if let Ok(_) = outcome {
-// 	    panic!("Unexpected success: {:?}", outcome);
-// 	}
-//     }
-
-//     fn expect(r : AOffset, outcome : Result<AOffset, &'static str>) {
-// 	if let Ok(r2) = outcome {
-// 	    assert_eq!(r, r2);
-// 	} else {
-// 	    panic!("Unexpected failure: {:?}", outcome);
-// 	}
-//     }
-
-//     #[test]
-//     pub fn test_ascii_digit_slice_success() {
-// 	assert_eq!(Some((0, 1)), ascii_digit_slice("0".as_bytes(), 0));
-// 	assert_eq!(Some((9, 1)), ascii_digit_slice("9".as_bytes(), 0));
-// 	assert_eq!(Some((11, 2)), ascii_digit_slice("11".as_bytes(), 0));
-// 	assert_eq!(Some((0, 2)), ascii_digit_slice("z0".as_bytes(), 1));
-// 	assert_eq!(Some((11, 3)), ascii_digit_slice("z11".as_bytes(), 1));
-
-// 	assert_eq!(Some((0, 1)), ascii_digit_slice("0P".as_bytes(), 0));
-// 	assert_eq!(Some((11, 2)), ascii_digit_slice("11P".as_bytes(), 0));
-// 	assert_eq!(Some((0, 2)), ascii_digit_slice("z0P".as_bytes(), 1));
-// 	assert_eq!(Some((11, 3)), ascii_digit_slice("z11P".as_bytes(), 1));
-//     }
-
-//     #[test]
-//     pub fn test_ascii_digit_slice_fail() {
-// 	assert_eq!(None, ascii_digit_slice("0".as_bytes(), 1));
-// 	assert_eq!(None, ascii_digit_slice("z".as_bytes(), 0));
-// 	assert_eq!(None, ascii_digit_slice("0".as_bytes(), 27));
-// 	assert_eq!(None, ascii_digit_slice("0a2".as_bytes(), 1));
-//     }
-
-//     #[test]
-//     pub fn test_parse_aoffset_chan() {
-// 	expect(AOffset::new(3, None, None, Rest),
-// 	       str::parse::<AOffset>("#3"));
-// 	expect(AOffset::new(13, None, None, Rest),
-// 	       str::parse::<AOffset>("#13"));
-//     }
-
-//     #[test]
-//     pub fn test_parse_aoffset_channel_bad() {
-// 	expect_fail(str::parse::<AOffset>(""));
-// 	expect_fail(str::parse::<AOffset>("#"));
-// 	expect_fail(str::parse::<AOffset>("7"));
-// 	expect_fail(str::parse::<AOffset>("*7"));
-//     }
-
-//     #[test]
-//     pub fn test_parse_aoffset_tick() {
-// 	expect(AOffset::new(2, Some(1), None, Relative(1)),
-// 	       str::parse::<AOffset>("#2t1"));
-// 	expect(AOffset::new(2, Some(17), None, Relative(1)),
-// 	       str::parse::<AOffset>("#2t17"));
-//     }
-
-//     #[test]
-//     pub fn test_parse_aoffset_tick_ellipsis() {
-// 	expect(AOffset::new(2, Some(1), None, Absolute(2)),
-// 	       str::parse::<AOffset>("#2t1..2"));
-// 	expect(AOffset::new(2, Some(17), None, Absolute(33)),
-// 	       str::parse::<AOffset>("#2t17..33"));
-// 	expect(AOffset::new(2, Some(1), None, Relative(2)),
-// 	       str::parse::<AOffset>("#2t1..+2"));
-// 	expect(AOffset::new(2, Some(17), None, Relative(33)),
-// 	       str::parse::<AOffset>("#2t17..+33"));
-// 	expect(AOffset::new(2, Some(1), None, Rest),
-// 	       str::parse::<AOffset>("#2t1.."));
-// 	expect(AOffset::new(2, Some(17), None, Rest),
-// 	       str::parse::<AOffset>("#2t17.."));
-//     }
-
-//     #[test]
-//     pub fn test_parse_aoffset_tick_pos() {
-// 	expect(AOffset::new(2, Some(1), Some(13), Relative(1)),
-// 	       str::parse::<AOffset>("#2t1p13"));
-// 	expect(AOffset::new(2, Some(17), Some(2), Relative(1)),
-// 	       str::parse::<AOffset>("#2t17p2"));
-//     }
-
-//     #[test]
-//     pub fn test_parse_aoffset_tick_pos_ellipsis() {
-// 	expect(AOffset::new(2, Some(1), Some(3), Absolute(2)),
-// 	       str::parse::<AOffset>("#2t1p3..2"));
-// 	expect(AOffset::new(2, Some(17), Some(999), Absolute(33)),
-// 	       str::parse::<AOffset>("#2t17p999..33"));
-// 	expect(AOffset::new(2, Some(1), Some(8), Relative(2)),
-// 	       str::parse::<AOffset>("#2t1p8..+2"));
-// 	expect(AOffset::new(2, Some(17), Some(128), Relative(33)),
-// 	       str::parse::<AOffset>("#2t17p128..+33"));
-// 	expect(AOffset::new(2, Some(1), Some(3), Rest),
-// 	       str::parse::<AOffset>("#2t1p3.."));
-// 	expect(AOffset::new(2, Some(17), Some(999), Rest),
-// 	       str::parse::<AOffset>("#2t17p999.."));
-//     }
-
-//     #[test]
-//     pub fn test_parse_aoffset_pos() {
-// 	expect(AOffset::new(2, None, Some(1), Relative(1)),
-// 	       str::parse::<AOffset>("#2p1"));
-// 	expect(AOffset::new(2, None, Some(17), Relative(1)),
-// 	       str::parse::<AOffset>("#2p17"));
-//     }
-
-//     #[test]
-//     pub fn test_parse_aoffset_pos_ellipsis() {
-// 	expect(AOffset::new(2, None, Some(1), Absolute(2)),
-// 	       str::parse::<AOffset>("#2p1..2"));
-// 	expect(AOffset::new(2, None, Some(17), Absolute(33)),
-// 	       str::parse::<AOffset>("#2p17..33"));
-// 	expect(AOffset::new(2, None, Some(1), Relative(2)),
-// 	       str::parse::<AOffset>("#2p1..+2"));
-// 	expect(AOffset::new(2, None, Some(17), Relative(33)),
-// 	       str::parse::<AOffset>("#2p17..+33"));
-// 	expect(AOffset::new(2, None, Some(1), Rest),
-// 	       str::parse::<AOffset>("#2p1.."));
-// 	expect(AOffset::new(2, None, Some(17), Rest),
-// 	       str::parse::<AOffset>("#2p17.."));
-//     }
-
-//     #[test]
-//     pub fn test_parse_aoffset_basic_bad() {
-// 	expect_fail(str::parse::<AOffset>("#0t"));
-// 	expect_fail(str::parse::<AOffset>("#0p"));
-// 	expect_fail(str::parse::<AOffset>("#0p1t2"));
-// 	expect_fail(str::parse::<AOffset>("#0ta"));
-// 	expect_fail(str::parse::<AOffset>("#0tp"));
-// 	expect_fail(str::parse::<AOffset>("#0pt"));
-// 	expect_fail(str::parse::<AOffset>("#0pz"));
-// 	expect_fail(str::parse::<AOffset>("#0t."));
-// 	expect_fail(str::parse::<AOffset>("#0p."));
-//     }
-
-
-//     #[test]
-//     pub fn test_parse_aoffset_ellipsis_bad() {
-// 	expect_fail(str::parse::<AOffset>("#0t1..+"));
-// 	expect_fail(str::parse::<AOffset>("#0t1p2..+"));
-// 	expect_fail(str::parse::<AOffset>("#0p1..+"));
-
-// 	expect_fail(str::parse::<AOffset>("#0t1..."));
-// 	expect_fail(str::parse::<AOffset>("#0t1p2..."));
-// 	expect_fail(str::parse::<AOffset>("#0p1..."));
-
-// 	expect_fail(str::parse::<AOffset>("#0t1."));
-// 	expect_fail(str::parse::<AOffset>("#0t1p2."));
-// 	expect_fail(str::parse::<AOffset>("#0p1."));
-
-// 	expect_fail(str::parse::<AOffset>("#0t1..+z"));
-// 	expect_fail(str::parse::<AOffset>("#0t1p2..+z"));
-// 	expect_fail(str::parse::<AOffset>("#0p1..+z"));
-
-// 	expect_fail(str::parse::<AOffset>("#0.."));
-// 	expect_fail(str::parse::<AOffset>("#0..+1"));
-// 	expect_fail(str::parse::<AOffset>("#0..1"));
-//     }
-
-// }
-
-// // ----------------------------------------
-// // AudioStreamIterator
-
-// #[derive(Clone)]
-// pub struct AudioStreamIterator {
-//     stream : Rc<AudioStream<ASMeta>>,
-//     pos : usize,
-//     it_pos : usize, // next position, if inside
-//     meta_index : usize,
-//     end_pos : usize,
-// }
-
-// impl AudioStreamIterator {
-//     pub fn new(stream : Rc<AudioStream<ASMeta>>, start_pos : usize, end_pos : usize) -> AudioStreamIterator {
-// 	AudioStreamIterator {
-// 	    stream : stream.clone(),
-// 	    pos : start_pos,
-// 	    it_pos : start_pos,
-// 	    meta_index : 0,
-// 	    end_pos,
-// 	}
-//     }
-
-//     /// Retrieves all meta-information for the current iterator index
-//     pub fn meta(&mut self) -> Vec<ASMeta> {
-// 	let pos_for_meta = self.pos;
-// 	while self.meta_index < self.stream.meta.len() && self.stream.meta[self.meta_index].0 < pos_for_meta {
-// 	    self.meta_index += 1;
-// 	}
-
-// 	let mut results = vec![];
-// 	let mut i = self.meta_index;
-// 	while i < self.stream.meta.len() && self.stream.meta[i].0 == pos_for_meta {
-// 	    results.push(self.stream.meta[i].1.clone());
-// 	    i += 1;
-// 	}
-// 	return results;
-//     }
-
-//     pub fn pcm(&self) -> Vec<f32> {
-// 	return self.stream.audio[self.pos..self.end_pos].to_vec();
-//     }
-// }
-
-// impl Iterator for AudioStreamIterator {
-//     type Item = (usize, f32, Vec<ASMeta>);
-
-//     fn next(&mut self) -> Option<Self::Item> {
-// 	self.pos = self.it_pos;
-// 	if self.it_pos < self.end_pos {
-// 	    self.it_pos += 1;
-// 	    return Some((self.pos, self.stream.audio[self.pos], self.meta()));
-// 	} else {
-// 	    return None;
-// 	}
-//     }
-// }
-
-// impl From<AudioStreamIterator> for MonoPCM {
-//     fn from(astreamit : AudioStreamIterator) -> Self {
-// 	return MonoPCM::from(astreamit.pcm());
-//     }
-// }
-
-// // ----------------------------------------
-// // Arguments
-
-// struct Args {
-//     kwargs : Vec<(String, AA)>,
-//     posargs : Vec<AA>,
-// }
-
-// impl Args {
-//     /// Set value of optional parameter
-//     pub fn set_optional<'a>(&mut self, s : &'a str, value : AA) {
-// 	self.kwargs.push((s.to_string(), value));
-//     }
-
-//     pub fn has_opt<'a>(&self, argname : &'a str) -> bool {
-// 	if let AA::Missing = self.get_opt(argname) {
-// 	    return false;
-// 	}
-// 	return true;
-//     }
-
-//     pub fn get_opt<'a>(&self, argname : &'a str) -> AA {
-// 	for (n, v) in &self.kwargs {
-// 	    if n == argname {
-// 		return v.clone();
-// 	    }
-// 	}
-// 	return AA::Missing;
-//     }
-
-//     pub fn get_opts<'a>(&self, argname : &'a str) -> Vec<AA> {
-// 	let mut results = vec![];
-// 	for (n, v) in &self.kwargs {
-// 	    if n == argname {
-// 		results.push(v.clone());
-// 	    }
-// 	}
-// 	return results;
-//     }
-// }
-
-// impl Index<usize> for Args {
-//     type Output = AA;
-
-//     fn index(&self, index: usize) -> &Self::Output {
-// 	return &self.posargs[index];
-//     }
-// }
-
-// impl AA {
-//     pub fn u(&self) -> usize {
-// 	return if let AA::U(v) = self { *v } else { panic!("Unexpected type"); };
-//     }
-
-//     pub fn s(&self) -> String {
-// 	return if let AA::S(s) = self { s.clone() } else { "<unexpected type>".to_string() };
-//     }
-
-//     pub fn offset(&self) -> AudioStreamIterator {
-// 	if let AA::O(offset) = self { offset.clone() } else { panic!("Unexpected type"); }
-//     }
-
-//     pub fn default_u(&self, default : usize) -> usize {
-// 	if let AA::Missing = self {
-// 	    return default;
-// 	} else {
-// 	    return self.u();
-// 	}
-//     }
-// }
-
-
-// impl FA {
-//     pub fn convert<'a>(&self, cli : &'a CLI, s : &'a str) -> Result<AA, String> {
-// 	match self {
-// 	    FA::U => match str::parse::<usize>(s) {
-// 		         Ok(v)  => Ok(AA::U(v)),
-// 		         Err(_) => Err(format!("Could not parse '{s}' as number")),
-// 	             },
-// 	    FA::O => match str::parse::<AOffset>(s) {
-// 		         Ok(o)  => { if o.channel >= cli.channels.len() {
-// 			                 Err(format!("Invalid channel number: We have {} channels in {}", cli.channels.len(), cli.songinfo))
-// 			           } else {
-// 			                 match o.try_iter(cli.channels[o.channel].clone()) {
-// 					     Err(s)   => Err(s),
-// 					     Ok(iter) => Ok(AA::O(iter)),
-// 					 } }
-// 			           },
-// 		         Err(e) => Err(format!("Could not parse '{s}' as offset: {e}")),
-// 	             },
-// 	    FA::S => Result::Ok(AA::S(s.to_string())),
-// 	}
-//     }
-
-//     pub fn str(&self) -> &'static str {
-// 	match self {
-// 	    FA::U => FA_TYPE_U_STR,
-// 	    FA::S => FA_TYPE_S_STR,
-// 	    FA::O => FA_TYPE_O_STR,
-// 	}
-//     }
-// }
-
-// struct CLI<'a> {
-//     data : &'a datafiles::AmberstarFiles,
-//     samples : Arc<Vec<i8>>,
-//     mixer : Mixer,
-//     songinfo : String,
-//     song_nr : Option<usize>,
-//     channels : Vec<Rc<AudioStream<ASMeta>>>,
-// }
-
-// fn parse_colonpair<'a>(s : &'a str) -> Option<(&'a str, &'a str)> {
-//     let tokens : Vec<&'a str> = s.split(":").collect();
-//     if tokens.len() == 2 {
-// 	return Some((tokens[0], tokens[1]));
-//     }
-//     return None
-// }
-
-// impl<'a> CLI<'a> {
-
-//     pub fn get_song(&self, song_id : usize) -> audio::iterator::ArcPoly {
-// 	let songit = amber::play_song(&self.data.songs[song_id]);
-// 	let mut guard = songit.lock().unwrap();
-// 	guard.set_default_samples(self.samples.clone());
-// 	return songit.clone();
-//     }
-
-//     pub fn run(&mut self, line : String) {
-// 	let mut tokens = line.split_whitespace();
-// 	if let Some(first_token) = tokens.next() {
-// 	    for c in COMMANDS {
-// 		if let CmdInfo::Cmd(cmd) = c {
-// 		    if cmd.matches(first_token) {
-// 			self.try_run(&cmd, &mut tokens);
-// 			return;
-// 		    }
-// 		}
-// 	    }
-// 	}
-//     }
-
-//     fn try_run(&mut self, cmd : &Command, tokens : &mut std::str::SplitWhitespace) {
-// 	let mut actuals = vec![];
-// 	let mut failed = false;
-
-// 	for (formal_name, formal) in cmd.a.iter() {
-// 	    match tokens.next() {
-// 		None     => { failed = true;
-// 			      println!("Not enough arguments");
-// 			      break;
-// 		            }
-// 		Some(s)  => match formal.convert(self, s) {
-// 		              Ok(a)  => actuals.push(a),
-// 		              Err(m) => { println!("{formal_name}: {}", m);
-// 					  failed = true; }
-// 		}
-// 	    }
-// 	}
-
-// 	let mut args = Args { posargs : actuals, kwargs : vec![], };
-
-// 	// Find optional arguments
-// 	while !failed {
-// 	    if let Some(optarg_candidate) = tokens.next() {
-// 		match parse_colonpair(optarg_candidate) {
-// 		    Some((l, r)) => match cmd.find_optarg(l) {
-// 			              Some(t) => match t.convert(self, r) {
-// 					           Ok(aa) => args.set_optional(l, aa),
-// 					           Err(s) => { failed = true;
-// 							       println!("Command '{}': argument '{l}': {s}", cmd.n);
-// 						             },
-// 				                 },
-// 			              None    => { failed = true;
-// 					           println!("Command '{}' does not know optional argument '{l}'", cmd.n);
-// 				                 },
-// 		                  },
-// 		    None       => { failed = true;
-// 				    println!("Too many arguments, expected {}", cmd.a.len());
-// 		                  }
-
-// 		}
-// 	    } else {
-// 		break;
-// 	    }
-// 	}
-
-// 	if !failed {
-// 	    cmd.run(self, &args);
-// 	}
-//     }
-// }
-
-// pub fn debug_audio(data : &datafiles::AmberstarFiles) -> rustyline::Result<()> {
-//     let sdl_context = sdl2::init().unwrap();
-
-//     let mut audiocore = audio::init(&sdl_context);
-//     let mut cli = CLI {
-// 	data,
-// 	samples : Arc::new(data.sample_data.data[..].to_vec()),
-// 	mixer : audiocore.start_mixer(&data.sample_data.data[..]),
-// 	songinfo : "<no song>".to_string(),
-// 	song_nr : None,
-// 	channels : vec![],
-//     };
-
-//     // `()` can be used when no completer is required
-//     let mut rl = Editor::<()>::new();
-//     rl.load_history(".amber-remix-debug-audio-history").unwrap_or(());
-//     loop {
-//         let readline = rl.readline("> ");
-//         match readline {
-//             Ok(line) => {
-//                 rl.add_history_entry(line.as_str());
-// 		cli.run(line);
-//             },
-//             Err(_) => break,
-//         }
-//     }
-//     rl.save_history(".amber-remix-debug-audio-history")
-// }
+#[allow(unused)]
+use log::{Level, log_enabled, trace, debug, info, warn, error};
+
+use rustyline::Editor;
+use std::{result::Result, sync::{Arc, Mutex}, ops::Index, str::FromStr};
+use crate::{audio::{amber::SongIterator,
+		    acore::AudioSource,
+		    iterator::AQOp,
+		    experiments::{SongPlayerAudioSource, SongTracer, SAMPLE_RATE}},
+	    datafiles};
+
+const NUM_CHANNELS : usize = 4;
+
+// ================================================================================
+// Cli commands
+
+enum CmdInfo {
+    Cmd(Command),
+    Section(&'static str),
+}
+
+struct Command {
+    n : &'static str,
+    a : &'static [(&'static str, FA)], // formal args
+    oa : &'static [(&'static str, FA, &'static str)], // named optional args
+    d : &'static str,
+    f : fn(&mut Cli, &Args) -> (),
+}
+
+impl Command {
+    // Length of name-plus-arguments string
+    pub fn len(&self) -> usize {
+	return self.to_string().len();
+    }
+
+    pub fn matches(&self, s : &str) -> bool {
+	return s == self.n;
+    }
+
+    pub fn run(&self, cli : &mut Cli, args : &Args) {
+	(self.f)(cli, args);
+    }
+
+    pub fn find_optarg(&self, name : &str) -> Option<FA> {
+	for (argname, argty, _) in self.oa {
+	    if name == *argname {
+		return Some(*argty);
+	    }
+	}
+	return None;
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	write!(f, "{} ", self.n)?;
+	for (aname, aty) in self.a {
+	    write!(f, " <{aname}:{}>", aty.str())?;
+	}
+	return Ok(());
+    }
+}
+
+const FA_TYPE_U_STR : &str = "num";
+const FA_TYPE_S_STR : &str = "str";
+const FA_TYPE_O_STR : &str = "offset";
+
+#[derive(Clone, Copy)]
+enum FA {
+    U,
+    S,
+    O,
+}
+
+#[derive(Clone)]
+enum AA {
+    U(usize),
+    S(String),
+    O(TraceRange),
+    Missing,
+}
+
+const COMMANDS : [CmdInfo; 18] = [
+    CmdInfo::Section("System commands"),
+
+    CmdInfo::Cmd(Command {
+	n : "echo",
+	f : cmd_echo,
+	a : &[("MESSAGE", FA::S)],
+	oa : &[],
+	d : "Echoes the specified message" }),
+
+    CmdInfo::Cmd(Command {
+	n : "quit",
+	f : cmd_quit,
+	a : &[],
+	oa : &[],
+	d : "Quit the program" }),
+
+    CmdInfo::Cmd(Command {
+	n : "help",
+	f : cmd_help,
+	a : &[],
+	oa : &[],
+	d : "Print basic introductory help" }),
+
+    CmdInfo::Cmd(Command {
+	n : "list",
+	f : cmd_list,
+	a : &[],
+	oa : &[],
+	d : "List all commands" }),
+
+    CmdInfo::Section("Running songs"),
+
+    CmdInfo::Cmd(Command {
+	n : "song",
+	f : cmd_set_song,
+	a : &[("SONGNR", FA::U)],
+	oa : &[
+	    ("first", FA::U, "First division to play (default: from song info)"),
+	    ("last", FA::U, "Last division to play (default: from song info)"),
+	],
+	d : "Sets the current song to debug and rewinds to tick 0" }),
+
+    CmdInfo::Cmd(Command {
+	n : "step",
+	f : cmd_step,
+	a : &[],
+	oa : &[
+	    ("n", FA::U, "Number of ticks to step (default: 1)"),
+	],
+	d : "Advances the current song by the given number of ticks, ignoring breakpoints" }),
+
+    CmdInfo::Cmd(Command {
+	n : "continue",
+	f : cmd_continue,
+	a : &[],
+	oa : &[
+	    ("max", FA::U, "Maximum number of ticks to run (default: until the song ends)"),
+	],
+	d : "Runs the current song until a breakpoint triggers or the song ends" }),
+
+    CmdInfo::Cmd(Command {
+	n : "state",
+	f : cmd_state,
+	a : &[],
+	oa : &[
+	    ("channel", FA::U, "Only show the specified channel"),
+	],
+	d : "Shows the internal state of the song iterator" }),
+
+    CmdInfo::Section("Breakpoints"),
+
+    CmdInfo::Cmd(Command {
+	n : "break",
+	f : cmd_break,
+	a : &[("KIND", FA::S)],
+	oa : &[
+	    ("nr", FA::U, "Only trigger for this tick/division/monopattern/timbre/instrument (default: any change)"),
+	    ("channel", FA::U, "Only trigger on this channel (default: all)"),
+	],
+	d : "Adds a breakpoint; KIND is one of: tick, division, monopattern, timbre, instrument" }),
+
+    CmdInfo::Cmd(Command {
+	n : "breakpoints",
+	f : cmd_breakpoints,
+	a : &[],
+	oa : &[],
+	d : "Lists all breakpoints" }),
+
+    CmdInfo::Cmd(Command {
+	n : "delete",
+	f : cmd_delete,
+	a : &[("BREAKPOINT", FA::U)],
+	oa : &[],
+	d : "Deletes the specified breakpoint" }),
+
+    CmdInfo::Section("Investigating traces"),
+
+    CmdInfo::Cmd(Command {
+	n : "show",
+	f : cmd_show,
+	a : &[("OFFSET", FA::O)],
+	oa : &[
+	    ("warn", FA::U, "Threshold (percent) difference between adjacent samples at which to print warning marker (default 25)"),
+	],
+	d : "Show the specified offset range in the current song" }),
+
+    CmdInfo::Cmd(Command {
+	n : "ops",
+	f : cmd_ops,
+	a : &[("OFFSET", FA::O)],
+	oa : &[],
+	d : "Dumps the audio queue operations for all ticks that start in the specified offset range" }),
+
+    CmdInfo::Cmd(Command {
+	n : "write",
+	f : cmd_write,
+	a : &[("OFFSET", FA::O),
+	      ("FILENAME", FA::S)],
+	oa : &[],
+	d : "Writes the specified channel / range into a WAV file" }),
+];
+
+// ----------------------------------------
+// Command implementations
+
+// --------------------
+fn cmd_echo(_cli: &mut Cli, args : &Args) {
+    println!("{}", args[0].s());
+}
+
+// --------------------
+fn cmd_help(_cli: &mut Cli, _ : &Args) {
+    println!("Debugger interface");
+    println!("- 'quit' quits (as do Ctrl-C and Ctrl-D)");
+    println!("- 'list' lists all available commands");
+    println!("Select a song with 'song', then 'step' or 'continue' to run it; the debugger records");
+    println!("all audio and events that the song produces, which you can then inspect.");
+    println!("Commands may require arguments.  The following types are supported:");
+    println!("    {FA_TYPE_U_STR}:\tnatural numbers");
+    println!("    {FA_TYPE_S_STR}:\tany string");
+    println!("    {FA_TYPE_O_STR}:\tstream channel and offset of the form:  #<chan>[t<tick>][p<pos>]  , possibly followed by ellipsis '..', '..<end>' or '..+<len>'.");
+    println!("\t\t  Examples:");
+    println!("\t\t\t#0\t\tChannel 0 in its entirety");
+    println!("\t\t\t#0t4\t\tChannel 0, tick 4");
+    println!("\t\t\t#0t4..\t\tChannel 0, tick 4 and all following");
+    println!("\t\t\t#0t4..6\t\tChannel 0, ticks 4..5");
+    println!("\t\t\t#0t4..+2\tChannel 0, ticks 4..5 (relative notation)");
+    println!("\t\t\t#0t4p5..10\tChannel 0, tick 4, offsets 5..9 relative to that tick start");
+    println!("\t\t\t#0p5..10\tChannel 0, offsets 5..9");
+    println!();
+    println!("Commands may also take optional keyword arguments, e.g. 'foo:num', specified AFTER the mandatory arguments.");
+}
+
+// --------------------
+fn cmd_quit(_cli: &mut Cli, _ : &Args) {
+    std::process::exit(0);
+}
+
+// --------------------
+fn cmd_list(_cli: &mut Cli, _ : &Args) {
+    let mut maxlen = 0;
+
+    for c in COMMANDS {
+	if let CmdInfo::Cmd(cmd) = c {
+	    maxlen = usize::max(maxlen, cmd.len());
+	}
+    }
+
+    let pad = maxlen + 4;
+
+    for c in COMMANDS {
+	match c {
+	    CmdInfo::Cmd(cmd)   => { println!("  {:2$}{}", cmd.to_string(), cmd.d, pad);
+	                             for (n, ty, descr) in cmd.oa {
+					 let lhs = format!("{n}:{}", ty.str());
+					 println!("      {lhs:<20}    {descr}");
+				     }
+	                           },
+	    CmdInfo::Section(s) => println!("---- {s}"),
+	}
+    }
+}
+
+// --------------------
+fn cmd_set_song(cli: &mut Cli, args : &Args) {
+    let song_nr = args[0].u();
+    if song_nr >= cli.data.songs.len() {
+	println!("Bad song number: 0..{}", cli.data.songs.len() - 1);
+	return;
+    }
+    let song = &cli.data.songs[song_nr];
+    let first = args.get_opt("first").default_u(song.songinfo.first_division);
+    let last = args.get_opt("last").default_u(song.songinfo.last_division);
+    if first > last || last >= song.divisions.len() {
+	println!("Bad division range {first}..{last}: song has divisions 0..{}", song.divisions.len() - 1);
+	return;
+    }
+    cli.songinfo = format!("Song {song_nr}");
+    cli.song_nr = Some(song_nr);
+    cli.source.play(&SongIterator::new(song, first, last));
+    cli.tracer.lock().unwrap().hits.clear();
+    println!("{}: divisions {first}..{last}", cli.songinfo);
+}
+
+// --------------------
+fn cmd_step(cli: &mut Cli, args : &Args) {
+    if cli.require_song() {
+	let n = args.get_opt("n").default_u(1);
+	for _ in 0..n {
+	    if !cli.step_tick() {
+		break;
+	    }
+	}
+	cli.tracer.lock().unwrap().hits.clear();
+	cli.print_position();
+    }
+}
+
+// --------------------
+fn cmd_continue(cli: &mut Cli, args : &Args) {
+    if cli.require_song() {
+	let max = args.get_opt("max").default_u(usize::MAX);
+	for hit in run_until_break(&cli.source, &cli.tracer, max) {
+	    println!("{hit}");
+	}
+	cli.print_position();
+    }
+}
+
+// --------------------
+fn cmd_state(cli: &mut Cli, args : &Args) {
+    if !cli.require_song() {
+	return;
+    }
+    let player = cli.source.player();
+    let guard = player.lock().unwrap();
+    if let Some(songit) = guard.song_iterator() {
+	println!("{} at tick {}: division {}{}", cli.songinfo, cli.tick(), songit.division(),
+		 if songit.is_stopped() { " (stopped)" } else { "" });
+	for (index, chan) in songit.channels.iter().enumerate() {
+	    if let AA::U(c) = args.get_opt("channel") {
+		if c != index {
+		    continue;
+		}
+	    }
+	    println!("#{index}: {chan}");
+	}
+    }
+}
+
+// --------------------
+fn cmd_break(cli: &mut Cli, args : &Args) {
+    let nr = match args.get_opt("nr") {
+	AA::U(n) => Some(n),
+	_        => None,
+    };
+    let condition = match (args[0].s().as_str(), nr) {
+	("tick", Some(t))  => BreakCondition::Tick(t),
+	("tick", None)     => { println!("Tick breakpoints require 'nr:<tick>'");
+				return; },
+	(kind, nr)         => match BREAK_CATEGORIES.iter().find(|k| **k == kind) {
+	    Some(category) => BreakCondition::Event(category, nr),
+	    None           => { println!("Unknown breakpoint kind '{kind}'");
+				return; },
+	},
+    };
+    let channel = match args.get_opt("channel") {
+	AA::U(c) if c >= NUM_CHANNELS => { println!("Invalid channel number {c}");
+					   return; },
+	AA::U(c) => Some(c),
+	_        => None,
+    };
+    let mut tracer = cli.tracer.lock().unwrap();
+    let bp = Breakpoint { id : tracer.next_breakpoint_id, condition, channel };
+    tracer.next_breakpoint_id += 1;
+    println!("{bp}");
+    tracer.breakpoints.push(bp);
+}
+
+// --------------------
+fn cmd_breakpoints(cli: &mut Cli, _ : &Args) {
+    let tracer = cli.tracer.lock().unwrap();
+    if tracer.breakpoints.is_empty() {
+	println!("No breakpoints");
+    }
+    for bp in &tracer.breakpoints {
+	println!("{bp}");
+    }
+}
+
+// --------------------
+fn cmd_delete(cli: &mut Cli, args : &Args) {
+    let id = args[0].u();
+    let mut tracer = cli.tracer.lock().unwrap();
+    let len_before = tracer.breakpoints.len();
+    tracer.breakpoints.retain(|bp| bp.id != id);
+    if tracer.breakpoints.len() == len_before {
+	println!("No breakpoint #{id}");
+    }
+}
+
+// --------------------
+fn cmd_show(cli: &mut Cli, args : &Args) {
+    let range = args[0].offset();
+    let tracer = cli.tracer.lock().unwrap();
+    let trace = &tracer.channels[range.channel];
+
+    let warn_threshold = args.get_opt("warn").default_u(25) as f32 * 0.02;
+
+    let annotations = trace.annotations(range.start, range.end);
+    let mut annotation_index = 0;
+    let mut must_newline = true;
+    let mut count = 0;
+    const MAX_COUNT : usize = 50;
+    let mut last_sample = None;
+    for pos in range.start..range.end {
+	let sample = trace.audio[pos];
+	if count >= MAX_COUNT {
+	    must_newline = true;
+	}
+	let first_annotation = annotation_index;
+	while annotation_index < annotations.len() && annotations[annotation_index].0 == pos {
+	    annotation_index += 1;
+	}
+	let meta = &annotations[first_annotation..annotation_index];
+	if !meta.is_empty() {
+	    must_newline = true;
+	}
+
+	if must_newline {
+	    if count > 0 {
+		println!();
+	    }
+	    count = 0;
+	}
+
+	for (_, m) in meta {
+	    println!("{m}");
+	}
+
+	if must_newline {
+	    print!("{:<7}: ", pos);
+	    must_newline = false;
+	}
+
+	let s = if sample < 0.0 {
+	    format!("-{}", (-(sample * 128.0)) as usize)
+	} else if sample > 0.0 {
+	    format!("+{}", (sample * 127.0) as usize)
+	} else {
+	    " 0".to_string()
+	};
+	match last_sample {
+	    Some(last) if f32::abs(last - sample) > warn_threshold
+		=> print!("\x1b[41;33m!\x1b[0m{:>3}", s),
+	    _   => print!(" {:>3}", s),
+	}
+	count += 1;
+
+	last_sample = Some(sample);
+    }
+    println!();
+}
+
+// --------------------
+fn cmd_ops(cli: &mut Cli, args : &Args) {
+    let range = args[0].offset();
+    let tracer = cli.tracer.lock().unwrap();
+    let trace = &tracer.channels[range.channel];
+    for (tick, start) in trace.tick_starts.iter().enumerate() {
+	if *start >= range.start && *start < range.end {
+	    println!("--- tick t{tick} (p{start})");
+	    for op in &trace.aqops[tick] {
+		println!("  {op:?}");
+	    }
+	}
+    }
+}
+
+// --------------------
+fn cmd_write(cli: &mut Cli, args : &Args) {
+    let range = args[0].offset();
+    let filename = args[1].s();
+    let spec = hound::WavSpec {
+	channels: 1,
+	sample_rate: SAMPLE_RATE as u32,
+	bits_per_sample: 32,
+	sample_format : hound::SampleFormat::Float,
+    };
+    let tracer = cli.tracer.lock().unwrap();
+    let trace = &tracer.channels[range.channel];
+    match hound::WavWriter::create(&filename, spec) {
+	Err(s) => println!("Error: {}", s),
+	Ok(mut writer) => {
+	    for s in &trace.audio[range.start..range.end] {
+		if let Err(e) = writer.write_sample(*s) {
+		    println!("Error while writing: {e}");
+		    return;
+		}
+	    }
+	    match writer.finalize() {
+		Err(e) => println!("Error while writing: {e}"),
+		Ok(_)  => println!("Wrote {} samples to {filename}", range.end - range.start),
+	    }
+	}
+    }
+}
+
+// ================================================================================
+// Tracing
+
+/// Stream log categories that we can set breakpoints on
+const BREAK_CATEGORIES : [&str; 4] = ["division", "monopattern", "timbre", "instrument"];
+
+#[derive(Clone, Copy)]
+enum BreakCondition {
+    Tick(usize),
+    /// Stream log category, and the value to match (if any)
+    Event(&'static str, Option<usize>),
+}
+
+struct Breakpoint {
+    id : usize,
+    condition : BreakCondition,
+    channel : Option<usize>,
+}
+
+impl Breakpoint {
+    fn matches_channel(&self, channel : usize) -> bool {
+	return match self.channel {
+	    None    => true,
+	    Some(c) => c == channel,
+	};
+    }
+
+    fn matches_event(&self, channel : usize, category : &str, value : isize) -> bool {
+	return match self.condition {
+	    BreakCondition::Event(cat, nr) =>
+		self.matches_channel(channel) && cat == category && nr.iter().all(|n| *n as isize == value),
+	    BreakCondition::Tick(_) => false,
+	};
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	write!(f, "Breakpoint #{}: ", self.id)?;
+	match self.condition {
+	    BreakCondition::Tick(t)               => write!(f, "tick {t}")?,
+	    BreakCondition::Event(cat, Some(nr))  => write!(f, "{cat} = {nr}")?,
+	    BreakCondition::Event(cat, None)      => write!(f, "any {cat}")?,
+	}
+	if let Some(c) = self.channel {
+	    write!(f, " on channel #{c}")?;
+	}
+	return Ok(());
+    }
+}
+
+/// Everything recorded for a single channel since the current song started
+#[derive(Default)]
+struct ChannelTrace {
+    audio : Vec<f32>,
+    /// Offset into `audio` at which each tick starts
+    tick_starts : Vec<usize>,
+    /// Stream log messages, by offset into `audio`
+    meta : Vec<(usize, String)>,
+    /// Audio queue operations, by tick
+    aqops : Vec<Vec<AQOp>>,
+}
+
+impl ChannelTrace {
+    fn find_tick(&self, tick : usize) -> Option<usize> {
+	return self.tick_starts.get(tick).copied();
+    }
+
+    /// Tick markers and stream log messages within the given range, ordered by position
+    fn annotations(&self, start : usize, end : usize) -> Vec<(usize, String)> {
+	let mut results = vec![];
+	for (tick, pos) in self.tick_starts.iter().enumerate() {
+	    if *pos >= start && *pos < end {
+		results.push((*pos, format!("--- tick t{tick}")));
+	    }
+	}
+	for (pos, msg) in &self.meta {
+	    if *pos >= start && *pos < end {
+		results.push((*pos, msg.clone()));
+	    }
+	}
+	// stable sort, so tick markers come first
+	results.sort_by_key(|(pos, _)| *pos);
+	return results;
+    }
+}
+
+#[derive(Default)]
+struct DebugTracer {
+    channels : [ChannelTrace; NUM_CHANNELS],
+    breakpoints : Vec<Breakpoint>,
+    next_breakpoint_id : usize,
+    /// Descriptions of all breakpoints that triggered since the last check
+    hits : Vec<String>,
+}
+
+impl DebugTracer {
+    /// Records hits for all tick breakpoints that trigger before playing the given tick
+    fn check_tick(&mut self, tick : usize) {
+	for bp in &self.breakpoints {
+	    if let BreakCondition::Tick(t) = bp.condition {
+		if t == tick {
+		    self.hits.push(format!("{bp}: triggered"));
+		}
+	    }
+	}
+    }
+}
+
+impl SongTracer for DebugTracer {
+    fn trace_buf(&mut self, tick: usize, channel: u8, buf: Vec<f32>) {
+	let trace = &mut self.channels[channel as usize];
+	if trace.tick_starts.len() == tick {
+	    trace.tick_starts.push(trace.audio.len());
+	}
+	trace.audio.extend(buf);
+    }
+
+    fn change_song(&mut self) {
+	self.channels = Default::default();
+	self.hits.clear();
+    }
+
+    fn trace_message(&mut self, tick: usize, channel: u8, subsystem: &'static str, category: &'static str, msg: String) {
+	let trace = &mut self.channels[channel as usize];
+	trace.meta.push((trace.audio.len(), format!("-- t{tick} [{subsystem}] {category} {msg}")));
+    }
+
+    fn trace_message_num(&mut self, tick: usize, channel: u8, subsystem: &'static str, category: &'static str, msg: isize) {
+	let trace = &mut self.channels[channel as usize];
+	trace.meta.push((trace.audio.len(), format!("-- t{tick} [{subsystem}] {category} = {msg}")));
+	for bp in &self.breakpoints {
+	    if bp.matches_event(channel as usize, category, msg) {
+		self.hits.push(format!("{bp}: triggered at tick {tick} on channel #{channel}: {category} = {msg}"));
+	    }
+	}
+    }
+
+    fn trace_aqops(&mut self, tick: usize, channel: u8, ops: &[AQOp]) {
+	let aqops = &mut self.channels[channel as usize].aqops;
+	if aqops.len() == tick {
+	    aqops.push(ops.to_vec());
+	}
+    }
+}
+
+// ================================================================================
+// Cli implementation
+
+// ----------------------------------------
+// Audio offset
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub(super) enum AOffsetEllipsis {
+    Rest, // All the rest
+    Relative(usize),
+    Absolute(usize),
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub(super) struct AOffset {
+    channel : usize,
+    tick : Option<usize>,
+    start : Option<usize>,
+    ellipsis : AOffsetEllipsis,
+}
+
+/// Resolved AOffset: channel and sample range within the channel trace
+#[derive(Clone, Copy, Debug)]
+struct TraceRange {
+    channel : usize,
+    start : usize,
+    end : usize,
+}
+
+impl AOffset {
+    pub fn new(channel : usize, tick : Option<usize>, start : Option<usize>, ellipsis : AOffsetEllipsis) -> AOffset {
+	return AOffset { channel, tick, start, ellipsis };
+    }
+
+    fn try_range(&self, trace : &ChannelTrace) -> Result<TraceRange, String> {
+	let start_pos = self.start.unwrap_or(0);
+	let start_tick = match self.tick {
+	    None    => 0,
+	    Some(t) => match trace.find_tick(t) {
+		           Some(o) => o,
+		           None    => { return Err(format!("Start tick {t} not found")); },
+	               },
+	};
+
+	let mut end = trace.audio.len(); // to the end
+
+	if self.start.is_none() {
+	    // Ellipsis refers to the tick
+	    if let Some(endtick) = match self.ellipsis {
+		AOffsetEllipsis::Rest        => None,
+		AOffsetEllipsis::Relative(n) => Some(n + self.tick.unwrap_or(0)),
+		AOffsetEllipsis::Absolute(n) => Some(n),
+	    } { match trace.find_tick(endtick) {
+		Some(e) => end = e,
+		None if endtick == trace.tick_starts.len() => {}, // end of the last recorded tick
+		None    => println!("End tick {endtick} not found, defaulting to end of channel stream"),
+	    }}
+	} else {
+	    // Ellipsis refers to the position
+	    if let Some(endpos) = match self.ellipsis {
+		AOffsetEllipsis::Rest        => None,
+		AOffsetEllipsis::Relative(n) => Some(start_pos + n),
+		AOffsetEllipsis::Absolute(n) => Some(n),
+	    } { end = usize::min(end, start_tick + endpos); }
+	}
+	let start = start_tick + start_pos;
+	if start > end {
+	    return Err(format!("Empty range: {start}..{end}"));
+	}
+
+	return Ok(TraceRange { channel : self.channel, start, end });
+    }
+}
+
+/// Returns (number, destoffset) on success
+pub(super) fn ascii_digit_slice(s : &[u8], start : usize) -> Option<(usize, usize)> {
+    let mut end = start;
+    while end < s.len() && s[end].is_ascii_digit() {
+	end += 1;
+    }
+    if end > start {
+	let number_str = match std::str::from_utf8(&s[start..end]) {
+	    Ok(v)  => v,
+	    Err(_) => return None,
+	};
+	let number = match str::parse::<usize>(number_str) {
+	    Ok(n)  => n,
+	    Err(_) => return None,
+	};
+	return Some((number, end));
+    }
+    return None;
+}
+
+impl FromStr for AOffset {
+    type Err = &'static str;
+
+    fn from_str(raw_s: &str) -> Result<Self, Self::Err> {
+	let s = raw_s.as_bytes();
+	let end = s.len();
+
+	if s.len() < 2 || s[0] as char != '#' {
+	    return Err("Must start with channel ID, e.g., '#0'");
+	}
+
+	if let Some((channel, next)) = ascii_digit_slice(s, 1) {
+	    if next == end {
+		return Ok(AOffset::new(channel, None, None, AOffsetEllipsis::Rest));
+	    }
+
+	    if s[next] as char == '.' {
+		return Err("Ellipsis not allowed for channel numbers")
+	    }
+
+	    let (tick, next) = if s[next] as char == 't' {
+		if let Some((tick_nr, next)) = ascii_digit_slice(s, next + 1) {
+		    (Some(tick_nr), next)
+		} else {
+		    return Err("Channel tick must be a number, e.g., '#0t42'")
+		}
+	    } else { (None, next) };
+
+	    if next == end {
+		return Ok(AOffset::new(channel, tick, None, AOffsetEllipsis::Relative(1)));
+	    }
+
+	    let (start, next) =
+		if s[next] as char == 'p'  {
+		    if let Some((start, next)) = ascii_digit_slice(s, next + 1) {
+			(Some(start), next)
+		    } else { return Err("Channel position must be a number, e.g., '#0p7'") }
+		} else { (None, next) };
+
+	    let mut next = next;
+	    let ellipsis = if next + 2 <= end
+		&& s[next] as char == '.'
+		&& s[next + 1] as char == '.' {
+		    next += 2;
+		    true
+		} else { false };
+
+	    if next == end {
+		return Ok(AOffset::new(channel, tick, start, if ellipsis { AOffsetEllipsis::Rest } else { AOffsetEllipsis::Relative(1) }));
+	    } else if !ellipsis {
+		return Err("Channel position ill-formed");
+	    }
+
+	    let relative = if s[next] as char == '+' {
+		next += 1;
+		true
+	    } else { false };
+
+	    if next == end {
+		return Err("Channel position ill-formed: trailing '..+'");
+	    }
+
+	    if let Some((offset, next)) = ascii_digit_slice(s, next) {
+		if next == end {
+		    return Ok(AOffset::new(channel, tick, start, if relative { AOffsetEllipsis::Relative(offset) } else { AOffsetEllipsis::Absolute(offset) }));
+		}
+	    }
+	    return Err("Channel position ill-formed: Ellipsis must end with number");
+	} else {
+	    return Err("Must start with channel ID, e.g., '#0'");
+	}
+    }
+}
+
+// ----------------------------------------
+// Arguments
+
+struct Args {
+    kwargs : Vec<(String, AA)>,
+    posargs : Vec<AA>,
+}
+
+impl Args {
+    /// Set value of optional parameter
+    pub fn set_optional(&mut self, s : &str, value : AA) {
+	self.kwargs.push((s.to_string(), value));
+    }
+
+    pub fn get_opt(&self, argname : &str) -> AA {
+	for (n, v) in &self.kwargs {
+	    if n == argname {
+		return v.clone();
+	    }
+	}
+	return AA::Missing;
+    }
+}
+
+impl Index<usize> for Args {
+    type Output = AA;
+
+    fn index(&self, index: usize) -> &Self::Output {
+	return &self.posargs[index];
+    }
+}
+
+impl AA {
+    pub fn u(&self) -> usize {
+	if let AA::U(v) = self { return *v; } else { panic!("Unexpected type"); }
+    }
+
+    pub fn s(&self) -> String {
+	if let AA::S(s) = self { return s.clone(); } else { return "<unexpected type>".to_string(); }
+    }
+
+    pub fn offset(&self) -> TraceRange {
+	if let AA::O(offset) = self { return *offset; } else { panic!("Unexpected type"); }
+    }
+
+    pub fn default_u(&self, default : usize) -> usize {
+	if let AA::Missing = self {
+	    return default;
+	} else {
+	    return self.u();
+	}
+    }
+}
+
+
+impl FA {
+    pub fn convert(&self, cli : &Cli, s : &str) -> Result<AA, String> {
+	return match self {
+	    FA::U => match str::parse::<usize>(s) {
+		         Ok(v)  => Ok(AA::U(v)),
+		         Err(_) => Err(format!("Could not parse '{s}' as number")),
+	             },
+	    FA::O => match str::parse::<AOffset>(s) {
+		         Ok(o)  => { if o.channel >= NUM_CHANNELS {
+			                 Err(format!("Invalid channel number: We have {NUM_CHANNELS} channels in {}", cli.songinfo))
+			           } else {
+			                 let tracer = cli.tracer.lock().unwrap();
+			                 o.try_range(&tracer.channels[o.channel]).map(AA::O)
+				   }
+			           },
+		         Err(e) => Err(format!("Could not parse '{s}' as offset: {e}")),
+	             },
+	    FA::S => Result::Ok(AA::S(s.to_string())),
+	};
+    }
+
+    pub fn str(&self) -> &'static str {
+	return match self {
+	    FA::U => FA_TYPE_U_STR,
+	    FA::S => FA_TYPE_S_STR,
+	    FA::O => FA_TYPE_O_STR,
+	};
+    }
+}
+
+struct Cli<'a> {
+    data : &'a datafiles::AmberstarFiles,
+    source : SongPlayerAudioSource,
+    tracer : Arc<Mutex<DebugTracer>>,
+    songinfo : String,
+    song_nr : Option<usize>,
+}
+
+fn parse_colonpair(s : &str) -> Option<(&str, &str)> {
+    let tokens : Vec<&str> = s.split(':').collect();
+    if tokens.len() == 2 {
+	return Some((tokens[0], tokens[1]));
+    }
+    return None;
+}
+
+impl<'a> Cli<'a> {
+
+    fn require_song(&self) -> bool {
+	if self.song_nr.is_none() {
+	    println!("No song selected, use 'song' first");
+	}
+	return self.song_nr.is_some();
+    }
+
+    /// Number of ticks recorded so far
+    fn tick(&self) -> usize {
+	let tracer = self.tracer.lock().unwrap();
+	return tracer.channels[0].tick_starts.len();
+    }
+
+    fn print_position(&self) {
+	let player = self.source.player();
+	let guard = player.lock().unwrap();
+	if let Some(songit) = guard.song_iterator() {
+	    println!("{}: tick {}, division {}{}", self.songinfo, self.tick(), songit.division(),
+		     if songit.is_stopped() { " (stopped)" } else { "" });
+	}
+    }
+
+    /// Renders one tick of the current song.  Returns false if the song has ended.
+    fn step_tick(&mut self) -> bool {
+	return step_tick(&self.source);
+    }
+
+    pub fn run(&mut self, line : String) {
+	let mut tokens = line.split_whitespace();
+	if let Some(first_token) = tokens.next() {
+	    for c in COMMANDS {
+		if let CmdInfo::Cmd(cmd) = c {
+		    if cmd.matches(first_token) {
+			self.try_run(&cmd, &mut tokens);
+			return;
+		    }
+		}
+	    }
+	    println!("Unknown command '{first_token}', try 'list'");
+	}
+    }
+
+    fn try_run(&mut self, cmd : &Command, tokens : &mut std::str::SplitWhitespace) {
+	let mut actuals = vec![];
+	let mut failed = false;
+
+	for (formal_name, formal) in cmd.a.iter() {
+	    match tokens.next() {
+		None     => { failed = true;
+			      println!("Not enough arguments");
+			      break;
+		            }
+		Some(s)  => match formal.convert(self, s) {
+		              Ok(a)  => actuals.push(a),
+		              Err(m) => { println!("{formal_name}: {}", m);
+					  failed = true; }
+		}
+	    }
+	}
+
+	let mut args = Args { posargs : actuals, kwargs : vec![], };
+
+	// Find optional arguments
+	while !failed {
+	    if let Some(optarg_candidate) = tokens.next() {
+		match parse_colonpair(optarg_candidate) {
+		    Some((l, r)) => match cmd.find_optarg(l) {
+			              Some(t) => match t.convert(self, r) {
+					           Ok(aa) => args.set_optional(l, aa),
+					           Err(s) => { failed = true;
+							       println!("Command '{}': argument '{l}': {s}", cmd.n);
+						             },
+				                 },
+			              None    => { failed = true;
+					           println!("Command '{}' does not know optional argument '{l}'", cmd.n);
+				                 },
+		                  },
+		    None       => { failed = true;
+				    println!("Too many arguments, expected {}", cmd.a.len());
+		                  }
+
+		}
+	    } else {
+		break;
+	    }
+	}
+
+	if !failed {
+	    cmd.run(self, &args);
+	}
+    }
+}
+
+/// Renders one tick of the current song.  Returns false if the song has ended.
+fn step_tick(source : &SongPlayerAudioSource) -> bool {
+    let player = source.player();
+    let mut guard = player.lock().unwrap();
+    match guard.song_iterator() {
+	Some(songit) if !songit.is_stopped() => {},
+	_ => { println!("Song has ended");
+	       return false; },
+    }
+    let samples_per_tick = guard.controls().samples_per_tick(SAMPLE_RATE);
+    let mut left = vec![0.0; samples_per_tick];
+    let mut right = vec![0.0; samples_per_tick];
+    AudioSource::fill(&mut *guard, &mut left, &mut right, SAMPLE_RATE);
+    return true;
+}
+
+/// Plays up to `max` ticks, until a breakpoint triggers or the song ends.  Returns the breakpoint hits.
+fn run_until_break(source : &SongPlayerAudioSource, tracer : &Arc<Mutex<DebugTracer>>, max : usize) -> Vec<String> {
+    let mut count = 0;
+    while count < max && step_tick(source) {
+	count += 1;
+	let mut tracer = tracer.lock().unwrap();
+	let tick = tracer.channels[0].tick_starts.len();
+	tracer.check_tick(tick);
+	if !tracer.hits.is_empty() {
+	    return tracer.hits.drain(..).collect();
+	}
+    }
+    return vec![];
+}
+
+/// Interactive song debugger: runs songs tick by tick without audio output and records
+/// per-channel audio, stream log messages and audio queue operations for inspection.
+pub fn debug_audio(data : &datafiles::AmberstarFiles) -> rustyline::Result<()> {
+    let tracer = Arc::new(Mutex::new(DebugTracer::default()));
+    let mut source = SongPlayerAudioSource::new(&data.sample_data, &data.songs, SAMPLE_RATE);
+    source.set_tracer(tracer.clone());
+    let mut cli = Cli {
+	data,
+	source,
+	tracer,
+	songinfo : "<no song>".to_string(),
+	song_nr : None,
+    };
+
+    // `()` can be used when no completer is required
+    let mut rl = Editor::<()>::new();
+    rl.load_history(".amber-remix-debug-audio-history").unwrap_or(());
+    loop {
+        let readline = rl.readline("> ");
+        match readline {
+            Ok(line) => {
+                rl.add_history_entry(line.as_str());
+		cli.run(line);
+            },
+            Err(_) => break,
+        }
+    }
+    return rl.save_history(".amber-remix-debug-audio-history");
+}
+
+// ----------------------------------------
+// Tests
+#[cfg(test)]
+mod test {
+    use crate::debug_audio::ascii_digit_slice;
+    use super::AOffset;
+    use super::AOffsetEllipsis::*;
+    use super::{run_until_break, Breakpoint, BreakCondition, DebugTracer};
+    use crate::audio::{amber::SongIterator, experiments::{SongPlayerAudioSource, SAMPLE_RATE}};
+    use crate::datafiles::{music, sampledata::SampleData};
+    use std::sync::{Arc, Mutex};
+
+    fn expect_fail(outcome : Result<AOffset, &'static str>) {
+	if outcome.is_ok() {
+	    panic!("Unexpected success: {:?}", outcome);
+	}
+    }
+
+    fn expect(r : AOffset, outcome : Result<AOffset, &'static str>) {
+	if let Ok(r2) = outcome {
+	    assert_eq!(r, r2);
+	} else {
+	    panic!("Unexpected failure: {:?}", outcome);
+	}
+    }
+
+    /// Plays the test song until the given breakpoint triggers; returns the hits and the division reached
+    fn run_test_song_until(condition : BreakCondition) -> (Vec<String>, usize) {
+	let song = music::test_song();
+	let sample_data = SampleData::new(vec![0; 0x1000]);
+	let tracer = Arc::new(Mutex::new(DebugTracer::default()));
+	let mut source = SongPlayerAudioSource::new(&sample_data, &[song.clone()], SAMPLE_RATE);
+	source.set_tracer(tracer.clone());
+	source.play(&SongIterator::new(&song, 0, 1));
+	tracer.lock().unwrap().breakpoints.push(Breakpoint { id : 0, condition, channel : Some(0) });
+
+	let hits = run_until_break(&source, &tracer, 1000);
+	let player = source.player();
+	let guard = player.lock().unwrap();
+	let songit = guard.song_iterator().unwrap();
+	assert!(!songit.is_stopped());
+	return (hits, songit.division());
+    }
+
+    #[test]
+    pub fn test_division_breakpoint() {
+	let (hits, division) = run_test_song_until(BreakCondition::Event("division", Some(1)));
+	assert_eq!(1, hits.len(), "{hits:?}");
+	assert!(hits[0].contains("division = 1"));
+	assert_eq!(1, division);
+    }
+
+    #[test]
+    pub fn test_timbre_breakpoint() {
+	// Division 1 adjusts the monopattern's timbre 0 to timbre 1
+	let (hits, division) = run_test_song_until(BreakCondition::Event("timbre", Some(1)));
+	assert_eq!(1, hits.len(), "{hits:?}");
+	assert!(hits[0].contains("timbre = 1"));
+	assert_eq!(1, division);
+    }
+
+    #[test]
+    pub fn test_ascii_digit_slice_success() {
+	assert_eq!(Some((0, 1)), ascii_digit_slice("0".as_bytes(), 0));
+	assert_eq!(Some((9, 1)), ascii_digit_slice("9".as_bytes(), 0));
+	assert_eq!(Some((11, 2)), ascii_digit_slice("11".as_bytes(), 0));
+	assert_eq!(Some((0, 2)), ascii_digit_slice("z0".as_bytes(), 1));
+	assert_eq!(Some((11, 3)), ascii_digit_slice("z11".as_bytes(), 1));
+
+	assert_eq!(Some((0, 1)), ascii_digit_slice("0P".as_bytes(), 0));
+	assert_eq!(Some((11, 2)), ascii_digit_slice("11P".as_bytes(), 0));
+	assert_eq!(Some((0, 2)), ascii_digit_slice("z0P".as_bytes(), 1));
+	assert_eq!(Some((11, 3)), ascii_digit_slice("z11P".as_bytes(), 1));
+    }
+
+    #[test]
+    pub fn test_ascii_digit_slice_fail() {
+	assert_eq!(None, ascii_digit_slice("0".as_bytes(), 1));
+	assert_eq!(None, ascii_digit_slice("z".as_bytes(), 0));
+	assert_eq!(None, ascii_digit_slice("0".as_bytes(), 27));
+	assert_eq!(None, ascii_digit_slice("0a2".as_bytes(), 1));
+    }
+
+    #[test]
+    pub fn test_parse_aoffset_chan() {
+	expect(AOffset::new(3, None, None, Rest),
+	       str::parse::<AOffset>("#3"));
+	expect(AOffset::new(13, None, None, Rest),
+	       str::parse::<AOffset>("#13"));
+    }
+
+    #[test]
+    pub fn test_parse_aoffset_channel_bad() {
+	expect_fail(str::parse::<AOffset>(""));
+	expect_fail(str::parse::<AOffset>("#"));
+	expect_fail(str::parse::<AOffset>("7"));
+	expect_fail(str::parse::<AOffset>("*7"));
+    }
+
+    #[test]
+    pub fn test_parse_aoffset_tick() {
+	expect(AOffset::new(2, Some(1), None, Relative(1)),
+	       str::parse::<AOffset>("#2t1"));
+	expect(AOffset::new(2, Some(17), None, Relative(1)),
+	       str::parse::<AOffset>("#2t17"));
+    }
+
+    #[test]
+    pub fn test_parse_aoffset_tick_ellipsis() {
+	expect(AOffset::new(2, Some(1), None, Absolute(2)),
+	       str::parse::<AOffset>("#2t1..2"));
+	expect(AOffset::new(2, Some(17), None, Absolute(33)),
+	       str::parse::<AOffset>("#2t17..33"));
+	expect(AOffset::new(2, Some(1), None, Relative(2)),
+	       str::parse::<AOffset>("#2t1..+2"));
+	expect(AOffset::new(2, Some(17), None, Relative(33)),
+	       str::parse::<AOffset>("#2t17..+33"));
+	expect(AOffset::new(2, Some(1), None, Rest),
+	       str::parse::<AOffset>("#2t1.."));
+	expect(AOffset::new(2, Some(17), None, Rest),
+	       str::parse::<AOffset>("#2t17.."));
+    }
+
+    #[test]
+    pub fn test_parse_aoffset_tick_pos() {
+	expect(AOffset::new(2, Some(1), Some(13), Relative(1)),
+	       str::parse::<AOffset>("#2t1p13"));
+	expect(AOffset::new(2, Some(17), Some(2), Relative(1)),
+	       str::parse::<AOffset>("#2t17p2"));
+    }
+
+    #[test]
+    pub fn test_parse_aoffset_tick_pos_ellipsis() {
+	expect(AOffset::new(2, Some(1), Some(3), Absolute(2)),
+	       str::parse::<AOffset>("#2t1p3..2"));
+	expect(AOffset::new(2, Some(17), Some(999), Absolute(33)),
+	       str::parse::<AOffset>("#2t17p999..33"));
+	expect(AOffset::new(2, Some(1), Some(8), Relative(2)),
+	       str::parse::<AOffset>("#2t1p8..+2"));
+	expect(AOffset::new(2, Some(17), Some(128), Relative(33)),
+	       str::parse::<AOffset>("#2t17p128..+33"));
+	expect(AOffset::new(2, Some(1), Some(3), Rest),
+	       str::parse::<AOffset>("#2t1p3.."));
+	expect(AOffset::new(2, Some(17), Some(999), Rest),
+	       str::parse::<AOffset>("#2t17p999.."));
+    }
+
+    #[test]
+    pub fn test_parse_aoffset_pos() {
+	expect(AOffset::new(2, None, Some(1), Relative(1)),
+	       str::parse::<AOffset>("#2p1"));
+	expect(AOffset::new(2, None, Some(17), Relative(1)),
+	       str::parse::<AOffset>("#2p17"));
+    }
+
+    #[test]
+    pub fn test_parse_aoffset_pos_ellipsis() {
+	expect(AOffset::new(2, None, Some(1), Absolute(2)),
+	       str::parse::<AOffset>("#2p1..2"));
+	expect(AOffset::new(2, None, Some(17), Absolute(33)),
+	       str::parse::<AOffset>("#2p17..33"));
+	expect(AOffset::new(2, None, Some(1), Relative(2)),
+	       str::parse::<AOffset>("#2p1..+2"));
+	expect(AOffset::new(2, None, Some(17), Relative(33)),
+	       str::parse::<AOffset>("#2p17..+33"));
+	expect(AOffset::new(2, None, Some(1), Rest),
+	       str::parse::<AOffset>("#2p1.."));
+	expect(AOffset::new(2, None, Some(17), Rest),
+	       str::parse::<AOffset>("#2p17.."));
+    }
+
+    #[test]
+    pub fn test_parse_aoffset_basic_bad() {
+	expect_fail(str::parse::<AOffset>("#0t"));
+	expect_fail(str::parse::<AOffset>("#0p"));
+	expect_fail(str::parse::<AOffset>("#0p1t2"));
+	expect_fail(str::parse::<AOffset>("#0ta"));
+	expect_fail(str::parse::<AOffset>("#0tp"));
+	expect_fail(str::parse::<AOffset>("#0pt"));
+	expect_fail(str::parse::<AOffset>("#0pz"));
+	expect_fail(str::parse::<AOffset>("#0t."));
+	expect_fail(str::parse::<AOffset>("#0p."));
+    }
+
+
+    #[test]
+    pub fn test_parse_aoffset_ellipsis_bad() {
+	expect_fail(str::parse::<AOffset>("#0t1..+"));
+	expect_fail(str::parse::<AOffset>("#0t1p2..+"));
+	expect_fail(str::parse::<AOffset>("#0p1..+"));
+
+	expect_fail(str::parse::<AOffset>("#0t1..."));
+	expect_fail(str::parse::<AOffset>("#0t1p2..."));
+	expect_fail(str::parse::<AOffset>("#0p1..."));
+
+	expect_fail(str::parse::<AOffset>("#0t1."));
+	expect_fail(str::parse::<AOffset>("#0t1p2."));
+	expect_fail(str::parse::<AOffset>("#0p1."));
+
+	expect_fail(str::parse::<AOffset>("#0t1..+z"));
+	expect_fail(str::parse::<AOffset>("#0t1p2..+z"));
+	expect_fail(str::parse::<AOffset>("#0p1..+z"));
+
+	expect_fail(str::parse::<AOffset>("#0.."));
+	expect_fail(str::parse::<AOffset>("#0..+1"));
+	expect_fail(str::parse::<AOffset>("#0..1"));
+    }
+
+}